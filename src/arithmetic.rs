@@ -0,0 +1,375 @@
+use crate::lexer::is_name;
+use crate::sherror::ShellError;
+use crate::shell::Shell;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Operator(&'static str)
+}
+
+const OPERATORS: [&str; 39] = [
+    "<<=", ">>=", "**", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||",
+    "+=", "-=", "*=", "/=", "%=", "&=", "|=", "^=",
+    "+", "-", "*", "/", "%", "<", ">", "=", "!", "~", "&", "|", "^", "?", ":", "(", ")", ","
+];
+
+/// Evaluates an arithmetic expression as found in `$(( ))`, `(( ))` and
+/// C-style `for` loops. Variables are read from and assigned to `shell`.
+pub fn evaluate(expression: &str, shell: &mut Shell) -> Result<i64, ShellError> {
+    let tokens = tokenize(expression)?;
+    if tokens.is_empty() {
+        return Ok(0);
+    }
+
+    let mut evaluator = Evaluator { tokens, pos: 0, shell, expression };
+    let value = evaluator.comma(true)?;
+
+    match evaluator.tokens.get(evaluator.pos) {
+        None => Ok(value),
+        Some(_) => Err(evaluator.error("syntax error in expression")),
+    }
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, ShellError> {
+    let chars: Vec<char> = expression.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '#') {
+                i += 1;
+            }
+            let literal: String = chars[start..i].iter().collect();
+            tokens.push(Token::Number(parse_number(&literal).ok_or_else(|| {
+                ShellError::InvalidArgument(format!("rshell: {}: value too great for base (error token is \"{}\")", expression.trim(), literal))
+            })?));
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Name(chars[start..i].iter().collect()));
+        } else {
+            let rest: String = chars[i..].iter().collect();
+            match OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+                Some(op) => {
+                    tokens.push(Token::Operator(op));
+                    i += op.len();
+                },
+                None => return Err(ShellError::InvalidArgument(format!("rshell: {}: syntax error: operand expected (error token is \"{}\")", expression.trim(), rest))),
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn parse_number(literal: &str) -> Option<i64> {
+    if let Some(hex) = literal.strip_prefix("0x").or_else(|| literal.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some((base, digits)) = literal.split_once('#') {
+        i64::from_str_radix(digits, base.parse().ok().filter(|b| (2..=36).contains(b))?).ok()
+    } else if literal.len() > 1 && literal.starts_with('0') {
+        i64::from_str_radix(&literal[1..], 8).ok()
+    } else {
+        literal.parse().ok()
+    }
+}
+
+struct Evaluator<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    shell: &'a mut Shell,
+    expression: &'a str
+}
+
+const BINARY_LEVELS: [&[&str]; 9] = [
+    &["|"],
+    &["^"],
+    &["&"],
+    &["==", "!="],
+    &["<", "<=", ">", ">="],
+    &["<<", ">>"],
+    &["+", "-"],
+    &["*", "/", "%"],
+    &["**"]
+];
+
+impl Evaluator<'_> {
+    fn error(&self, message: &str) -> ShellError {
+        ShellError::InvalidArgument(format!("rshell: {}: {}", self.expression.trim(), message))
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_operator(&self) -> Option<&'static str> {
+        match self.peek() {
+            Some(Token::Operator(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, operator: &str) -> Result<(), ShellError> {
+        if self.peek_operator() == Some(operator) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("syntax error: `{}' expected", operator)))
+        }
+    }
+
+    fn variable(&mut self, name: &str) -> Result<i64, ShellError> {
+        let value = self.shell.get_var(name).unwrap_or_default();
+        let value = value.trim();
+
+        if value.is_empty() {
+            Ok(0)
+        } else if let Some(number) = parse_number(value) {
+            Ok(number)
+        } else if is_name(value) && value != name {
+            self.variable(value)
+        } else {
+            Err(self.error(&format!("{}: invalid number", value)))
+        }
+    }
+
+    fn comma(&mut self, eval: bool) -> Result<i64, ShellError> {
+        let mut value = self.assignment(eval)?;
+        while self.peek_operator() == Some(",") {
+            self.pos += 1;
+            value = self.assignment(eval)?;
+        }
+        Ok(value)
+    }
+
+    fn assignment(&mut self, eval: bool) -> Result<i64, ShellError> {
+        if let (Some(Token::Name(name)), Some(Token::Operator(op))) = (self.peek().cloned(), self.tokens.get(self.pos + 1).cloned()) {
+            if op.ends_with('=') && !matches!(op, "==" | "!=" | "<=" | ">=") {
+                self.pos += 2;
+                let right = self.assignment(eval)?;
+                if !eval {
+                    return Ok(0);
+                }
+
+                let value = match op {
+                    "=" => right,
+                    _ => {
+                        let left = self.variable(&name)?;
+                        self.apply(&op[..op.len() - 1], left, right)?
+                    },
+                };
+                self.shell.set_var(&name, &value.to_string());
+                return Ok(value);
+            }
+        }
+
+        self.ternary(eval)
+    }
+
+    fn ternary(&mut self, eval: bool) -> Result<i64, ShellError> {
+        let condition = self.logical_or(eval)?;
+
+        if self.peek_operator() != Some("?") {
+            return Ok(condition);
+        }
+
+        self.pos += 1;
+        let then = self.assignment(eval && condition != 0)?;
+        self.expect(":")?;
+        let otherwise = self.assignment(eval && condition == 0)?;
+
+        Ok(if condition != 0 { then } else { otherwise })
+    }
+
+    fn logical_or(&mut self, eval: bool) -> Result<i64, ShellError> {
+        let mut value = self.logical_and(eval)?;
+        while self.peek_operator() == Some("||") {
+            self.pos += 1;
+            let right = self.logical_and(eval && value == 0)?;
+            value = (value != 0 || right != 0) as i64;
+        }
+        Ok(value)
+    }
+
+    fn logical_and(&mut self, eval: bool) -> Result<i64, ShellError> {
+        let mut value = self.binary(0, eval)?;
+        while self.peek_operator() == Some("&&") {
+            self.pos += 1;
+            let right = self.binary(0, eval && value != 0)?;
+            value = (value != 0 && right != 0) as i64;
+        }
+        Ok(value)
+    }
+
+    fn binary(&mut self, level: usize, eval: bool) -> Result<i64, ShellError> {
+        if level == BINARY_LEVELS.len() {
+            return self.unary(eval);
+        }
+
+        let mut value = self.binary(level + 1, eval)?;
+
+        while let Some(op) = self.peek_operator().filter(|op| BINARY_LEVELS[level].contains(op)) {
+            self.pos += 1;
+            // Exponentiation is right associative.
+            let right = if op == "**" { self.binary(level, eval)? } else { self.binary(level + 1, eval)? };
+            value = if eval { self.apply(op, value, right)? } else { 0 };
+        }
+
+        Ok(value)
+    }
+
+    fn apply(&self, op: &str, left: i64, right: i64) -> Result<i64, ShellError> {
+        Ok(match op {
+            "|" => left | right,
+            "^" => left ^ right,
+            "&" => left & right,
+            "==" => (left == right) as i64,
+            "!=" => (left != right) as i64,
+            "<" => (left < right) as i64,
+            "<=" => (left <= right) as i64,
+            ">" => (left > right) as i64,
+            ">=" => (left >= right) as i64,
+            "<<" => left.wrapping_shl(right as u32),
+            ">>" => left.wrapping_shr(right as u32),
+            "+" => left.wrapping_add(right),
+            "-" => left.wrapping_sub(right),
+            "*" => left.wrapping_mul(right),
+            "/" | "%" if right == 0 => return Err(self.error("division by 0")),
+            "/" => left.wrapping_div(right),
+            "%" => left.wrapping_rem(right),
+            "**" if right < 0 => return Err(self.error("exponent less than 0")),
+            "**" => left.wrapping_pow(right as u32),
+            _ => return Err(self.error(&format!("syntax error: invalid operator `{}'", op))),
+        })
+    }
+
+    fn unary(&mut self, eval: bool) -> Result<i64, ShellError> {
+        match self.peek_operator() {
+            Some(op @ ("++" | "--")) => {
+                self.pos += 1;
+                let name = match self.peek() {
+                    Some(Token::Name(name)) => name.clone(),
+                    _ => return Err(self.error("syntax error: operand expected")),
+                };
+                self.pos += 1;
+                if !eval {
+                    return Ok(0);
+                }
+                let value = self.variable(&name)? + if op == "++" { 1 } else { -1 };
+                self.shell.set_var(&name, &value.to_string());
+                Ok(value)
+            },
+            Some(op @ ("+" | "-" | "!" | "~")) => {
+                self.pos += 1;
+                let value = self.unary(eval)?;
+                Ok(match op {
+                    "-" => value.wrapping_neg(),
+                    "!" => (value == 0) as i64,
+                    "~" => !value,
+                    _ => value,
+                })
+            },
+            _ => self.postfix(eval),
+        }
+    }
+
+    fn postfix(&mut self, eval: bool) -> Result<i64, ShellError> {
+        match self.peek().cloned() {
+            Some(Token::Number(number)) => {
+                self.pos += 1;
+                Ok(number)
+            },
+            Some(Token::Name(name)) => {
+                self.pos += 1;
+                if !eval {
+                    if matches!(self.peek_operator(), Some("++" | "--")) {
+                        self.pos += 1;
+                    }
+                    return Ok(0);
+                }
+
+                let value = self.variable(&name)?;
+                if let Some(op @ ("++" | "--")) = self.peek_operator() {
+                    self.pos += 1;
+                    let updated = value + if op == "++" { 1 } else { -1 };
+                    self.shell.set_var(&name, &updated.to_string());
+                }
+                Ok(value)
+            },
+            Some(Token::Operator("(")) => {
+                self.pos += 1;
+                let value = self.comma(eval)?;
+                self.expect(")")?;
+                Ok(value)
+            },
+            _ => Err(self.error("syntax error: operand expected")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(expression: &str) -> Result<i64, ShellError> {
+        let mut shell = Shell::new();
+        shell.set_var("x", "4");
+        evaluate(expression, &mut shell)
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("2 + 3 * 4").unwrap(), 14);
+        assert_eq!(eval("(2 + 3) * 4").unwrap(), 20);
+        assert_eq!(eval("10 - 4 - 3").unwrap(), 3);
+        assert_eq!(eval("2 ** 3 ** 2").unwrap(), 512);
+        assert_eq!(eval("1 << 2 + 1").unwrap(), 8);
+        assert_eq!(eval("1 + 2 == 3 && 4 > 3").unwrap(), 1);
+        assert_eq!(eval("0 || 2 & 3").unwrap(), 1);
+        assert_eq!(eval("-x + 10").unwrap(), 6);
+        assert_eq!(eval("!0 + ~0").unwrap(), 0);
+        assert_eq!(eval("x > 3 ? 10 : 20").unwrap(), 10);
+        assert_eq!(eval("7 / 2 * 2 + 7 % 2").unwrap(), 7);
+    }
+
+    #[test]
+    fn variables_and_assignments() {
+        let mut shell = Shell::new();
+        shell.set_var("x", "4");
+        assert_eq!(evaluate("y = x * 2, y + 1", &mut shell).unwrap(), 9);
+        assert_eq!(shell.get_var("y").as_deref(), Some("8"));
+        assert_eq!(evaluate("x++ + x", &mut shell).unwrap(), 9);
+        assert_eq!(evaluate("unset_name + 1", &mut shell).unwrap(), 1);
+    }
+
+    #[test]
+    fn bases() {
+        assert_eq!(eval("0x1f + 010 + 2#101").unwrap(), 31 + 8 + 5);
+        assert!(eval("2#102").is_err());
+    }
+
+    #[test]
+    fn division_by_zero_is_an_error() {
+        assert!(eval("1 / 0").is_err());
+        assert!(eval("1 % 0").is_err());
+        assert!(eval("x / (x - 4)").is_err());
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert!(eval("1 +").is_err());
+        assert!(eval("(1").is_err());
+        assert!(eval("1 2").is_err());
+        assert_eq!(eval("").unwrap(), 0);
+    }
+}
@@ -1,116 +1,542 @@
-use std::{cell::RefCell, collections::HashSet, io::{self, Write}};
+use std::{borrow::Cow, cell::RefCell, collections::{HashMap, HashSet}};
+use std::path::{Path, PathBuf};
 
-use rustyline::completion::Completer;
-use rustyline_derive::{Helper, Highlighter, Hinter, Validator};
+use rustyline::{Cmd, ConditionalEventHandler, Context, Event, EventContext, RepeatCount};
+use rustyline::completion::{Completer, Pair};
+use rustyline::highlight::{CmdKind, Highlighter};
+use rustyline::hint::Hinter;
+use rustyline::history::SearchDirection;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline_derive::Helper;
 
-use crate::shfile::find_executables;
+use crate::completion::{complete_paths, fallback, generate, hosts, users, CompletionSpec, Matching, PathKind, Word};
+use crate::help::{command_flags, Flag};
+use crate::highlight::{highlight, Styles};
+use crate::parser::is_incomplete;
+use crate::prompt::Prompt;
+use crate::shell::Shell;
+use crate::shfile::{find_executables, is_executable};
+use crate::tools::{self, Invocation};
 
-#[derive(Helper, Highlighter, Hinter, Validator)]
+#[derive(Helper)]
 pub struct CommandCompleter {
     pub commands: Vec<String>,
     pub path: String,
-    state: RefCell<CompleterState>    
+    /// Commands run so far with the directory they ran in, oldest first,
+    /// used to suggest the rest of the line.
+    history: Vec<(String, String)>,
+    cwd: String,
+    /// Functions and aliases of the shell, which are valid commands.
+    names: HashSet<String>,
+    aliases: HashMap<String, String>,
+    /// Shell variables, expanded in the paths being completed, and the
+    /// names of arrays.
+    variables: HashMap<String, String>,
+    arrays: Vec<String>,
+    completions: HashMap<String, CompletionSpec>,
+    /// How candidates are matched when none starts with the word.
+    matchings: Vec<Matching>,
+    /// A copy of the shell in which completion functions and commands run,
+    /// when some are registered.
+    shell: RefCell<Option<Shell>>,
+    /// Lines already read of an unfinished command.
+    previous: String,
+    prompt: Prompt,
+    styles: Styles
 }
 
-struct CompleterState {
-    pub tab_count: u8,
-    pub last_partial: String,
-    pub last_matches: HashSet<String>
-} 
-
 impl CommandCompleter {
     pub fn new(commands: Vec<String>, path: String) -> Self {
         Self {
             commands,
             path,
-            state: RefCell::new(CompleterState {
-                tab_count: 0,
-                last_partial: String::new(),
-                last_matches: HashSet::new()
-            })
+            history: vec![],
+            cwd: String::new(),
+            names: HashSet::new(),
+            aliases: HashMap::new(),
+            variables: HashMap::new(),
+            arrays: vec![],
+            completions: HashMap::new(),
+            matchings: Matching::from_setting(None),
+            shell: RefCell::new(None),
+            previous: String::new(),
+            prompt: Prompt::default(),
+            styles: Styles::from_env(None, None, None)
+        }
+    }
+}
+
+impl CommandCompleter {
+    pub fn remember(&mut self, command: &str, cwd: &str) {
+        self.history.push((command.to_string(), cwd.to_string()));
+    }
+
+    /// Takes the state of the shell the next line is read for.
+    pub fn refresh(&mut self, shell: &Shell, cwd: &str) {
+        self.cwd = cwd.to_string();
+        self.path = shell.get_var("PATH").unwrap_or_default();
+        self.names = shell.functions.keys().chain(shell.aliases.keys()).cloned().collect();
+        self.aliases = shell.aliases.clone();
+        self.variables = shell.environment.clone();
+        self.arrays = shell.arrays.keys().cloned().collect();
+        self.completions = shell.completions.clone();
+        self.matchings = Matching::from_setting(shell.get_var("RSHELL_COMPLETION").as_deref());
+        *self.shell.get_mut() = (!shell.completions.is_empty()).then(|| shell.clone());
+        self.styles = Styles::from_env(
+            shell.get_var("NO_COLOR").as_deref(),
+            shell.get_var("TERM").as_deref(),
+            shell.get_var("RSHELL_COLORS").as_deref()
+        );
+    }
+
+    /// Sets the lines read so far of the command being continued.
+    pub fn continue_command(&mut self, previous: &str) {
+        self.previous = previous.to_string();
+    }
+
+    /// Sets the prompt being shown, whose non-printing parts the line
+    /// editor doesn't know about.
+    pub fn set_prompt(&mut self, prompt: Prompt) {
+        self.prompt = prompt;
+    }
+
+    fn is_command(&self, word: &str) -> bool {
+        self.commands.iter().any(|command| command == word)
+            || self.names.contains(word)
+            || is_executable(&self.path, word).is_ok()
+    }
+
+    /// Distinct commands from the most recent one.
+    pub fn recent_commands(&self, extra: &[String]) -> Vec<String> {
+        let mut seen = HashSet::new();
+        extra
+            .iter()
+            .rev()
+            .chain(self.history.iter().rev().map(|(command, _)| command))
+            .filter(|command| seen.insert(command.as_str()))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Suggests the most recent command starting with the line, preferring the
+/// ones run in the current directory.
+impl Hinter for CommandCompleter {
+    type Hint = String;
+
+    fn hint(&self, line: &str, pos: usize, ctx: &Context<'_>) -> Option<String> {
+        if line.trim().is_empty() || pos < line.len() || !self.previous.is_empty() {
+            return None;
         }
+
+        let history = ctx.history();
+        let session = (0..history.len())
+            .rev()
+            .filter_map(|i| history.get(i, SearchDirection::Reverse).ok().flatten())
+            .map(|result| result.entry.into_owned());
+
+        let here = self.history.iter().rev().filter(|(_, cwd)| *cwd == self.cwd);
+        let everywhere = self.history.iter().rev();
+
+        here.chain(everywhere)
+            .map(|(command, _)| command.clone())
+            .chain(session)
+            .find(|command| command.starts_with(line) && command.len() > line.len() && !command.contains('\n'))
+            .map(|command| command[line.len()..].to_string())
+    }
+}
+
+impl Highlighter for CommandCompleter {
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        if !self.styles.enabled {
+            return Cow::Borrowed(line);
+        }
+        Cow::Owned(highlight(&self.previous, line, pos, &self.styles, |word| self.is_command(word)))
+    }
+
+    fn highlight_prompt<'b, 's: 'b, 'p: 'b>(&'s self, prompt: &'p str, _default: bool) -> Cow<'b, str> {
+        if prompt == self.prompt.visible {
+            Cow::Borrowed(&self.prompt.display)
+        } else {
+            Cow::Borrowed(prompt)
+        }
+    }
+
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(self.styles.paint(&self.styles.hint, hint))
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize, _kind: CmdKind) -> bool {
+        self.styles.enabled
+    }
+}
+
+/// Enter inserts a newline while a command spanning several lines, as
+/// recalled from history or pasted, is unfinished. A single unfinished
+/// line is accepted and the shell reads the rest with `PS2`.
+impl Validator for CommandCompleter {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        let input = ctx.input();
+        if input.contains('\n') && is_incomplete(input, &self.aliases) {
+            return Ok(ValidationResult::Incomplete);
+        }
+        Ok(ValidationResult::Valid(None))
+    }
+}
+
+/// Commands taking `user@host` arguments, whose host is completed.
+const REMOTE_COMMANDS: [&str; 6] = ["ssh", "scp", "sftp", "rsync", "mosh", "ssh-copy-id"];
+
+/// Ctrl-F accepts the suggestion when the cursor is at the end of the line,
+/// and moves forward otherwise.
+pub struct AcceptHint;
+
+impl ConditionalEventHandler for AcceptHint {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, ctx: &EventContext) -> Option<Cmd> {
+        (ctx.has_hint() && ctx.pos() == ctx.line().len()).then_some(Cmd::CompleteHint)
     }
 }
 
 impl Completer for CommandCompleter {
-    type Candidate = String;
-    
+    type Candidate = Pair;
+
     fn complete(
         &self,
         line: &str,
         pos: usize,
         _ctx: &rustyline::Context<'_>,
     ) -> rustyline::Result<(usize, Vec<Self::Candidate>)> {
-        let words: Vec<&str> = line[..pos].split_whitespace().collect();
-        let partial = words.last().unwrap_or(&"");
-
-        {
-            let mut state = self.state.borrow_mut();
-            if state.last_partial != *partial {
-                state.tab_count = 0;
-                state.last_partial = partial.to_string();
-                state.last_matches.clear();
+        let word = Word::at(line, pos, &self.variables);
+        let partial = &line[word.start..pos];
+
+        if let Some(completion) = self.complete_source(&word, partial) {
+            return Ok(completion);
+        }
+        if word.is_command() && !partial.contains('/') && !partial.starts_with('~') {
+            return Ok(self.complete_command(&word, partial));
+        }
+        if let Some(spec) = word.command().filter(|_| !word.redirect).and_then(|command| self.spec(command)) {
+            return Ok(self.complete_programmable(spec, &word, partial, line, pos));
+        }
+        if let Some(completion) = self.complete_tool(&word, partial) {
+            return Ok(completion);
+        }
+
+        if word.value.starts_with('-') && !word.redirect {
+            if let Some(flags) = word.command().and_then(|command| self.help_flags(command)) {
+                let flags: Vec<(String, String)> = flags.into_iter().map(|flag| (flag.name, flag.description)).collect();
+                let mut matching: Vec<(String, String)> = flags.iter().filter(|(name, _)| name.starts_with(&word.value)).cloned().collect();
+                if matching.is_empty() {
+                    matching = fallback(flags, &word.value, &self.matchings, |(name, _)| name);
+                }
+                if !matching.is_empty() {
+                    let nospace = matching.len() == 1 && matching[0].0.ends_with('=');
+                    return Ok(self.insert(&word, matching, false, nospace, partial));
+                }
+            }
+        }
+
+        let kind = if word.is_command() {
+            PathKind::Executables
+        } else if !word.redirect && matches!(word.command(), Some("cd" | "pushd")) {
+            PathKind::Directories
+        } else {
+            PathKind::Any
+        };
+        Ok(self.complete_path(&word, partial, kind))
+    }
+}
+
+impl CommandCompleter {
+    /// Completes from the source the text before the cursor calls for:
+    /// a variable name after `$` or `${`, a user name after `~`, or a host
+    /// after `@` in the arguments of `ssh`, `scp` and the like.
+    fn complete_source(&self, word: &Word, partial: &str) -> Option<(usize, Vec<Pair>)> {
+        if word.quote == Some('\'') {
+            return None;
+        }
+
+        let name_start = partial.len() - partial.chars().rev().take_while(|c| c.is_ascii_alphanumeric() || *c == '_').count();
+        let name = &partial[name_start..];
+        let before = &partial[..name_start];
+        let lead = if before.ends_with("${") { "${" } else { "$" };
+        if before.ends_with('$') || before.ends_with("${") {
+            let dollar = name_start - lead.len();
+            let escaped = partial[..dollar].chars().rev().take_while(|c| *c == '\\').count() % 2 == 1;
+            if !escaped && !name.starts_with(|c: char| c.is_ascii_digit()) {
+                let mut names: Vec<String> = self.variables.keys().chain(&self.arrays).cloned().collect();
+                names.sort();
+                names.dedup();
+                let end = |variable: &str| {
+                    let value = self.variables.get(variable).filter(|value| !value.is_empty());
+                    let directory = value.is_some_and(|value| Path::new(&self.cwd).join(value).is_dir());
+                    let close = if lead == "${" { "}" } else { "" };
+                    match (directory, word.quote) {
+                        (true, _) => format!("{}/", close),
+                        (false, None) => format!("{} ", close),
+                        (false, Some(_)) => close.to_string(),
+                    }
+                };
+                return Some(self.complete_name(word.start + dollar, lead, name, names, end));
+            }
+        }
+
+        if word.quote.is_none() && partial.len() > 1 && partial.starts_with('~') && !partial.contains('/') {
+            return Some(self.complete_name(word.start, "~", &partial[1..], users(""), |_| "/".to_string()));
+        }
+
+        let command = word.command().filter(|_| !word.is_command() && !word.redirect)?;
+        let command = command.rsplit('/').next().unwrap_or(command);
+        if REMOTE_COMMANDS.contains(&command) && !partial.starts_with('-') {
+            let (_, host) = partial.rsplit_once('@')?;
+            if host.contains(['/', ':']) {
+                return None;
+            }
+            let end = if matches!(command, "scp" | "sftp" | "rsync") { ":" } else { " " };
+            let start = word.start + partial.len() - host.len();
+            return Some(self.complete_name(start, "", host, hosts(self.variables.get("HOME").map(String::as_str), ""), |_| end.to_string()));
+        }
+        None
+    }
+
+    /// Completes a name typed after `lead` at `start`, among `names`: the
+    /// only match is followed by what `end` gives for it, several are
+    /// extended to the start they share or listed.
+    fn complete_name<F: Fn(&str) -> String>(&self, start: usize, lead: &str, typed: &str, names: Vec<String>, end: F) -> (usize, Vec<Pair>) {
+        let matching: Vec<String> = names.iter().filter(|name| name.starts_with(typed)).cloned().collect();
+        let names = match matching.is_empty() {
+            true => fallback(names, typed, &self.matchings, |name| name),
+            false => matching,
+        };
+
+        match names.len() {
+            0 => (start, Vec::new()),
+            1 => (start, vec![pair(format!("{}{}{}", lead, names[0], end(&names[0])))]),
+            _ => {
+                let common = common_prefix(names.clone());
+                if extends(&common, typed) {
+                    return (start, vec![pair(format!("{}{}", lead, common))]);
+                }
+                let entries: Vec<(String, String)> = names.iter().map(|name| (format!("{}{}", lead, name), String::new())).collect();
+                self.ambiguous(start, &entries, &format!("{}{}", lead, typed))
             }
         }
+    }
 
-        if words.is_empty() || (words.len() == 1 && !line.ends_with(' ')) {
-            let partial = words.last().unwrap_or(&"");
+    fn complete_command(&self, word: &Word, partial: &str) -> (usize, Vec<Pair>) {
+        let mut matches: HashSet<String> = self.commands
+            .iter()
+            .filter(|cmd| cmd.starts_with(partial))
+            .map(|s| format!("{} ", s))
+            .collect();
 
-            let mut matches: HashSet<String> = self.commands
+        if matches.is_empty() {
+            matches = find_executables(&self.path, partial)
                 .iter()
                 .filter(|cmd| cmd.starts_with(partial))
                 .map(|s| format!("{} ", s))
                 .collect();
+                
+        }
 
-            if matches.len() == 0 {
-                matches = find_executables(&self.path, partial)
-                    .iter()
-                    .filter(|cmd| cmd.starts_with(partial))
-                    .map(|s| format!("{} ", s))
-                    .collect();
-                    
-            }
-
-            let mut matches: Vec<String> = matches.into_iter().collect();
-            matches.sort();
+        if matches.is_empty() {
+            let mut commands = self.commands.clone();
+            commands.extend(find_executables(&self.path, ""));
+            commands.sort();
+            commands.dedup();
+            let found = fallback(commands, partial, &self.matchings, |command| command);
+            let entries = found.into_iter().map(|command| (command, String::new())).collect();
+            return self.insert(word, entries, false, false, partial);
+        }
 
-            match matches.len() {
-                0 => Ok((0, Vec::new())),
-                1 => Ok((0, matches.clone().into_iter().collect())),
-                _ => {
-                    let common_prefix = format!("{} ", common_prefix(matches.clone()));
+        let mut matches: Vec<String> = matches.into_iter().collect();
+        matches.sort();
 
-                    if !common_prefix.is_empty() && matches.contains(&common_prefix){
-                        return Ok((0, vec![common_prefix.trim().to_string()]));
-                    }
+        match matches.len() {
+            0 => (word.start, Vec::new()),
+            1 => (word.start, matches.into_iter().map(pair).collect()),
+            _ => {
+                let common_prefix = format!("{} ", common_prefix(matches.clone()));
 
-                    let mut state = self.state.borrow_mut();
-                    state.tab_count += 1;
-                    
-                    match state.tab_count {
-                        1 => {
-                            print!("\x07");
-                            io::stdout().flush().unwrap();
-                            Ok((0, Vec::new()))
-                        },
-                        2 => {
-                            println!();
-                            for m in matches {
-                                print!("{} ", m)
-                            }
-                            println!();
-                            print!("$ {}", partial);
-                            io::stdout().flush().unwrap(); 
-                            Ok((0, Vec::new()))
-                        },
-                        _ => Ok((0, Vec::new())),
-                    }
+                if !common_prefix.is_empty() && matches.contains(&common_prefix){
+                    return (word.start, vec![pair(common_prefix.trim().to_string())]);
                 }
+
+                let entries: Vec<(String, String)> = matches.into_iter().map(|name| (name.trim_end().to_string(), String::new())).collect();
+                self.ambiguous(word.start, &entries, partial)
             }
-        } else {
-            Ok((pos, Vec::new()))
         }
     }
+
+    /// The completion registered for a command, by its name or the last
+    /// component of its path.
+    fn spec(&self, command: &str) -> Option<&CompletionSpec> {
+        self.completions
+            .get(command)
+            .or_else(|| self.completions.get(command.rsplit('/').next().unwrap_or_default()))
+    }
+
+    /// Completes with the completion shipped for the command, if any. Words
+    /// matching none of its candidates are left to path completion where
+    /// a path fits, and matched loosely otherwise.
+    fn complete_tool(&self, word: &Word, partial: &str) -> Option<(usize, Vec<Pair>)> {
+        if word.redirect || word.value.starts_with('-') {
+            return None;
+        }
+
+        let command = word.command()?;
+        let command = self.aliases.get(command).and_then(|alias| alias.split_whitespace().next()).unwrap_or(command);
+        let spec = tools::find(command.rsplit('/').next().unwrap_or(command))?;
+        let invocation = Invocation {
+            words: &word.previous,
+            cwd: Path::new(&self.cwd),
+            home: self.variables.get("HOME").filter(|home| !home.is_empty()).map(Path::new)
+        };
+        let candidates = (spec.complete)(&invocation)?;
+
+        let mut matching: Vec<String> = candidates.words.iter().filter(|candidate| candidate.starts_with(&word.value)).cloned().collect();
+        if matching.is_empty() && candidates.paths {
+            return None;
+        }
+        if matching.is_empty() {
+            matching = fallback(candidates.words, &word.value, &self.matchings, |candidate| candidate);
+        }
+        let matching = matching.into_iter().map(|candidate| (candidate, String::new())).collect();
+        Some(self.insert(word, matching, false, false, partial))
+    }
+
+    /// Completes with the registered completion of the command, falling
+    /// back to paths with `-o default` or `-o dirnames`.
+    fn complete_programmable(&self, spec: &CompletionSpec, word: &Word, partial: &str, line: &str, pos: usize) -> (usize, Vec<Pair>) {
+        let mut candidates = match self.shell.borrow_mut().as_mut() {
+            Some(shell) => generate(spec, word, line, pos, &self.cwd, shell),
+            None => vec![],
+        };
+
+        let (directory, typed) = word.split_path();
+        if candidates.is_empty() && !typed.is_empty() {
+            // Ask for everything, as if the last component were not typed yet.
+            let blank = word.blank();
+            let line = format!("{}{}", &line[..word.segment], &line[pos..]);
+            let all = match self.shell.borrow_mut().as_mut() {
+                Some(shell) => generate(spec, &blank, &line, word.segment, &self.cwd, shell),
+                None => vec![],
+            };
+            candidates = fallback(all, typed, &self.matchings, |candidate| candidate.strip_prefix(directory).unwrap_or(candidate));
+        }
+
+        if candidates.is_empty() && spec.option("default") {
+            return self.complete_path(word, partial, PathKind::Any);
+        }
+        if candidates.is_empty() && spec.option("dirnames") {
+            return self.complete_path(word, partial, PathKind::Directories);
+        }
+        let candidates = candidates.into_iter().map(|candidate| (candidate, String::new())).collect();
+        self.insert(word, candidates, spec.filenames(), spec.option("nospace"), partial)
+    }
+
+    /// Completes the last component of a path. Arguments of `cd` and
+    /// `pushd` only complete to directories, commands to directories and
+    /// executables.
+    fn complete_path(&self, word: &Word, partial: &str, kind: PathKind) -> (usize, Vec<Pair>) {
+        if partial == "~" {
+            return (word.start, vec![pair("~/".to_string())]);
+        }
+
+        let (directory, typed) = word.split_path();
+        let mut names = complete_paths(word, kind, &self.cwd);
+        if names.is_empty() {
+            let all = complete_paths(&word.blank(), kind, &self.cwd);
+            names = fallback(all, typed, &self.matchings, |name| name.trim_end_matches('/'));
+        }
+        let paths = names
+            .into_iter()
+            .map(|name| (format!("{}{}", directory, name), String::new()))
+            .collect();
+        self.insert(word, paths, true, false, partial)
+    }
+
+    /// Options of an external command read from its `--help`, for a word
+    /// starting with `-`.
+    fn help_flags(&self, command: &str) -> Option<Vec<Flag>> {
+        let command = self.aliases.get(command).and_then(|alias| alias.split_whitespace().next()).unwrap_or(command);
+        if self.commands.iter().any(|builtin| builtin == command) || self.names.contains(command) {
+            return None;
+        }
+
+        let binary = is_executable(&self.path, command).ok()?;
+        let cache = match self.variables.get("XDG_CACHE_HOME").filter(|dir| !dir.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(self.variables.get("HOME")?).join(".cache"),
+        };
+        Some(command_flags(Path::new(&binary), Some(&cache.join("rshell").join("help"))))
+    }
+
+    /// Replaces the word with the only candidate, followed by a space, or
+    /// with the start the candidates share. File names are quoted, and
+    /// only their last component is replaced. Candidates come with a
+    /// description, shown when they are listed. `partial` is the word as
+    /// typed up to the cursor.
+    fn insert(&self, word: &Word, candidates: Vec<(String, String)>, filenames: bool, nospace: bool, partial: &str) -> (usize, Vec<Pair>) {
+        if candidates.is_empty() {
+            return (word.start, Vec::new());
+        }
+
+        let common = common_prefix(candidates.iter().map(|(candidate, _)| candidate.clone()).collect());
+        if candidates.len() > 1 && !extends(&common, &word.value) {
+            let shown: Vec<(String, String)> = match filenames {
+                true => candidates.into_iter().map(|(path, description)| (file_name(&path).to_string(), description)).collect(),
+                false => candidates,
+            };
+            return self.ambiguous(word.start, &shown, partial);
+        }
+
+        let finished = candidates.len() == 1 && !nospace && !(filenames && common.ends_with('/'));
+        let (directory, _) = word.split_path();
+        match common.strip_prefix(directory) {
+            Some(name) if filenames => (word.segment, vec![pair(word.replacement(name, finished))]),
+            _ if finished => (word.start, vec![pair(format!("{} ", common))]),
+            _ => (word.start, vec![pair(common)]),
+        }
+    }
+
+    /// Several matches with nothing to add: each leaves the word as typed,
+    /// so that the editor rings the bell on the first Tab and lists them
+    /// in columns on the second, asking first when there are many.
+    /// Descriptions are aligned after the names.
+    fn ambiguous(&self, start: usize, matches: &[(String, String)], partial: &str) -> (usize, Vec<Pair>) {
+        let described = matches.iter().any(|(_, description)| !description.is_empty());
+        let width = matches.iter().map(|(m, _)| m.chars().count()).max().unwrap_or_default();
+
+        let candidates = matches
+            .iter()
+            .map(|(m, description)| Pair {
+                display: match described {
+                    true => format!("{:width$}  {}", m, description, width = width).trim_end().to_string(),
+                    false => m.clone(),
+                },
+                replacement: partial.to_string(),
+            })
+            .collect();
+        (start, candidates)
+    }
+}
+
+/// Whether the start the candidates share adds to the word typed or fixes
+/// its case.
+fn extends(common: &str, typed: &str) -> bool {
+    common != typed && common.to_lowercase().starts_with(&typed.to_lowercase())
+}
+
+/// A candidate shown as it is inserted.
+fn pair(text: String) -> Pair {
+    Pair { display: text.clone(), replacement: text }
+}
+
+/// The last component of a path, keeping the `/` of a directory.
+fn file_name(path: &str) -> &str {
+    let end = path.len() - usize::from(path.ends_with('/') && path.len() > 1);
+    path[..end].rfind('/').map_or(path, |slash| &path[slash + 1..])
 }
 
 pub fn common_prefix(names: Vec<String>) -> String {
@@ -130,4 +556,108 @@ pub fn common_prefix(names: Vec<String>) -> String {
     }
 
     prefix
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustyline::history::DefaultHistory;
+
+    fn complete(completer: &CommandCompleter, line: &str) -> (usize, Vec<(String, String)>) {
+        let history = DefaultHistory::new();
+        let (start, pairs) = completer.complete(line, line.len(), &Context::new(&history)).unwrap();
+        (start, pairs.into_iter().map(|pair| (pair.display, pair.replacement)).collect())
+    }
+
+    fn listed(names: &[&str], typed: &str) -> Vec<(String, String)> {
+        names.iter().map(|name| (name.to_string(), typed.to_string())).collect()
+    }
+
+    #[test]
+    fn ambiguous_commands_are_listed_and_keep_the_word() {
+        let completer = CommandCompleter::new(["echo", "exit", "export"].map(String::from).to_vec(), String::new());
+        assert_eq!(complete(&completer, "ls; e"), (4, listed(&["echo", "exit", "export"], "e")));
+        assert_eq!(complete(&completer, "ex"), (0, listed(&["exit", "export"], "ex")));
+        assert_eq!(complete(&completer, "ech"), (0, vec![("echo ".to_string(), "echo ".to_string())]));
+    }
+
+    #[test]
+    fn ambiguous_files_are_listed_by_name() {
+        let dir = std::env::temp_dir().join(format!("rshell-listing-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("alps")).unwrap();
+        std::fs::write(dir.join("alpha.txt"), "").unwrap();
+
+        let mut completer = CommandCompleter::new(vec![], String::new());
+        completer.cwd = dir.display().to_string();
+        // The start they share is inserted first.
+        assert_eq!(complete(&completer, "cat al"), (4, vec![("alp".to_string(), "alp".to_string())]));
+        assert_eq!(complete(&completer, "cat alp"), (4, listed(&["alpha.txt", "alps/"], "alp")));
+        assert_eq!(complete(&completer, "cat ./alp"), (4, listed(&["alpha.txt", "alps/"], "./alp")));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn descriptions_are_aligned_after_the_names() {
+        let completer = CommandCompleter::new(vec![], String::new());
+        let word = Word::at("ls --al", 7, &HashMap::new());
+        let options = [("--all", "show everything"), ("--almost-all", "")].map(|(name, text)| (name.to_string(), text.to_string()));
+        let (start, pairs) = completer.insert(&word, options.to_vec(), false, false, "--al");
+        let shown: Vec<&str> = pairs.iter().map(|pair| pair.display.as_str()).collect();
+        assert_eq!(start, 3);
+        assert_eq!(shown, ["--all         show everything", "--almost-all"]);
+        assert!(pairs.iter().all(|pair| pair.replacement == "--al"));
+    }
+
+    fn inserted(text: &str) -> Vec<(String, String)> {
+        vec![(text.to_string(), text.to_string())]
+    }
+
+    #[test]
+    fn variable_names_after_a_dollar() {
+        let mut completer = CommandCompleter::new(vec![], String::new());
+        completer.cwd = "/".to_string();
+        completer.variables = [("VAR_ONE", "1"), ("VAR_TWO", "2"), ("VALUE", "x"), ("TMPDIR", "/tmp")]
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .into();
+        completer.arrays = vec!["VARRAY".to_string()];
+
+        assert_eq!(complete(&completer, "echo ${VA"), (5, listed(&["${VALUE", "${VARRAY", "${VAR_ONE", "${VAR_TWO"], "${VA")));
+        assert_eq!(complete(&completer, "echo ${VAR_"), (5, listed(&["${VAR_ONE", "${VAR_TWO"], "${VAR_")));
+        assert_eq!(complete(&completer, "echo ${VAL"), (5, inserted("${VALUE} ")));
+        assert_eq!(complete(&completer, "echo \"$VAL"), (6, inserted("$VALUE")));
+        assert_eq!(complete(&completer, "cd $TMP"), (3, inserted("$TMPDIR/")));
+        assert_eq!(complete(&completer, "echo a$VARR"), (6, inserted("$VARRAY ")));
+        // Escaped or single-quoted dollars are not expansions.
+        assert!(complete(&completer, "echo \\$VAL").1.iter().all(|(shown, _)| !shown.contains("VALUE")));
+        assert!(complete(&completer, "echo '$VAL").1.iter().all(|(shown, _)| !shown.contains("VALUE")));
+    }
+
+    #[test]
+    fn user_names_after_a_tilde() {
+        let completer = CommandCompleter::new(vec![], String::new());
+        assert_eq!(complete(&completer, "ls ~roo"), (3, inserted("~root/")));
+        assert_eq!(complete(&completer, "ls ~no-such-user-here").1, vec![]);
+    }
+
+    #[test]
+    fn hosts_after_an_at_sign() {
+        let home = std::env::temp_dir().join(format!("rshell-hosts-{}", std::process::id()));
+        std::fs::create_dir_all(home.join(".ssh")).unwrap();
+        std::fs::write(home.join(".ssh/known_hosts"), [
+            "fixture-host1,10.1.2.3 ssh-ed25519 AAAA",
+            "[fixture-host2]:2222 ssh-ed25519 AAAA",
+            "|1|F1E1KeoE/eEWhi10WpGv4OdiO6Y=|3988QV0VE8wmZL7suNrYQLITLCg= ssh-rsa AAAA",
+            "fixture-other ssh-ed25519 AAAA",
+        ].join("\n")).unwrap();
+
+        let mut completer = CommandCompleter::new(vec![], String::new());
+        completer.variables.insert("HOME".to_string(), home.display().to_string());
+        assert_eq!(complete(&completer, "ssh user@fixture-ho"), (9, inserted("fixture-host")));
+        assert_eq!(complete(&completer, "ssh user@fixture-host"), (9, listed(&["fixture-host1", "fixture-host2"], "fixture-host")));
+        assert_eq!(complete(&completer, "scp f user@fixture-ot"), (11, inserted("fixture-other:")));
+        assert_eq!(complete(&completer, "ssh -l user @fixture-ot"), (13, inserted("fixture-other ")));
+        // Hashed entries name no host.
+        assert!(complete(&completer, "ssh user@F1E1").1.is_empty());
+        let _ = std::fs::remove_dir_all(&home);
+    }
+}
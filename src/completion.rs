@@ -0,0 +1,734 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::executor::{call_function, command_substitution, report};
+use crate::search::fuzzy_score;
+use crate::expansion::{expand_text, quote_word, user_home};
+use crate::shell::{Flow, Shell};
+use crate::sherror::ShellError;
+use crate::shfile::is_executable_file;
+
+/// Options of `complete -o`.
+const OPTIONS: [&str; 4] = ["default", "dirnames", "filenames", "nospace"];
+
+/// How the arguments of a command are completed, as registered with
+/// `complete`.
+#[derive(Clone, Default, PartialEq)]
+pub struct CompletionSpec {
+    /// `-W`: a list of words, expanded each time.
+    pub words: Option<String>,
+    /// `-F`: a function that sets `COMPREPLY`.
+    pub function: Option<String>,
+    /// `-C`: a command printing the candidates, one per line.
+    pub command: Option<String>,
+    /// `-d` and `-f`: directory and file names.
+    pub directories: bool,
+    pub files: bool,
+    /// `-o` options.
+    pub options: Vec<String>
+}
+
+impl CompletionSpec {
+    /// Reads the options of `complete` or `compgen`. Flags of the builtin
+    /// itself, listed in `flags`, are returned with the other arguments.
+    pub fn parse(builtin: &str, arguments: &[String], flags: &str) -> Result<(CompletionSpec, Vec<char>, Vec<String>), ShellError> {
+        let mut spec = CompletionSpec::default();
+        let mut seen = vec![];
+        let mut arguments = arguments.iter();
+        let mut rest = vec![];
+
+        while let Some(argument) = arguments.next() {
+            if argument == "--" {
+                rest.extend(arguments.cloned());
+                break;
+            }
+            if argument.len() < 2 || !argument.starts_with('-') {
+                rest.push(argument.clone());
+                rest.extend(arguments.cloned());
+                break;
+            }
+
+            let mut letters = argument[1..].chars();
+            while let Some(letter) = letters.next() {
+                match letter {
+                    'd' => spec.directories = true,
+                    'f' => spec.files = true,
+                    'o' | 'W' | 'F' | 'C' => {
+                        let attached: String = letters.by_ref().collect();
+                        let value = match attached.is_empty() {
+                            true => arguments.next().cloned().ok_or_else(|| {
+                                ShellError::Usage(format!("{}: -{}: option requires an argument", builtin, letter))
+                            })?,
+                            false => attached,
+                        };
+                        match letter {
+                            'o' if !OPTIONS.contains(&value.as_str()) => {
+                                return Err(ShellError::InvalidArgument(format!("{}: {}: invalid option name", builtin, value)));
+                            },
+                            'o' => spec.options.push(value),
+                            'W' => spec.words = Some(value),
+                            'F' => spec.function = Some(value),
+                            _ => spec.command = Some(value),
+                        }
+                    },
+                    letter if flags.contains(letter) => seen.push(letter),
+                    letter => return Err(ShellError::Usage(format!("{}: -{}: invalid option", builtin, letter))),
+                }
+            }
+        }
+
+        Ok((spec, seen, rest))
+    }
+
+    pub fn option(&self, name: &str) -> bool {
+        self.options.iter().any(|option| option == name)
+    }
+
+    /// Whether the candidates are file names, to be quoted and completed
+    /// into when they are directories.
+    pub fn filenames(&self) -> bool {
+        self.files || self.directories || self.option("filenames")
+    }
+
+    /// The `complete` command that registers this completion for `name`.
+    pub fn to_command(&self, name: &str) -> String {
+        let mut command = vec!["complete".to_string()];
+        for option in &self.options {
+            command.push(format!("-o {}", option));
+        }
+        if self.directories {
+            command.push("-d".to_string());
+        }
+        if self.files {
+            command.push("-f".to_string());
+        }
+        if let Some(words) = &self.words {
+            command.push(format!("-W {}", quote_word(words)));
+        }
+        if let Some(function) = &self.function {
+            command.push(format!("-F {}", function));
+        }
+        if let Some(source) = &self.command {
+            command.push(format!("-C {}", quote_word(source)));
+        }
+        command.push(quote_word(name));
+        command.join(" ")
+    }
+}
+
+/// Candidates of `spec` for `word`, as whole words. Functions and commands
+/// see the line being completed in `COMP_WORDS`, `COMP_CWORD`, `COMP_LINE`
+/// and `COMP_POINT`, and get the command name, the word and the word
+/// before it as arguments.
+pub fn generate(spec: &CompletionSpec, word: &Word, line: &str, pos: usize, cwd: &str, shell: &mut Shell) -> Vec<String> {
+    let mut candidates = vec![];
+    let current = word.value.clone();
+
+    if spec.files || spec.directories {
+        let kind = if spec.files { PathKind::Any } else { PathKind::Directories };
+        let (directory, _) = word.split_path();
+        candidates.extend(complete_paths(word, kind, cwd).into_iter().map(|name| format!("{}{}", directory, name)));
+    }
+
+    if let Some(words) = &spec.words {
+        let expanded = expand_text(words, shell).unwrap_or_else(|err| {
+            report(&err);
+            String::new()
+        });
+        candidates.extend(expanded.split_whitespace().filter(|candidate| candidate.starts_with(&current)).map(String::from));
+    }
+
+    if spec.function.is_some() || spec.command.is_some() {
+        let mut words = word.previous.clone();
+        words.push(current.clone());
+        let command = words.first().cloned().unwrap_or_default();
+        let previous = words.len().checked_sub(2).map(|i| words[i].clone()).unwrap_or_default();
+        let point = line[..pos.min(line.len())].chars().count();
+
+        shell.set_var("COMP_CWORD", &(words.len() - 1).to_string());
+        shell.set_var("COMP_LINE", line);
+        shell.set_var("COMP_POINT", &point.to_string());
+        shell.exported.extend(["COMP_LINE".to_string(), "COMP_POINT".to_string()]);
+        shell.arrays.insert("COMP_WORDS".to_string(), words);
+
+        if let Some(function) = spec.function.as_ref().and_then(|name| shell.functions.get(name).cloned()) {
+            shell.environment.remove("COMPREPLY");
+            shell.arrays.remove("COMPREPLY");
+            call_function(&function, vec![command.clone(), current.clone(), previous.clone()], shell);
+            shell.flow = Flow::Normal;
+
+            let reply = shell.arrays.get("COMPREPLY").cloned().or_else(|| shell.get_var("COMPREPLY").map(|reply| vec![reply]));
+            candidates.extend(reply.unwrap_or_default());
+        }
+
+        if let Some(source) = &spec.command {
+            let source = format!("{} {} {} {}", source, quote_word(&command), quote_word(&current), quote_word(&previous));
+            match command_substitution(&source, shell) {
+                Ok(output) => candidates.extend(output.lines().filter(|line| !line.is_empty()).map(String::from)),
+                Err(err) => {
+                    report(&err);
+                },
+            }
+        }
+    }
+
+    candidates.sort();
+    candidates.dedup();
+    candidates
+}
+
+/// The word under the cursor, as the shell would read it.
+#[derive(Clone)]
+pub struct Word {
+    /// Byte offset of the word in the line.
+    pub start: usize,
+    /// The word without its quotes, with `~` and variables expanded.
+    pub value: String,
+    /// Byte offset in the line, and in `value`, of the last component of a
+    /// path: what completion replaces.
+    pub segment: usize,
+    pub segment_value: usize,
+    /// The quote still open at the cursor, and the one open where the last
+    /// component starts.
+    pub quote: Option<char>,
+    pub segment_quote: Option<char>,
+    /// Words of the command before this one. Empty in command position.
+    pub previous: Vec<String>,
+    /// Whether the word follows a redirection operator.
+    pub redirect: bool
+}
+
+impl Word {
+    /// Reads the word ending at `pos` in `line`. Variables are looked up in
+    /// `variables`.
+    pub fn at(line: &str, pos: usize, variables: &HashMap<String, String>) -> Word {
+        let text = &line[..pos];
+        let mut word = Word::empty(pos);
+        let mut in_word = false;
+        let mut chars = text.char_indices().peekable();
+
+        while let Some((i, c)) = chars.next() {
+            if word.quote.is_none() && !in_word && c == '#' {
+                // A comment runs to the end of the line.
+                word = Word::empty(pos);
+                if !text[i..].contains('\n') {
+                    return word;
+                }
+                while chars.next_if(|(_, c)| *c != '\n').is_some() {}
+                continue;
+            }
+
+            if word.quote.is_none() && (c.is_whitespace() || ";&|()<>".contains(c)) {
+                if in_word {
+                    in_word = false;
+                    if !word.redirect {
+                        word.previous.push(std::mem::take(&mut word.value));
+                    }
+                    word.redirect = false;
+                }
+                match c {
+                    '<' | '>' => word.redirect = true,
+                    ';' | '&' | '|' | '(' | ')' | '\n' => {
+                        word.previous.clear();
+                        word.redirect = false;
+                    },
+                    _ => {},
+                }
+                word.value.clear();
+                word.segment_value = 0;
+                word.segment_quote = None;
+                continue;
+            }
+
+            if !in_word {
+                in_word = true;
+                word.start = i;
+                word.segment = i;
+                word.segment_value = 0;
+                word.segment_quote = None;
+                word.value.clear();
+
+                if c == '~' {
+                    let end = text[i..].find('/').map(|end| i + end);
+                    let home = match end {
+                        Some(end) if end == i + 1 => variables.get("HOME").cloned(),
+                        Some(end) => user_home(&text[i + 1..end]),
+                        None => None,
+                    };
+                    if let (Some(end), Some(home)) = (end, home) {
+                        word.value.push_str(&home);
+                        while chars.next_if(|(j, _)| *j <= end).is_some() {}
+                        word.push_slash(end, None);
+                        continue;
+                    }
+                }
+            }
+
+            match (word.quote, c) {
+                (Some('\''), '\'') | (Some('"'), '"') => word.quote = None,
+                (quote, '/') => word.push_slash(i, quote),
+                (Some('\''), c) => word.value.push(c),
+                (None, '\'' | '"') => word.quote = Some(c),
+                (quote, '\\') => match chars.next() {
+                    Some((_, next)) if quote.is_none() || "$`\"\\".contains(next) => word.value.push(next),
+                    Some((_, next)) => {
+                        word.value.push('\\');
+                        word.value.push(next);
+                    },
+                    None => {},
+                },
+                (_, '$') => {
+                    let braced = chars.next_if(|(_, c)| *c == '{').is_some();
+                    let mut name = String::new();
+                    while let Some((_, c)) = chars.next_if(|(_, c)| c.is_ascii_alphanumeric() || *c == '_') {
+                        name.push(c);
+                    }
+                    if braced {
+                        chars.next_if(|(_, c)| *c == '}');
+                    }
+                    if name.is_empty() && !braced {
+                        word.value.push('$');
+                    } else if let Some(value) = variables.get(&name) {
+                        word.value.push_str(value);
+                    }
+                },
+                (_, c) => word.value.push(c),
+            }
+        }
+
+        if !in_word {
+            word.start = pos;
+            word.segment = pos;
+            word.value.clear();
+            word.segment_value = 0;
+            word.segment_quote = None;
+        }
+        word
+    }
+
+    /// A word already unquoted, as given to `compgen`.
+    pub fn literal(value: &str) -> Word {
+        let mut word = Word::empty(0);
+        word.value = value.to_string();
+        word.segment_value = value.rfind('/').map_or(0, |slash| slash + 1);
+        word
+    }
+
+    fn empty(pos: usize) -> Word {
+        Word {
+            start: pos,
+            value: String::new(),
+            segment: pos,
+            segment_value: 0,
+            quote: None,
+            segment_quote: None,
+            previous: vec![],
+            redirect: false
+        }
+    }
+
+    fn push_slash(&mut self, i: usize, quote: Option<char>) {
+        self.value.push('/');
+        self.segment = i + 1;
+        self.segment_value = self.value.len();
+        self.segment_quote = quote;
+    }
+
+    pub fn is_command(&self) -> bool {
+        self.previous.is_empty() && !self.redirect
+    }
+
+    /// The command this word is an argument of.
+    pub fn command(&self) -> Option<&str> {
+        self.previous.first().map(String::as_str)
+    }
+
+    /// The directory part of the word, and the start of the name in it.
+    pub fn split_path(&self) -> (&str, &str) {
+        self.value.split_at(self.segment_value.min(self.value.len()))
+    }
+
+    /// The word without what was typed of its last component, but a
+    /// leading dot, to list everything it could complete to.
+    pub fn blank(&self) -> Word {
+        let mut word = self.clone();
+        let segment = self.segment_value.min(self.value.len());
+        let hidden = self.value[segment..].starts_with('.');
+        word.value.truncate(segment);
+        if hidden {
+            word.value.push('.');
+        }
+        word
+    }
+
+    /// Text replacing the last component of the word with `name`, quoted
+    /// the way the word is. A finished word is closed and followed by a
+    /// space.
+    pub fn replacement(&self, name: &str, finished: bool) -> String {
+        let mut text = String::new();
+        if self.quote.is_some() && self.quote != self.segment_quote {
+            text.push(self.quote.unwrap_or_default());
+        }
+        text.push_str(&escape(name, self.quote));
+        if finished {
+            if let Some(quote) = self.quote {
+                text.push(quote);
+            }
+            text.push(' ');
+        }
+        text
+    }
+}
+
+/// Escapes `text` for the inside of `quote`, or with backslashes outside
+/// quotes.
+pub fn escape(text: &str, quote: Option<char>) -> String {
+    match quote {
+        Some('\'') => text.replace('\'', "'\\''"),
+        Some(_) => {
+            let mut escaped = String::new();
+            for c in text.chars() {
+                if "$`\"\\".contains(c) {
+                    escaped.push('\\');
+                }
+                escaped.push(c);
+            }
+            escaped
+        },
+        None => {
+            let mut escaped = String::new();
+            for (i, c) in text.chars().enumerate() {
+                if c.is_whitespace() || "\\'\"$`;&|()<>*?[]{}!#".contains(c) || (i == 0 && c == '~') {
+                    escaped.push('\\');
+                }
+                escaped.push(c);
+            }
+            escaped
+        },
+    }
+}
+
+/// How candidates are matched when none starts with the word, in the order
+/// given by `RSHELL_COMPLETION`, e.g. `ignore-case:fuzzy`.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Matching {
+    IgnoreCase,
+    Substring,
+    /// The characters of the word in order, possibly with gaps.
+    Fuzzy
+}
+
+impl Matching {
+    /// The matchings listed in `setting`, all of them when it is unset.
+    pub fn from_setting(setting: Option<&str>) -> Vec<Matching> {
+        setting
+            .unwrap_or("ignore-case:substring:fuzzy")
+            .split(':')
+            .filter_map(|name| match name {
+                "ignore-case" => Some(Matching::IgnoreCase),
+                "substring" => Some(Matching::Substring),
+                "fuzzy" => Some(Matching::Fuzzy),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn score(self, typed: &str, name: &str) -> Option<i64> {
+        match self {
+            Matching::IgnoreCase => name.to_lowercase().starts_with(&typed.to_lowercase()).then_some(0),
+            Matching::Substring => name.to_lowercase().find(&typed.to_lowercase()).map(|i| -(i as i64)),
+            Matching::Fuzzy => fuzzy_score(typed, name),
+        }
+    }
+}
+
+/// The candidates whose name, given by `name`, matches `typed` with the
+/// first of `matchings` that matches any, best first.
+pub fn fallback<T, F: Fn(&T) -> &str>(candidates: Vec<T>, typed: &str, matchings: &[Matching], name: F) -> Vec<T> {
+    if typed.is_empty() {
+        return vec![];
+    }
+
+    for matching in matchings {
+        let mut scores: Vec<(i64, usize)> = candidates
+            .iter()
+            .enumerate()
+            .filter_map(|(i, candidate)| matching.score(typed, name(candidate)).map(|score| (score, i)))
+            .collect();
+        if scores.is_empty() {
+            continue;
+        }
+
+        scores.sort_by_key(|(score, i)| (-score, *i));
+        let mut candidates: Vec<Option<T>> = candidates.into_iter().map(Some).collect();
+        return scores.into_iter().filter_map(|(_, i)| candidates[i].take()).collect();
+    }
+    vec![]
+}
+
+/// Which entries of a directory a path can complete to.
+#[derive(Clone, Copy, PartialEq)]
+pub enum PathKind {
+    Any,
+    Directories,
+    /// Directories and executable files, for a command.
+    Executables
+}
+
+/// Entries of the directory part of `word` whose name starts with its last
+/// component, sorted. Directories end with `/`. Hidden files are only
+/// included when the name starts with a dot.
+pub fn complete_paths(word: &Word, kind: PathKind, cwd: &str) -> Vec<String> {
+    let (directory, prefix) = word.split_path();
+    let base = if directory.is_empty() {
+        Path::new(cwd).to_path_buf()
+    } else {
+        Path::new(cwd).join(directory)
+    };
+
+    let Ok(entries) = fs::read_dir(&base) else {
+        return vec![];
+    };
+
+    let mut names: Vec<String> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+                return None;
+            }
+
+            // Follow symbolic links to tell directories apart.
+            let path = entry.path();
+            if path.is_dir() {
+                return Some(format!("{}/", name));
+            }
+            match kind {
+                PathKind::Any => Some(name),
+                PathKind::Directories => None,
+                PathKind::Executables => is_executable_file(&path.to_string_lossy()).then_some(name),
+            }
+        })
+        .collect();
+
+    names.sort();
+    names
+}
+
+/// Login names from `/etc/passwd` starting with `prefix`, sorted.
+pub fn users(prefix: &str) -> Vec<String> {
+    users_in(&fs::read_to_string("/etc/passwd").unwrap_or_default(), prefix)
+}
+
+fn users_in(passwd: &str, prefix: &str) -> Vec<String> {
+    let mut names: Vec<String> = passwd
+        .lines()
+        .filter_map(|line| line.split(':').next())
+        .filter(|name| !name.is_empty() && !name.starts_with('#') && name.starts_with(prefix))
+        .map(String::from)
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Host names starting with `prefix`, sorted: those of `/etc/hosts`, the
+/// `Host` entries of `~/.ssh/config` without patterns, and the hosts of
+/// `~/.ssh/known_hosts` that are not hashed.
+pub fn hosts(home: Option<&str>, prefix: &str) -> Vec<String> {
+    let ssh = home.filter(|home| !home.is_empty()).map(|home| Path::new(home).join(".ssh"));
+    hosts_in(&fs::read_to_string("/etc/hosts").unwrap_or_default(), ssh.as_deref(), prefix)
+}
+
+fn hosts_in(etc_hosts: &str, ssh: Option<&Path>, prefix: &str) -> Vec<String> {
+    let mut names = vec![];
+
+    for line in etc_hosts.lines() {
+        let line = line.split('#').next().unwrap_or_default();
+        names.extend(line.split_whitespace().skip(1).map(String::from));
+    }
+
+    if let Some(ssh) = ssh {
+        for line in fs::read_to_string(ssh.join("config")).unwrap_or_default().lines() {
+            let mut fields = line.split(|c: char| c.is_whitespace() || c == '=').filter(|field| !field.is_empty());
+            if fields.next().is_some_and(|keyword| keyword.eq_ignore_ascii_case("host")) {
+                names.extend(fields.filter(|name| !name.contains(['*', '?', '!'])).map(String::from));
+            }
+        }
+
+        for line in fs::read_to_string(ssh.join("known_hosts")).unwrap_or_default().lines() {
+            let mut fields = line.split_whitespace();
+            let Some(mut field) = fields.next() else { continue };
+            if field.starts_with('@') {
+                // A marker such as `@cert-authority` comes first.
+                let Some(next) = fields.next() else { continue };
+                field = next;
+            }
+            if field.starts_with(['#', '|']) {
+                continue;
+            }
+            for host in field.split(',') {
+                let host = match host.strip_prefix('[') {
+                    Some(bracketed) => bracketed.split(']').next().unwrap_or_default(),
+                    None => host,
+                };
+                if !host.contains(['*', '?']) {
+                    names.push(host.to_string());
+                }
+            }
+        }
+    }
+
+    names.retain(|name| !name.is_empty() && name.starts_with(prefix));
+    names.sort();
+    names.dedup();
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn word_after_a_path_argument_is_empty() {
+        let word = Word::at("cat /tmp/foo ", 13, &HashMap::new());
+        assert_eq!(word.value, "");
+        assert_eq!(word.start, 13);
+        assert_eq!(word.previous, ["cat", "/tmp/foo"]);
+        assert_eq!(word.split_path(), ("", ""));
+        assert_eq!(word.blank().value, "");
+    }
+
+    #[test]
+    fn word_splits_at_the_last_slash() {
+        let word = Word::at("ls '/tmp/fo", 11, &HashMap::new());
+        assert_eq!(word.value, "/tmp/fo");
+        assert_eq!(word.split_path(), ("/tmp/", "fo"));
+        assert_eq!(word.quote, Some('\''));
+        assert_eq!(word.blank().value, "/tmp/");
+    }
+
+    fn strings(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn spec_options() {
+        let (spec, flags, names) = CompletionSpec::parse("complete", &strings(&["-o", "nospace", "-df", "-Wa b", "-F", "_f", "-p", "git", "-x"]), "pr").unwrap();
+        assert!(spec.directories && spec.files && spec.option("nospace") && spec.filenames());
+        assert_eq!(spec.words.as_deref(), Some("a b"));
+        assert_eq!(spec.function.as_deref(), Some("_f"));
+        assert_eq!((flags, names), (vec!['p'], strings(&["git", "-x"])));
+        assert_eq!(spec.to_command("git"), "complete -o nospace -d -f -W 'a b' -F _f git");
+
+        let (spec, _, words) = CompletionSpec::parse("compgen", &strings(&["-C", "ls -a", "--", "-v"]), "").unwrap();
+        assert_eq!(spec.command.as_deref(), Some("ls -a"));
+        assert_eq!(words, ["-v"]);
+    }
+
+    #[test]
+    fn spec_errors() {
+        let message = |arguments: &[&str]| match CompletionSpec::parse("complete", &strings(arguments), "pr") {
+            Err(ShellError::Usage(message) | ShellError::InvalidArgument(message)) => message,
+            _ => panic!("{:?} should fail", arguments),
+        };
+        assert_eq!(message(&["-W"]), "complete: -W: option requires an argument");
+        assert_eq!(message(&["-z", "git"]), "complete: -z: invalid option");
+        assert_eq!(message(&["-o", "bogus", "git"]), "complete: bogus: invalid option name");
+    }
+
+    #[test]
+    fn word_lists_are_expanded_and_filtered() {
+        let mut shell = Shell::new();
+        shell.set_var("more", "stash status");
+        let spec = CompletionSpec { words: Some("commit checkout $more".to_string()), ..Default::default() };
+        assert_eq!(generate(&spec, &Word::literal("st"), "", 0, "/", &mut shell), ["stash", "status"]);
+        assert_eq!(generate(&spec, &Word::literal("c"), "", 0, "/", &mut shell), ["checkout", "commit"]);
+        assert!(generate(&spec, &Word::literal("x"), "", 0, "/", &mut shell).is_empty());
+    }
+
+    #[test]
+    fn functions_see_the_line_being_completed() {
+        let mut shell = Shell::new();
+        let source = r#"_f() { COMPREPLY=("cword=$COMP_CWORD" "words=${COMP_WORDS[*]}" "args=$1,$2,$3" "line=$COMP_LINE:$COMP_POINT"); }"#;
+        crate::executor::execute_list(&crate::parser::parse(source, &HashMap::new()).unwrap(), &mut shell);
+
+        let line = "git commit --am";
+        let spec = CompletionSpec { function: Some("_f".to_string()), ..Default::default() };
+        let word = Word::at(line, line.len(), &HashMap::new());
+        assert_eq!(
+            generate(&spec, &word, line, line.len(), "/", &mut shell),
+            ["args=git,--am,commit", "cword=2", "line=git commit --am:15", "words=git commit --am"]
+        );
+    }
+
+    #[test]
+    fn commands_print_the_candidates() {
+        // They get the command, the word and the word before it.
+        let mut shell = Shell::new();
+        shell.set_var("PATH", "/usr/bin:/bin");
+        let spec = CompletionSpec { command: Some("printf '%s\\n' b a".to_string()), ..Default::default() };
+        let line = "tool x";
+        let word = Word::at(line, line.len(), &HashMap::new());
+        assert_eq!(generate(&spec, &word, line, line.len(), "/", &mut shell), ["a", "b", "tool", "x"]);
+    }
+
+    #[test]
+    fn users_come_from_passwd() {
+        let passwd = "root:x:0:0::/root:/bin/sh\n# comment\nuser1:x:1000:1000::/home/user1:/bin/sh\nuser2:x:1001:1001::/home/user2:/bin/sh\n";
+        assert_eq!(users_in(passwd, "us"), ["user1", "user2"]);
+        assert_eq!(users_in(passwd, ""), ["root", "user1", "user2"]);
+        assert!(users_in(passwd, "nobody").is_empty());
+    }
+
+    #[test]
+    fn hosts_come_from_hosts_files_and_ssh() {
+        let ssh = std::env::temp_dir().join(format!("rshell-ssh-{}", std::process::id()));
+        fs::create_dir_all(&ssh).unwrap();
+        fs::write(ssh.join("config"), "Host build box-*\n  HostName 10.0.0.9\nHost=bastion\n").unwrap();
+        fs::write(ssh.join("known_hosts"), [
+            "home.lan,192.168.1.2 ssh-ed25519 AAAA",
+            "[home-nas]:2222 ssh-ed25519 AAAA",
+            "|1|F1E1KeoE/eEWhi10WpGv4OdiO6Y=|3988QV0VE8wmZL7suNrYQLITLCg= ssh-rsa AAAA",
+            "@cert-authority *.lan ssh-rsa AAAA",
+            "# home.old ssh-rsa AAAA",
+        ].join("\n")).unwrap();
+        let etc_hosts = "127.0.0.1 localhost\n192.168.1.3 homeserver home # the server\n# 10.0.0.1 hidden\n";
+
+        assert_eq!(hosts_in(etc_hosts, Some(&ssh), "ho"), ["home", "home-nas", "home.lan", "homeserver"]);
+        assert_eq!(hosts_in(etc_hosts, Some(&ssh), "b"), ["bastion", "build"]);
+        assert_eq!(hosts_in(etc_hosts, None, ""), ["home", "homeserver", "localhost"]);
+        assert_eq!(hosts_in("", Some(&ssh), "1"), ["192.168.1.2"]);
+        let _ = fs::remove_dir_all(&ssh);
+    }
+
+    fn fall_back(typed: &str, matchings: &[Matching]) -> Vec<&'static str> {
+        let candidates = vec!["Documents/", "downloads/", "docker-compose.yml", "xxtest", "test", "atest"];
+        fallback(candidates, typed, matchings, |name| name)
+    }
+
+    #[test]
+    fn fallbacks_apply_in_order() {
+        let all = Matching::from_setting(None);
+        assert_eq!(fall_back("docum", &all), ["Documents/"]);
+        assert_eq!(fall_back("load", &all), ["downloads/"]);
+        assert_eq!(fall_back("dcy", &all), ["docker-compose.yml"]);
+        assert_eq!(fall_back("zzz", &all), Vec::<&str>::new());
+        assert_eq!(fall_back("", &all), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn fallbacks_rank_best_first() {
+        assert_eq!(fall_back("est", &[Matching::Substring]), ["test", "atest", "xxtest"]);
+        assert_eq!(fall_back("tst", &[Matching::Fuzzy]), ["test", "atest", "xxtest"]);
+    }
+
+    #[test]
+    fn fallbacks_are_configurable() {
+        assert_eq!(fall_back("docum", &Matching::from_setting(Some("substring"))), ["Documents/"]);
+        assert_eq!(fall_back("DOCUM", &Matching::from_setting(Some(""))), Vec::<&str>::new());
+        assert_eq!(fall_back("dcy", &Matching::from_setting(Some("ignore-case:substring"))), Vec::<&str>::new());
+        assert_eq!(Matching::from_setting(Some("fuzzy:unknown")).len(), 1);
+    }
+}
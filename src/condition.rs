@@ -0,0 +1,322 @@
+use std::fs;
+use std::ffi::CString;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+use regex::Regex;
+
+use crate::arithmetic;
+use crate::expansion::{expand_pattern, expand_regex, expand_word, glob_match};
+use crate::parser::CondExpr;
+use crate::sherror::ShellError;
+use crate::shell::Shell;
+
+pub const UNARY_OPERATORS: [&str; 24] = [
+    "-a", "-b", "-c", "-d", "-e", "-f", "-g", "-h", "-k", "-p", "-r", "-s",
+    "-t", "-u", "-w", "-x", "-G", "-L", "-N", "-O", "-S", "-z", "-n", "-v"
+];
+
+pub const BINARY_OPERATORS: [&str; 15] = [
+    "=", "==", "!=", "<", ">", "-eq", "-ne", "-lt", "-le", "-gt", "-ge", "-nt", "-ot", "-ef", "=~"
+];
+
+/// Evaluates the arguments of the `test` and `[` builtins.
+pub fn test(arguments: &[String], shell: &Shell) -> Result<bool, ShellError> {
+    let args: Vec<&str> = arguments.iter().map(|s| s.as_str()).collect();
+
+    // POSIX defines the meaning of up to four arguments by their count.
+    match args.as_slice() {
+        [] => Ok(false),
+        [word] => Ok(!word.is_empty()),
+        ["!", word] => Ok(word.is_empty()),
+        [op, operand] if UNARY_OPERATORS.contains(op) => unary_test(op, operand, shell),
+        [op, _] => Err(ShellError::Usage(format!("test: {}: unary operator expected", op))),
+        [left, op, right] if is_test_binary(op) => binary_test(op, left, right),
+        ["!", rest @ ..] if rest.len() <= 3 => test(&arguments[1..], shell).map(|result| !result),
+        ["(", inner @ .., ")"] if inner.len() <= 2 => test(&arguments[1..arguments.len() - 1], shell),
+        _ => {
+            let mut parser = TestParser { args: &args, pos: 0, shell };
+            let result = parser.or()?;
+            match parser.args.get(parser.pos) {
+                None => Ok(result),
+                Some(extra) => Err(ShellError::Usage(format!("test: {}: unexpected argument", extra))),
+            }
+        },
+    }
+}
+
+fn is_test_binary(op: &str) -> bool {
+    (BINARY_OPERATORS.contains(&op) && op != "=~") || op == "-a" || op == "-o"
+}
+
+struct TestParser<'a> {
+    args: &'a [&'a str],
+    pos: usize,
+    shell: &'a Shell
+}
+
+impl<'a> TestParser<'a> {
+    fn next(&mut self) -> Result<&'a str, ShellError> {
+        let arg = *self.args.get(self.pos).ok_or_else(|| ShellError::Usage("test: argument expected".to_string()))?;
+        self.pos += 1;
+        Ok(arg)
+    }
+
+    fn or(&mut self) -> Result<bool, ShellError> {
+        let mut result = self.and()?;
+        while self.args.get(self.pos) == Some(&"-o") {
+            self.pos += 1;
+            result |= self.and()?;
+        }
+        Ok(result)
+    }
+
+    fn and(&mut self) -> Result<bool, ShellError> {
+        let mut result = self.not()?;
+        while self.args.get(self.pos) == Some(&"-a") {
+            self.pos += 1;
+            result &= self.not()?;
+        }
+        Ok(result)
+    }
+
+    fn not(&mut self) -> Result<bool, ShellError> {
+        if self.args.get(self.pos) == Some(&"!") {
+            self.pos += 1;
+            return self.not().map(|result| !result);
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<bool, ShellError> {
+        let first = self.next()?;
+
+        if first == "(" {
+            let result = self.or()?;
+            return match self.next()? {
+                ")" => Ok(result),
+                other => Err(ShellError::Usage(format!("test: `)' expected, found {}", other))),
+            };
+        }
+
+        if let Some(op) = self.args.get(self.pos).filter(|op| BINARY_OPERATORS.contains(op) && **op != "=~") {
+            if self.pos + 1 < self.args.len() {
+                let right = self.args[self.pos + 1];
+                self.pos += 2;
+                return binary_test(op, first, right);
+            }
+        }
+
+        if UNARY_OPERATORS.contains(&first) && self.pos < self.args.len() {
+            let operand = self.next()?;
+            return unary_test(first, operand, self.shell);
+        }
+
+        Ok(!first.is_empty())
+    }
+}
+
+pub fn unary_test(op: &str, operand: &str, shell: &Shell) -> Result<bool, ShellError> {
+    let metadata = fs::metadata(operand);
+
+    Ok(match op {
+        "-z" => operand.is_empty(),
+        "-n" => !operand.is_empty(),
+        "-v" => shell.get_var(operand).is_some(),
+        "-t" => {
+            let fd = operand.parse::<i32>()
+                .map_err(|_| ShellError::Usage(format!("test: {}: integer expression expected", operand)))?;
+            unsafe { libc::isatty(fd) == 1 }
+        },
+        "-a" | "-e" => metadata.is_ok(),
+        "-f" => metadata.is_ok_and(|m| m.is_file()),
+        "-d" => metadata.is_ok_and(|m| m.is_dir()),
+        "-s" => metadata.is_ok_and(|m| m.len() > 0),
+        "-b" => metadata.is_ok_and(|m| m.file_type().is_block_device()),
+        "-c" => metadata.is_ok_and(|m| m.file_type().is_char_device()),
+        "-p" => metadata.is_ok_and(|m| m.file_type().is_fifo()),
+        "-S" => metadata.is_ok_and(|m| m.file_type().is_socket()),
+        "-g" => metadata.is_ok_and(|m| m.mode() & 0o2000 != 0),
+        "-u" => metadata.is_ok_and(|m| m.mode() & 0o4000 != 0),
+        "-k" => metadata.is_ok_and(|m| m.mode() & 0o1000 != 0),
+        "-O" => metadata.is_ok_and(|m| m.uid() == unsafe { libc::geteuid() }),
+        "-G" => metadata.is_ok_and(|m| m.gid() == unsafe { libc::getegid() }),
+        "-N" => metadata.is_ok_and(|m| m.mtime() > m.atime()),
+        "-h" | "-L" => fs::symlink_metadata(operand).is_ok_and(|m| m.file_type().is_symlink()),
+        "-r" => access(operand, libc::R_OK),
+        "-w" => access(operand, libc::W_OK),
+        "-x" => access(operand, libc::X_OK),
+        _ => return Err(ShellError::Usage(format!("test: {}: unary operator expected", op))),
+    })
+}
+
+fn access(path: &str, mode: i32) -> bool {
+    match CString::new(path) {
+        Ok(path) => unsafe { libc::access(path.as_ptr(), mode) == 0 },
+        Err(_) => false,
+    }
+}
+
+fn integer(operand: &str) -> Result<i64, ShellError> {
+    operand.trim().parse()
+        .map_err(|_| ShellError::Usage(format!("test: {}: integer expression expected", operand)))
+}
+
+pub fn binary_test(op: &str, left: &str, right: &str) -> Result<bool, ShellError> {
+    Ok(match op {
+        "=" | "==" => left == right,
+        "!=" => left != right,
+        "<" => left < right,
+        ">" => left > right,
+        "-a" => !left.is_empty() && !right.is_empty(),
+        "-o" => !left.is_empty() || !right.is_empty(),
+        "-eq" => integer(left)? == integer(right)?,
+        "-ne" => integer(left)? != integer(right)?,
+        "-lt" => integer(left)? < integer(right)?,
+        "-le" => integer(left)? <= integer(right)?,
+        "-gt" => integer(left)? > integer(right)?,
+        "-ge" => integer(left)? >= integer(right)?,
+        "-nt" | "-ot" => {
+            let modified = |path: &str| fs::metadata(path).and_then(|m| m.modified()).ok();
+            match (modified(left), modified(right)) {
+                (Some(l), Some(r)) => if op == "-nt" { l > r } else { l < r },
+                (Some(_), None) => op == "-nt",
+                (None, Some(_)) => op == "-ot",
+                (None, None) => false,
+            }
+        },
+        "-ef" => match (fs::metadata(left), fs::metadata(right)) {
+            (Ok(l), Ok(r)) => l.dev() == r.dev() && l.ino() == r.ino(),
+            _ => false,
+        },
+        _ => return Err(ShellError::Usage(format!("test: {}: binary operator expected", op))),
+    })
+}
+
+/// Evaluates a `[[ ]]` expression. Words are expanded without field
+/// splitting or globbing, `==` and `!=` match patterns and `=~` matches
+/// extended regular expressions, filling `BASH_REMATCH`.
+pub fn conditional(expression: &CondExpr, shell: &mut Shell) -> Result<bool, ShellError> {
+    match expression {
+        CondExpr::And(left, right) => Ok(conditional(left, shell)? && conditional(right, shell)?),
+        CondExpr::Or(left, right) => Ok(conditional(left, shell)? || conditional(right, shell)?),
+        CondExpr::Not(inner) => conditional(inner, shell).map(|result| !result),
+        CondExpr::Word(word) => Ok(!expand_word(word, shell)?.is_empty()),
+        CondExpr::Unary(op, operand) => {
+            let operand = expand_word(operand, shell)?;
+            unary_test(op, &operand, shell)
+        },
+        CondExpr::Binary(op, left, right) => {
+            let left = expand_word(left, shell)?;
+
+            match op.as_str() {
+                "=" | "==" | "!=" => {
+                    let pattern = expand_pattern(right, shell)?;
+                    Ok(glob_match(&pattern, &left) == (op != "!="))
+                },
+                "=~" => {
+                    let pattern = expand_regex(right, shell)?;
+                    let regex = Regex::new(&pattern)
+                        .map_err(|_| ShellError::Usage(format!("[[: {}: invalid regular expression", pattern)))?;
+
+                    let groups: Vec<String> = match regex.captures(&left) {
+                        Some(captures) => captures
+                            .iter()
+                            .map(|group| group.map(|m| m.as_str().to_string()).unwrap_or_default())
+                            .collect(),
+                        None => vec![],
+                    };
+                    let matched = !groups.is_empty();
+                    shell.arrays.insert("BASH_REMATCH".to_string(), groups);
+                    Ok(matched)
+                },
+                "-eq" | "-ne" | "-lt" | "-le" | "-gt" | "-ge" => {
+                    // Operands of integer comparisons are arithmetic expressions.
+                    let right = expand_word(right, shell)?;
+                    let left = arithmetic::evaluate(&left, shell)?;
+                    let right = arithmetic::evaluate(&right, shell)?;
+                    binary_test(op, &left.to_string(), &right.to_string())
+                },
+                _ => {
+                    let right = expand_word(right, shell)?;
+                    binary_test(op, &left, &right)
+                },
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::parser::{self, Command, Compound};
+
+    fn cond(source: &str, shell: &mut Shell) -> Result<bool, ShellError> {
+        let list = parser::parse(source, &HashMap::new()).unwrap();
+        match &list[0].first.commands[0] {
+            Command::Compound(Compound::Conditional(expression), _) => conditional(expression, shell),
+            command => panic!("not a conditional: {:?}", command),
+        }
+    }
+
+    fn holds(source: &str) -> bool {
+        let mut shell = Shell::new();
+        shell.set_var("v", "hello world");
+        cond(source, &mut shell).unwrap()
+    }
+
+    #[test]
+    fn string_operators() {
+        assert!(holds("[[ abc == a* ]]"));
+        assert!(!holds("[[ abc == \"a*\" ]]"));
+        assert!(holds("[[ abc != b* ]]"));
+        assert!(holds("[[ $v == hello\\ * ]]"));
+        assert!(holds("[[ b > a ]]"));
+        assert!(holds("[[ a < b ]]"));
+        assert!(holds("[[ -z '' && -n x ]]"));
+        assert!(holds("[[ $v ]]"));
+        assert!(!holds("[[ $unset_variable ]]"));
+    }
+
+    #[test]
+    fn integer_operators_take_arithmetic() {
+        assert!(holds("[[ 2 -lt 10 ]]"));
+        assert!(!holds("[[ 10 -lt 2 ]]"));
+        assert!(holds("[[ 1+1 -eq 2 ]]"));
+        assert!(holds("[[ 3 -ge 3 && 3 -le 3 && 3 -ne 4 ]]"));
+    }
+
+    #[test]
+    fn logical_operators() {
+        assert!(holds("[[ a == b || 1 -eq 1 ]]"));
+        assert!(!holds("[[ a == b && 1 -eq 1 ]]"));
+        assert!(holds("[[ ! a == b ]]"));
+        assert!(holds("[[ ( a == b || a == a ) && -n x ]]"));
+    }
+
+    #[test]
+    fn regex_matches_fill_bash_rematch() {
+        let mut shell = Shell::new();
+        assert!(cond("[[ key=value =~ ^([a-z]+)=(.*)$ ]]", &mut shell).unwrap());
+        assert_eq!(shell.arrays["BASH_REMATCH"], ["key=value", "key", "value"]);
+        assert!(!cond("[[ abc =~ ^b ]]", &mut shell).unwrap());
+        assert!(cond("[[ abc =~ a{2,1} ]]", &mut shell).is_err());
+    }
+
+    #[test]
+    fn file_operators() {
+        assert!(holds("[[ -d / && -e / && ! -f / ]]"));
+        assert!(holds("[[ ! -e /nonexistent/file ]]"));
+    }
+
+    #[test]
+    fn test_builtin() {
+        let shell = Shell::new();
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        assert!(test(&args(&["abc", "=", "abc"]), &shell).unwrap());
+        assert!(!test(&args(&["!", "x"]), &shell).unwrap());
+        assert!(test(&args(&["1", "-lt", "2", "-a", "(", "x", "!=", "y", ")"]), &shell).unwrap());
+        assert!(test(&args(&["-q", "x"]), &shell).is_err());
+    }
+}
@@ -0,0 +1,908 @@
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::os::fd::{FromRawFd, IntoRawFd};
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::condition::conditional;
+use crate::expansion::{expand_arithmetic, expand_array, expand_pattern, expand_text, expand_word, expand_words, glob_match, quote_word};
+use crate::handle_input;
+use crate::instruction::Output;
+use crate::lexer::is_name;
+use crate::parser::{self, AndOr, CaseTerminator, Command, Compound, Connector, Function, List, Pipeline, SimpleCommand};
+use crate::prompt::expand_prompt;
+use crate::redirection::{RedirOp, RedirType, Redirection};
+use crate::sherror::{get_error_message, ShellError};
+use crate::shell::{Flow, Shell};
+
+const DECLARATION_BUILTINS: [&str; 2] = ["local", "export"];
+const MAX_FUNCTION_DEPTH: usize = 200;
+
+pub fn execute_list(list: &List, shell: &mut Shell) -> i32 {
+    let mut status = 0;
+
+    for and_or in list {
+        status = execute_and_or(and_or, shell);
+        if shell.flow != Flow::Normal {
+            break;
+        }
+    }
+
+    status
+}
+
+fn execute_and_or(and_or: &AndOr, shell: &mut Shell) -> i32 {
+    let mut status = execute_pipeline(&and_or.first, shell);
+
+    for (connector, pipeline) in &and_or.rest {
+        if shell.flow != Flow::Normal {
+            break;
+        }
+        if (*connector == Connector::And) == (status == 0) {
+            status = execute_pipeline(pipeline, shell);
+        }
+    }
+
+    status
+}
+
+fn execute_pipeline(pipeline: &Pipeline, shell: &mut Shell) -> i32 {
+    let mut status = match pipeline.commands.as_slice() {
+        [command] => execute_command(command, shell),
+        commands => run_pipeline(commands, shell),
+    };
+
+    if pipeline.negated {
+        status = (status == 0) as i32;
+    }
+
+    shell.last_status = status;
+    status
+}
+
+/// Runs each command of a pipeline in its own subshell, connecting the
+/// standard output of each one to the standard input of the next.
+fn run_pipeline(commands: &[Command], shell: &mut Shell) -> i32 {
+    let mut children = vec![];
+    let mut input: Option<i32> = None;
+
+    for (i, command) in commands.iter().enumerate() {
+        let (read, write) = if i + 1 < commands.len() {
+            match pipe() {
+                Ok((read, write)) => (Some(read), Some(write)),
+                Err(err) => {
+                    report_at(&err, shell);
+                    break;
+                },
+            }
+        } else {
+            (None, None)
+        };
+
+        let child = fork_subshell(shell, |shell| {
+            unsafe {
+                if let Some(fd) = input {
+                    libc::dup2(fd, 0);
+                    libc::close(fd);
+                }
+                if let Some(fd) = write {
+                    libc::dup2(fd, 1);
+                    libc::close(fd);
+                }
+                if let Some(fd) = read {
+                    libc::close(fd);
+                }
+            }
+            execute_command(command, shell)
+        });
+
+        unsafe {
+            if let Some(fd) = input {
+                libc::close(fd);
+            }
+            if let Some(fd) = write {
+                libc::close(fd);
+            }
+        }
+        input = read;
+
+        match child {
+            Ok(pid) => children.push(pid),
+            Err(err) => { report_at(&err, shell); },
+        }
+    }
+
+    if let Some(fd) = input {
+        unsafe { libc::close(fd) };
+    }
+
+    children.into_iter().map(wait_for).next_back().unwrap_or(1)
+}
+
+/// Forks a subshell: `run` executes in a copy of the shell whose variables,
+/// functions and working directory are isolated from the parent, and its
+/// result becomes the exit status of the child. Returns the child's pid.
+pub fn fork_subshell<F: FnOnce(&mut Shell) -> i32>(shell: &mut Shell, run: F) -> Result<libc::pid_t, ShellError> {
+    flush_std();
+
+    match unsafe { libc::fork() } {
+        -1 => Err(ShellError::IoError(io::Error::last_os_error())),
+        0 => {
+            shell.flow = Flow::Normal;
+            let status = run(shell);
+            let status = match shell.flow {
+                Flow::Exit(code) => code,
+                _ => status,
+            };
+            flush_std();
+            unsafe { libc::_exit(status) }
+        },
+        pid => Ok(pid),
+    }
+}
+
+/// Waits for a child process and returns its exit status.
+pub fn wait_for(pid: libc::pid_t) -> i32 {
+    let mut status = 0;
+
+    loop {
+        if unsafe { libc::waitpid(pid, &mut status, 0) } >= 0 {
+            break;
+        }
+        if io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+            return 1;
+        }
+    }
+
+    if libc::WIFEXITED(status) {
+        libc::WEXITSTATUS(status)
+    } else if libc::WIFSIGNALED(status) {
+        128 + libc::WTERMSIG(status)
+    } else {
+        1
+    }
+}
+
+fn pipe() -> Result<(i32, i32), ShellError> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+        return Err(ShellError::IoError(io::Error::last_os_error()));
+    }
+    Ok((fds[0], fds[1]))
+}
+
+/// Runs `source` in a subshell and returns what it wrote on its standard
+/// output, without trailing newlines.
+pub fn command_substitution(source: &str, shell: &mut Shell) -> Result<String, ShellError> {
+    let list = parser::parse(source, &shell.aliases).map_err(|err| ShellError::Usage(format!("rshell: {}", err)))?;
+    let (read, write) = pipe()?;
+
+    let child = fork_subshell(shell, |shell| {
+        unsafe {
+            libc::close(read);
+            libc::dup2(write, 1);
+            libc::close(write);
+        }
+        execute_list(&list, shell)
+    });
+    unsafe { libc::close(write) };
+
+    // SAFETY: the read end is owned here and closed when `output` drops.
+    let mut output = unsafe { fs::File::from_raw_fd(read) };
+    let pid = child?;
+
+    let mut bytes = vec![];
+    let _ = output.read_to_end(&mut bytes);
+    let status = wait_for(pid);
+    shell.last_status = status;
+    shell.substitution_status = Some(status);
+
+    let mut text = String::from_utf8_lossy(&bytes).to_string();
+    while text.ends_with('\n') {
+        text.pop();
+    }
+    Ok(text)
+}
+
+fn execute_command(command: &Command, shell: &mut Shell) -> i32 {
+    match command {
+        Command::Simple(simple) => execute_simple(simple, shell),
+        Command::Function(name, function) => {
+            shell.functions.insert(name.clone(), function.clone());
+            0
+        },
+        Command::Compound(compound, redirections) => {
+            let saved = match apply_redirections(redirections, shell) {
+                Ok(saved) => saved,
+                Err(err) => return report_at(&err, shell),
+            };
+            let status = execute_compound(compound, shell);
+            restore_fds(saved);
+            status
+        },
+    }
+}
+
+fn execute_simple(command: &SimpleCommand, shell: &mut Shell) -> i32 {
+    let words = match expand_command_words(&command.words, shell) {
+        Ok(words) => words,
+        Err(err) => return report_at(&err, shell),
+    };
+
+    if words.is_empty() {
+        // Without a command, the status is the one of the last command
+        // substitution, if any.
+        shell.substitution_status = None;
+        for (name, value) in &command.assignments {
+            if let Some(elements) = value.strip_prefix('(').and_then(|value| value.strip_suffix(')')) {
+                match expand_array(elements, shell) {
+                    Ok(values) => {
+                        let quoted: Vec<String> = values.iter().map(|value| quote_word(value)).collect();
+                        trace(&[format!("{}=({})", name, quoted.join(" "))], shell);
+                        shell.environment.remove(name);
+                        shell.arrays.insert(name.clone(), values);
+                    },
+                    Err(err) => return report_at(&err, shell),
+                }
+                continue;
+            }
+            match expand_word(value, shell) {
+                Ok(value) => {
+                    trace(&[format!("{}={}", name, quote_word(&value))], shell);
+                    shell.set_var(name, &value);
+                },
+                Err(err) => return report_at(&err, shell),
+            }
+        }
+
+        return match apply_redirections(&command.redirections, shell) {
+            Ok(saved) => {
+                restore_fds(saved);
+                shell.substitution_status.unwrap_or(0)
+            },
+            Err(err) => report_at(&err, shell),
+        };
+    }
+
+    let saved = match apply_redirections(&command.redirections, shell) {
+        Ok(saved) => saved,
+        Err(err) => return report_at(&err, shell),
+    };
+
+    // Assignments preceding a command only apply to that command.
+    let mut previous = vec![];
+    let mut traced = vec![];
+    for (name, value) in &command.assignments {
+        let value = match expand_word(value, shell) {
+            Ok(value) => value,
+            Err(err) => {
+                restore_fds(saved);
+                return report_at(&err, shell);
+            },
+        };
+        traced.push(format!("{}={}", name, quote_word(&value)));
+        previous.push(shell.save_var(name));
+        shell.set_var(name, &value);
+        shell.exported.insert(name.clone());
+    }
+
+    traced.extend(words.iter().map(|word| quote_word(word)));
+    trace(&traced, shell);
+
+    let status = run_command(&words[0], words[1..].to_vec(), shell);
+
+    for saved_var in previous.into_iter().rev() {
+        shell.restore_var(saved_var);
+    }
+
+    restore_fds(saved);
+    status
+}
+
+/// With `set -x`, prints a command about to run after `PS4`.
+fn trace(words: &[String], shell: &mut Shell) {
+    if !shell.option("xtrace") {
+        return;
+    }
+
+    // Commands run while expanding `PS4` are not traced.
+    let ps4 = shell.get_var("PS4").unwrap_or_default();
+    shell.options.remove("xtrace");
+    let prompt = expand_prompt(&ps4, shell);
+    shell.options.insert("xtrace".to_string());
+
+    flush_std();
+    let _ = writeln!(io::stderr(), "{}{}", prompt.display, words.join(" "));
+}
+
+/// Expands the words of a simple command. Arguments of declaration
+/// builtins such as `local` that look like assignments are not split.
+fn expand_command_words(words: &[String], shell: &mut Shell) -> Result<Vec<String>, ShellError> {
+    if !words.first().is_some_and(|word| DECLARATION_BUILTINS.contains(&word.as_str())) {
+        return expand_words(words, shell);
+    }
+
+    let mut expanded = vec![];
+    for word in words {
+        match word.split_once('=') {
+            Some((name, _)) if is_name(name) => expanded.push(expand_word(word, shell)?),
+            _ => expanded.extend(expand_words(std::slice::from_ref(word), shell)?),
+        }
+    }
+    Ok(expanded)
+}
+
+/// Runs a shell function with `arguments` as its positional parameters.
+pub fn call_function(function: &Function, arguments: Vec<String>, shell: &mut Shell) -> i32 {
+    if shell.locals.len() >= MAX_FUNCTION_DEPTH {
+        return report_at(&ShellError::ExecutionError(format!("rshell: maximum function nesting level exceeded ({})", MAX_FUNCTION_DEPTH)), shell);
+    }
+
+    let positional = mem::replace(&mut shell.positional, arguments);
+    let loop_depth = mem::replace(&mut shell.loop_depth, 0);
+    shell.locals.push(vec![]);
+
+    let mut status = execute_command(&function.body, shell);
+    if let Flow::Return(code) = shell.flow {
+        shell.flow = Flow::Normal;
+        status = code;
+    }
+
+    for saved_var in shell.locals.pop().unwrap_or_default().into_iter().rev() {
+        shell.restore_var(saved_var);
+    }
+    shell.loop_depth = loop_depth;
+    shell.positional = positional;
+
+    status
+}
+
+fn run_command(command: &str, arguments: Vec<String>, shell: &mut Shell) -> i32 {
+    match handle_input(command, arguments, shell) {
+        Ok(output) => print_output(output),
+        Err(err) => report_at(&err, shell),
+    }
+}
+
+fn print_output(output: Output) -> i32 {
+    match output {
+        Output::String(x) => {
+            if !x.is_empty() {
+                println!("{}", x.trim());
+            }
+            0
+        },
+        Output::StdOutErr(stdout, stderr) => {
+            if !stdout.is_empty() {
+                println!("{}", stdout.trim());
+            }
+            if stderr.is_empty() {
+                0
+            } else {
+                eprintln!("{}", stderr.trim());
+                1
+            }
+        },
+    }
+}
+
+/// Prints the message of `err` on stderr and returns the matching status.
+pub fn report(err: &ShellError) -> i32 {
+    let message = match (get_error_message(err), err) {
+        (Some(message), _) => message.trim_end().to_string(),
+        (None, ShellError::IoError(io_err)) => format!("rshell: {}", io_err),
+        _ => return err.status(),
+    };
+    let _ = writeln!(io::stderr(), "{}", message);
+    err.status()
+}
+
+/// Reports `err` like `report`, located at the line of the script being
+/// run, if any.
+fn report_at(err: &ShellError, shell: &Shell) -> i32 {
+    match (&shell.script, get_error_message(err)) {
+        (Some((name, line)), Some(message)) => {
+            let _ = writeln!(io::stderr(), "{}: line {}: {}", name, line, message.trim_start_matches("rshell: ").trim_end());
+            err.status()
+        },
+        _ => report(err),
+    }
+}
+
+fn execute_compound(compound: &Compound, shell: &mut Shell) -> i32 {
+    match compound {
+        Compound::If { branches, otherwise } => {
+            for (condition, body) in branches {
+                let status = execute_list(condition, shell);
+                if shell.flow != Flow::Normal {
+                    return status;
+                }
+                if status == 0 {
+                    return execute_list(body, shell);
+                }
+            }
+
+            match otherwise {
+                Some(body) => execute_list(body, shell),
+                None => 0,
+            }
+        },
+
+        Compound::Loop { condition, body, until } => {
+            let mut status = 0;
+            shell.loop_depth += 1;
+
+            loop {
+                let condition_status = execute_list(condition, shell);
+                if shell.flow != Flow::Normal && loop_should_stop(shell) {
+                    break;
+                }
+                if (condition_status == 0) == *until {
+                    break;
+                }
+
+                status = execute_list(body, shell);
+                if loop_should_stop(shell) {
+                    break;
+                }
+            }
+
+            shell.loop_depth -= 1;
+            status
+        },
+
+        Compound::For { variable, words, body } => {
+            let values = match words {
+                Some(words) => match expand_words(words, shell) {
+                    Ok(values) => values,
+                    Err(err) => return report_at(&err, shell),
+                },
+                None => shell.positional.clone(),
+            };
+
+            let mut status = 0;
+            shell.loop_depth += 1;
+
+            for value in values {
+                shell.set_var(variable, &value);
+                status = execute_list(body, shell);
+                if loop_should_stop(shell) {
+                    break;
+                }
+            }
+
+            shell.loop_depth -= 1;
+            status
+        },
+
+        Compound::ArithFor { init, condition, step, body } => {
+            if let Err(err) = expand_arithmetic(init, shell) {
+                return report_at(&err, shell);
+            }
+
+            let mut status = 0;
+            shell.loop_depth += 1;
+
+            loop {
+                match expand_arithmetic(condition, shell) {
+                    Ok(0) if !condition.is_empty() => break,
+                    Ok(_) => {},
+                    Err(err) => {
+                        status = report_at(&err, shell);
+                        break;
+                    },
+                }
+
+                status = execute_list(body, shell);
+                if loop_should_stop(shell) {
+                    break;
+                }
+
+                if let Err(err) = expand_arithmetic(step, shell) {
+                    status = report_at(&err, shell);
+                    break;
+                }
+            }
+
+            shell.loop_depth -= 1;
+            status
+        },
+
+        Compound::Case { word, arms } => {
+            let subject = match expand_word(word, shell) {
+                Ok(subject) => subject,
+                Err(err) => return report_at(&err, shell),
+            };
+
+            let mut status = 0;
+            let mut fall_through = false;
+
+            for arm in arms {
+                if !fall_through {
+                    let mut matched = false;
+                    for pattern in &arm.patterns {
+                        match expand_pattern(pattern, shell) {
+                            Ok(pattern) if glob_match(&pattern, &subject) => {
+                                matched = true;
+                                break;
+                            },
+                            Ok(_) => {},
+                            Err(err) => return report_at(&err, shell),
+                        }
+                    }
+                    if !matched {
+                        continue;
+                    }
+                }
+
+                status = execute_list(&arm.body, shell);
+                if shell.flow != Flow::Normal {
+                    break;
+                }
+
+                match arm.terminator {
+                    CaseTerminator::Break => break,
+                    CaseTerminator::FallThrough => fall_through = true,
+                    CaseTerminator::Continue => fall_through = false,
+                }
+            }
+
+            status
+        },
+
+        Compound::Group(list) => execute_list(list, shell),
+
+        Compound::Subshell(list) => {
+            match fork_subshell(shell, |shell| execute_list(list, shell)) {
+                Ok(pid) => wait_for(pid),
+                Err(err) => report_at(&err, shell),
+            }
+        },
+
+        Compound::Conditional(expression) => {
+            match conditional(expression, shell) {
+                Ok(result) => !result as i32,
+                Err(err) => report_at(&err, shell).max(2),
+            }
+        },
+
+        Compound::Arithmetic(expression) => {
+            match expand_arithmetic(expression, shell) {
+                Ok(value) => (value == 0) as i32,
+                Err(err) => report_at(&err, shell),
+            }
+        },
+    }
+}
+
+/// Consumes a pending `break` or `continue` aimed at the innermost loop
+/// and returns whether that loop has to stop.
+fn loop_should_stop(shell: &mut Shell) -> bool {
+    match shell.flow {
+        Flow::Normal => false,
+        Flow::Break(levels) => {
+            shell.flow = if levels > 1 { Flow::Break(levels - 1) } else { Flow::Normal };
+            true
+        },
+        Flow::Continue(levels) => {
+            if levels > 1 {
+                shell.flow = Flow::Continue(levels - 1);
+                true
+            } else {
+                shell.flow = Flow::Normal;
+                false
+            }
+        },
+        Flow::Return(_) | Flow::Exit(_) => true,
+    }
+}
+
+/// File descriptors replaced by redirections, with the copies needed to
+/// restore them.
+pub struct SavedFds(Vec<(i32, i32)>);
+
+pub fn apply_redirections(redirections: &[Redirection], shell: &mut Shell) -> Result<SavedFds, ShellError> {
+    let mut saved = SavedFds(vec![]);
+    flush_std();
+
+    for redirection in redirections {
+        if let Err(err) = apply_redirection(redirection, shell, &mut saved) {
+            restore_fds(saved);
+            return Err(err);
+        }
+    }
+
+    Ok(saved)
+}
+
+fn apply_redirection(redirection: &Redirection, shell: &mut Shell, saved: &mut SavedFds) -> Result<(), ShellError> {
+    let target = match redirection.r_type {
+        RedirType::HereDoc { expand: true } => expand_text(&redirection.path, shell)?,
+        RedirType::HereDoc { expand: false } => redirection.path.clone(),
+        _ => expand_word(&redirection.path, shell)?,
+    };
+
+    let (fds, source, opened) = match &redirection.r_type {
+        RedirType::Duplicate(fd) => match target.parse::<i32>() {
+            Ok(source) => (vec![*fd], source, false),
+            Err(_) => return Err(ShellError::InvalidArgument(format!("rshell: {}: ambiguous redirect", target))),
+        },
+        RedirType::Stdin => {
+            let file = fs::File::open(&target).map_err(|err| file_error(&target, err))?;
+            (vec![0], file.into_raw_fd(), true)
+        },
+        RedirType::HereDoc { .. } => (vec![0], here_document(&target)?, true),
+        r_type => {
+            let path = Path::new(&target);
+            if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+                let _ = fs::create_dir_all(parent);
+            }
+
+            let operation = r_type.clone().get_redir_op();
+            let file = OpenOptions::new()
+                .create(true)
+                .write(true)
+                .append(operation == Some(RedirOp::Append))
+                .truncate(operation == Some(RedirOp::Write))
+                .open(path)
+                .map_err(|err| file_error(&target, err))?;
+
+            let fds = match r_type {
+                RedirType::Stderr(_) => vec![2],
+                RedirType::Both(_) => vec![1, 2],
+                _ => vec![1],
+            };
+            (fds, file.into_raw_fd(), true)
+        },
+    };
+
+    for fd in fds {
+        if !saved.0.iter().any(|(saved_fd, _)| *saved_fd == fd) {
+            // SAFETY: duplicating a descriptor has no memory safety implications.
+            let copy = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 10) };
+            saved.0.push((fd, copy));
+        }
+
+        // SAFETY: see above.
+        if unsafe { libc::dup2(source, fd) } < 0 {
+            let err = io::Error::last_os_error();
+            if opened {
+                unsafe { libc::close(source) };
+            }
+            return Err(ShellError::InvalidArgument(format!("rshell: {}: {}", source, err)));
+        }
+    }
+
+    if opened {
+        unsafe { libc::close(source) };
+    }
+
+    Ok(())
+}
+
+/// Opens a descriptor reading `text`, from an unlinked temporary file so
+/// that a large body cannot fill a pipe nobody reads yet.
+fn here_document(text: &str) -> Result<i32, ShellError> {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    let path = env::temp_dir().join(format!("rshell-{}-{}", process::id(), COUNT.fetch_add(1, Ordering::Relaxed)));
+    let name = path.display().to_string();
+
+    let mut file = OpenOptions::new()
+        .create_new(true)
+        .read(true)
+        .write(true)
+        .open(&path)
+        .map_err(|err| file_error(&name, err))?;
+    let _ = fs::remove_file(&path);
+
+    file.write_all(text.as_bytes())
+        .and_then(|_| file.seek(SeekFrom::Start(0)))
+        .map_err(|err| file_error(&name, err))?;
+    Ok(file.into_raw_fd())
+}
+
+pub fn restore_fds(saved: SavedFds) {
+    flush_std();
+
+    for (fd, copy) in saved.0.into_iter().rev() {
+        unsafe {
+            if copy < 0 {
+                libc::close(fd);
+            } else {
+                libc::dup2(copy, fd);
+                libc::close(copy);
+            }
+        }
+    }
+}
+
+fn flush_std() {
+    let _ = io::stdout().flush();
+    let _ = io::stderr().flush();
+}
+
+pub fn file_error(path: &str, err: io::Error) -> ShellError {
+    let reason = match err.kind() {
+        io::ErrorKind::NotFound => "No such file or directory".to_string(),
+        io::ErrorKind::PermissionDenied => "Permission denied".to_string(),
+        _ => err.to_string(),
+    };
+    ShellError::FileNotFound(format!("rshell: {}: {}", path, reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::os::fd::AsRawFd;
+    use std::sync::Mutex;
+
+    use super::*;
+
+    /// Held while a test sends the process's stderr to a file.
+    static STDERR: Mutex<()> = Mutex::new(());
+
+    /// Runs `run` in a fresh shell without PATH, so that only builtins
+    /// and assignments run. Returns the status, the shell and what was
+    /// printed on stderr.
+    fn capture<F: FnOnce(&mut Shell) -> i32>(run: F) -> (i32, Shell, String) {
+        let _lock = STDERR.lock().unwrap_or_else(|err| err.into_inner());
+        let path = std::env::temp_dir().join(format!("rshell-stderr-{}", std::process::id()));
+        let file = fs::File::create(&path).unwrap();
+
+        let mut shell = Shell::new();
+        shell.set_var("PATH", "");
+        let saved = unsafe { libc::dup(2) };
+        unsafe { libc::dup2(file.as_raw_fd(), 2) };
+        let status = run(&mut shell);
+        unsafe {
+            libc::dup2(saved, 2);
+            libc::close(saved);
+        }
+
+        let errors = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        (status, shell, errors)
+    }
+
+    fn run(source: &str) -> (i32, Shell, String) {
+        let list = crate::parser::parse(source, &HashMap::new()).unwrap();
+        capture(|shell| execute_list(&list, shell))
+    }
+
+    fn var(source: &str, name: &str) -> Option<String> {
+        run(source).1.get_var(name)
+    }
+
+    #[test]
+    fn and_or_lists_follow_the_status() {
+        assert_eq!(var("false && a=1 || b=2", "b").as_deref(), Some("2"));
+        assert_eq!(var("false && a=1 || b=2", "a"), None);
+        assert_eq!(var("true || a=1; b=$?", "b").as_deref(), Some("0"));
+        assert_eq!(run("! true").0, 1);
+        assert_eq!(run("true; false").0, 1);
+    }
+
+    #[test]
+    fn compound_commands() {
+        assert_eq!(var("for i in 1 2 3; do s=$s$i; done", "s").as_deref(), Some("123"));
+        assert_eq!(var("i=0; while (( i < 5 )); do i=$((i + 1)); done", "i").as_deref(), Some("5"));
+        assert_eq!(var("if false; then r=a; elif true; then r=b; else r=c; fi", "r").as_deref(), Some("b"));
+        assert_eq!(var("case foo.rs in *.txt) r=text;; *.rs) r=rust;; esac", "r").as_deref(), Some("rust"));
+
+        let (status, shell, errors) = run("for ((i = 0; i < 3; i++)); do :; done");
+        assert_eq!((status, errors.as_str()), (0, ""));
+        assert_eq!(shell.get_var("i").as_deref(), Some("3"));
+        assert_eq!(run("while :; do break; done").0, 0);
+    }
+
+    #[test]
+    fn functions_and_locals() {
+        let (_, shell, _) = run("x=global; f() { local x=local; y=$x; return 3; }; f; s=$?");
+        assert_eq!(shell.get_var("x").as_deref(), Some("global"));
+        assert_eq!(shell.get_var("y").as_deref(), Some("local"));
+        assert_eq!(shell.get_var("s").as_deref(), Some("3"));
+
+        // Locals are visible to the functions called, not after the call.
+        let (_, shell, _) = run("v=outer; f() { local v=inner; g; }; g() { seen=$v; }; f");
+        assert_eq!(shell.get_var("seen").as_deref(), Some("inner"));
+        assert_eq!(shell.get_var("v").as_deref(), Some("outer"));
+        assert_eq!(var("function f { local u; u=set; }; f", "u"), None);
+    }
+
+    #[test]
+    fn return_leaves_the_function() {
+        assert_eq!(var("f() { r=1; return; r=2; }; f", "r").as_deref(), Some("1"));
+        assert_eq!(run("f() { false; return; }; f").0, 1);
+        assert_eq!(var("f() { for i in 1 2 3; do return $i; done; }; f; s=$?", "s").as_deref(), Some("1"));
+
+        let (status, _, errors) = run("return 2");
+        assert_eq!(status, 1);
+        assert_eq!(errors, "return: can only `return' from a function or sourced script\n");
+        assert_eq!(run("f() { return x; }; f").2, "return: x: numeric argument required\n");
+    }
+
+    #[test]
+    fn positional_parameters() {
+        let (_, shell, _) = run("f() { n=$#; first=$1; tenth=${10}; all=\"$*\"; shift 8; rest=\"$@\"; }; f a b c d e f g h i j");
+        assert_eq!(shell.get_var("n").as_deref(), Some("10"));
+        assert_eq!(shell.get_var("first").as_deref(), Some("a"));
+        assert_eq!(shell.get_var("tenth").as_deref(), Some("j"));
+        assert_eq!(shell.get_var("all").as_deref(), Some("a b c d e f g h i j"));
+        assert_eq!(shell.get_var("rest").as_deref(), Some("i j"));
+
+        assert_eq!(var("count() { n=$#; }; f() { count \"$@\"; }; f 'a b' c", "n").as_deref(), Some("2"));
+        assert_eq!(var("f() { for arg; do s=$s$arg; done; }; f x y", "s").as_deref(), Some("xy"));
+        assert_eq!(var("f() { :; }; g() { f 1 2; second=$2; }; g a b", "second").as_deref(), Some("b"));
+
+        let (status, _, errors) = run("f() { shift 2; }; f a");
+        assert_eq!(status, 1);
+        assert_eq!(errors, "shift: shift count out of range\n");
+    }
+
+    #[test]
+    fn subshells_and_groups() {
+        assert_eq!(var("x=1; (x=2; y=3); z=$x", "z").as_deref(), Some("1"));
+        assert_eq!(var("(y=3)", "y"), None);
+        assert_eq!(var("{ x=2; y=3; }", "y").as_deref(), Some("3"));
+        assert_eq!(run("(exit 3)").0, 3);
+        assert_eq!(run("{ true; false; }").0, 1);
+
+        let file = std::env::temp_dir().join(format!("rshell-group-{}", std::process::id()));
+        run(&format!("PATH=/usr/bin:/bin; {{ printf a; printf b; }} >{}; (printf c) >>{}", file.display(), file.display()));
+        assert_eq!(fs::read_to_string(&file).unwrap(), "abc");
+        let _ = fs::remove_file(&file);
+    }
+
+    #[test]
+    fn pipelines_return_the_last_status() {
+        assert_eq!(run("true | false").0, 1);
+        assert_eq!(run("false | true").0, 0);
+        assert_eq!(run("! true | false").0, 0);
+        assert_eq!(var("PATH=/usr/bin:/bin; x=$(printf 'a b\\n' | tr a-z A-Z)", "x").as_deref(), Some("A B"));
+    }
+
+    #[test]
+    fn command_substitution() {
+        let (_, shell, _) = run("PATH=/usr/bin:/bin; a=$(printf 'x\\n\\n'); b=`printf y`; c=$(printf '%s' \"$(printf in)\"); d=\"$(printf '1 2')\"");
+        assert_eq!(shell.get_var("a").as_deref(), Some("x"));
+        assert_eq!(shell.get_var("b").as_deref(), Some("y"));
+        assert_eq!(shell.get_var("c").as_deref(), Some("in"));
+        assert_eq!(shell.get_var("d").as_deref(), Some("1 2"));
+
+        // An assignment alone takes the status of its substitution.
+        assert_eq!(run("x=$(false)").0, 1);
+        assert_eq!(var("x=$(y=1)", "y"), None);
+    }
+
+    #[test]
+    fn errors_in_scripts_give_the_line() {
+        let (_, _, errors) = capture(|shell| crate::script::run_source("test.sh", "x=1\n\ny=${z@}\nreturn 5\n".as_bytes(), shell));
+        assert_eq!(errors, "test.sh: line 3: ${z@}: bad substitution\ntest.sh: line 4: return: can only `return' from a function or sourced script\n");
+    }
+
+    #[test]
+    fn missing_commands_fail_with_127() {
+        let (status, _, errors) = run("no_such_command_here");
+        assert_eq!(status, 127);
+        assert_eq!(errors, "no_such_command_here: command not found\n");
+    }
+
+    #[test]
+    fn expansion_errors_are_reported_by_the_shell() {
+        let (status, _, errors) = run("x=${y@}");
+        assert_eq!(status, 1);
+        assert_eq!(errors, "rshell: ${y@}: bad substitution\n");
+        assert_eq!(run("x=$((1 / 0))").2, "rshell: 1 / 0: division by 0\n");
+    }
+
+    #[test]
+    fn xtrace_prints_commands_after_ps4() {
+        let (_, _, errors) = run("set -x; x='a b'; : $x; set +x; : hidden");
+        assert_eq!(errors, "+ x='a b'\n+ : a b\n+ set +x\n");
+        let (_, _, errors) = run("n=0; PS4='$((n += 1))+ '; set -x; : one; : two");
+        assert_eq!(errors, "1+ : one\n2+ : two\n");
+    }
+}
@@ -0,0 +1,739 @@
+use std::{ffi::{CStr, CString}, fs, path::Path, process};
+
+use crate::arithmetic;
+use crate::executor::command_substitution;
+use crate::lexer::{is_name, scan_backtick, scan_dollar, scan_double_quote, scan_single_quote, Lexer, Token};
+use crate::sherror::ShellError;
+use crate::shell::Shell;
+
+enum Piece {
+    Literal(String),
+    Quoted(String),
+    Expanded(String),
+    /// A quoted `"$@"`, expanding to one field per positional parameter.
+    Params(Vec<String>)
+}
+
+struct Field {
+    value: String,
+    pattern: String,
+    has_glob: bool
+}
+
+/// Expands words into command arguments: parameters, arithmetic and
+/// tildes are expanded, unquoted results are split into fields and
+/// unquoted glob patterns are matched against the filesystem.
+pub fn expand_words(words: &[String], shell: &mut Shell) -> Result<Vec<String>, ShellError> {
+    let mut arguments = vec![];
+
+    for word in words {
+        for field in split_fields(expand_pieces(word, shell)?, shell) {
+            if field.has_glob {
+                let matches = glob_paths(&field.pattern);
+                if !matches.is_empty() {
+                    arguments.extend(matches);
+                    continue;
+                }
+            }
+            arguments.push(field.value);
+        }
+    }
+
+    Ok(arguments)
+}
+
+/// Expands a word without field splitting or pathname expansion, as done
+/// for assignments, redirection targets and `case` subjects.
+pub fn expand_word(word: &str, shell: &mut Shell) -> Result<String, ShellError> {
+    Ok(expand_pieces(word, shell)?
+        .into_iter()
+        .map(|piece| match piece {
+            Piece::Literal(s) | Piece::Quoted(s) | Piece::Expanded(s) => s,
+            Piece::Params(params) => params.join(" "),
+        })
+        .collect())
+}
+
+/// Expands the elements of an array assignment, `(word...)` without its
+/// parentheses, into its values.
+pub fn expand_array(elements: &str, shell: &mut Shell) -> Result<Vec<String>, ShellError> {
+    let mut lexer = Lexer::new(elements);
+    let mut words = vec![];
+    loop {
+        match lexer.next_token().map_err(|err| ShellError::Usage(format!("rshell: {}", err)))? {
+            Token::Word(word) => words.push(word),
+            Token::Newline => {},
+            Token::Eof => break,
+            token => return Err(ShellError::Usage(format!("rshell: syntax error near unexpected token `{}'", token))),
+        }
+    }
+    expand_words(&words, shell)
+}
+
+/// Expands the body of a here-document or a prompt like the text of a
+/// double-quoted string, except that a double quote is an ordinary
+/// character.
+pub fn expand_text(body: &str, shell: &mut Shell) -> Result<String, ShellError> {
+    let mut chars = vec![];
+    let mut iter = body.chars();
+    while let Some(c) = iter.next() {
+        chars.push(c);
+        if c == '\\' {
+            match iter.next() {
+                Some('"') => chars.extend(['\\', '"']),
+                Some(next) => chars.push(next),
+                None => {},
+            }
+        }
+    }
+
+    let mut pieces = vec![];
+    parse_into(&chars, true, shell, &mut pieces)?;
+    Ok(pieces
+        .into_iter()
+        .map(|piece| match piece {
+            Piece::Literal(s) | Piece::Quoted(s) | Piece::Expanded(s) => s,
+            Piece::Params(params) => params.join(" "),
+        })
+        .collect())
+}
+
+/// Expands a word into an extended regular expression in which quoted
+/// characters only match themselves.
+pub fn expand_regex(word: &str, shell: &mut Shell) -> Result<String, ShellError> {
+    Ok(expand_pieces(word, shell)?
+        .into_iter()
+        .map(|piece| match piece {
+            Piece::Literal(s) | Piece::Expanded(s) => s,
+            Piece::Quoted(s) => regex::escape(&s),
+            Piece::Params(params) => regex::escape(&params.join(" ")),
+        })
+        .collect())
+}
+
+/// Expands parameters in an arithmetic expression, then evaluates it.
+pub fn expand_arithmetic(expression: &str, shell: &mut Shell) -> Result<i64, ShellError> {
+    let expression = expand_word(expression, shell)?;
+    arithmetic::evaluate(&expression, shell)
+}
+
+/// Expands a word into a glob pattern in which quoted characters only
+/// match themselves.
+pub fn expand_pattern(word: &str, shell: &mut Shell) -> Result<String, ShellError> {
+    Ok(expand_pieces(word, shell)?
+        .into_iter()
+        .map(|piece| match piece {
+            Piece::Literal(s) | Piece::Expanded(s) => s,
+            Piece::Quoted(s) => escape_glob(&s),
+            Piece::Params(params) => escape_glob(&params.join(" ")),
+        })
+        .collect())
+}
+
+/// Quotes `text` so that the shell reads it back as a single word.
+pub fn quote_word(text: &str) -> String {
+    if !text.is_empty() && text.chars().all(|c| c.is_ascii_alphanumeric() || "_./-=:,@%+^".contains(c)) {
+        return text.to_string();
+    }
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+pub fn escape_glob(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn has_glob_chars(text: &str) -> bool {
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => { chars.next(); },
+            '*' | '?' | '[' => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+fn unescape_glob(text: &str) -> String {
+    let mut result = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => result.extend(chars.next()),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+fn split_fields(pieces: Vec<Piece>, shell: &Shell) -> Vec<Field> {
+    let ifs = shell.get_var("IFS").unwrap_or_else(|| " \t\n".to_string());
+    let mut fields = vec![];
+    let mut current = Field { value: String::new(), pattern: String::new(), has_glob: false };
+    let mut started = false;
+
+    for piece in pieces {
+        match piece {
+            Piece::Literal(s) => {
+                current.has_glob |= has_glob_chars(&s);
+                current.value.push_str(&s);
+                current.pattern.push_str(&s);
+                started = true;
+            },
+            Piece::Quoted(s) => {
+                current.pattern.push_str(&escape_glob(&s));
+                current.value.push_str(&s);
+                started = true;
+            },
+            Piece::Params(params) => {
+                for (i, param) in params.iter().enumerate() {
+                    if i > 0 {
+                        fields.push(current);
+                        current = Field { value: String::new(), pattern: String::new(), has_glob: false };
+                    }
+                    current.pattern.push_str(&escape_glob(param));
+                    current.value.push_str(param);
+                    started = true;
+                }
+            },
+            Piece::Expanded(s) => {
+                for c in s.chars() {
+                    if ifs.contains(c) {
+                        if started {
+                            fields.push(current);
+                            current = Field { value: String::new(), pattern: String::new(), has_glob: false };
+                            started = false;
+                        }
+                    } else {
+                        current.has_glob |= matches!(c, '*' | '?' | '[');
+                        current.value.push(c);
+                        current.pattern.push(c);
+                        started = true;
+                    }
+                }
+            },
+        }
+    }
+
+    if started {
+        fields.push(current);
+    }
+
+    fields
+}
+
+fn expand_pieces(word: &str, shell: &mut Shell) -> Result<Vec<Piece>, ShellError> {
+    let chars: Vec<char> = word.chars().collect();
+    let mut pieces = vec![];
+
+    if chars.first() == Some(&'~') {
+        let end = chars.iter().position(|c| *c == '/').unwrap_or(chars.len());
+        if end == 1 {
+            pieces.push(Piece::Quoted(shell.get_var("HOME").unwrap_or_default()));
+            parse_into(&chars[1..], false, shell, &mut pieces)?;
+            return Ok(pieces);
+        }
+        let name: String = chars[1..end].iter().collect();
+        if let Some(home) = user_home(&name) {
+            pieces.push(Piece::Quoted(home));
+            parse_into(&chars[end..], false, shell, &mut pieces)?;
+            return Ok(pieces);
+        }
+    }
+
+    parse_into(&chars, false, shell, &mut pieces)?;
+    Ok(pieces)
+}
+
+/// Home directory of the user `name`, for `~name`.
+pub fn user_home(name: &str) -> Option<String> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || "._-".contains(c)) {
+        return None;
+    }
+
+    let name = CString::new(name).ok()?;
+    let passwd = unsafe { libc::getpwnam(name.as_ptr()) };
+    if passwd.is_null() {
+        return None;
+    }
+    Some(unsafe { CStr::from_ptr((*passwd).pw_dir) }.to_string_lossy().to_string())
+}
+
+fn parse_into(chars: &[char], quoted: bool, shell: &mut Shell, pieces: &mut Vec<Piece>) -> Result<(), ShellError> {
+    let mut literal = String::new();
+    let mut i = 0;
+
+    let flush = |literal: &mut String, pieces: &mut Vec<Piece>| {
+        if !literal.is_empty() {
+            pieces.push(if quoted { Piece::Quoted(literal.clone()) } else { Piece::Literal(literal.clone()) });
+            literal.clear();
+        }
+    };
+
+    while i < chars.len() {
+        match chars[i] {
+            '\'' if !quoted => {
+                let end = scan_single_quote(chars, i + 1).unwrap_or(chars.len());
+                flush(&mut literal, pieces);
+                pieces.push(Piece::Quoted(chars[i + 1..end].iter().collect()));
+                i = end + 1;
+            },
+            '"' if !quoted => {
+                let end = scan_double_quote(chars, i + 1).unwrap_or(chars.len());
+                flush(&mut literal, pieces);
+                let count = pieces.len();
+                parse_into(&chars[i + 1..end], true, shell, pieces)?;
+                // An empty pair of quotes still produces an (empty) field.
+                if pieces.len() == count {
+                    pieces.push(Piece::Quoted(String::new()));
+                }
+                i = end + 1;
+            },
+            '\\' if i + 1 < chars.len() => {
+                let next = chars[i + 1];
+                if next == '\n' {
+                    // Line continuation.
+                } else if !quoted {
+                    flush(&mut literal, pieces);
+                    pieces.push(Piece::Quoted(next.to_string()));
+                } else if matches!(next, '\\' | '$' | '"' | '`') {
+                    literal.push(next);
+                } else {
+                    literal.push('\\');
+                    literal.push(next);
+                }
+                i += 2;
+            },
+            '`' => {
+                let end = scan_backtick(chars, i + 1).unwrap_or(chars.len());
+                let mut source = String::new();
+                let mut j = i + 1;
+                while j < end {
+                    if chars[j] == '\\' && matches!(chars.get(j + 1), Some('$' | '`' | '\\')) {
+                        j += 1;
+                    }
+                    source.push(chars[j]);
+                    j += 1;
+                }
+
+                flush(&mut literal, pieces);
+                let output = command_substitution(&source, shell)?;
+                pieces.push(if quoted { Piece::Quoted(output) } else { Piece::Expanded(output) });
+                i = end + 1;
+            },
+            '$' => {
+                let end = scan_dollar(chars, i).map_err(|e| ShellError::InvalidArgument(format!("rshell: {}", e)))?;
+                if end == i + 1 && !chars.get(i + 1).is_some_and(|c| is_param_start(*c)) {
+                    literal.push('$');
+                    i += 1;
+                    continue;
+                }
+
+                flush(&mut literal, pieces);
+                let text: String = chars[i..end].iter().collect();
+                if quoted && (text == "${@}" || (text == "$" && chars.get(i + 1) == Some(&'@'))) {
+                    pieces.push(Piece::Params(shell.positional.clone()));
+                    i = end.max(i + 2);
+                    continue;
+                }
+                if let Some(name) = quoted.then(|| text.strip_prefix("${").and_then(|t| t.strip_suffix("[@]}"))).flatten().filter(|name| is_name(name)) {
+                    pieces.push(Piece::Params(array_values(name, shell)));
+                    i = end;
+                    continue;
+                }
+
+                let (value, end) = expand_dollar(chars, i, end, shell)?;
+                pieces.push(if quoted { Piece::Quoted(value) } else { Piece::Expanded(value) });
+                i = end;
+            },
+            c => {
+                literal.push(c);
+                i += 1;
+            },
+        }
+    }
+
+    flush(&mut literal, pieces);
+    Ok(())
+}
+
+fn is_param_start(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '?' | '$' | '#' | '@' | '*' | '!' | '-')
+}
+
+/// Expands the construct starting with the `$` at `start`, returning its
+/// value and the index right after it.
+fn expand_dollar(chars: &[char], start: usize, end: usize, shell: &mut Shell) -> Result<(String, usize), ShellError> {
+    let text: String = chars[start..end].iter().collect();
+
+    if text.starts_with("$((") && text.ends_with("))") {
+        return expand_arithmetic(&text[3..text.len() - 2], shell).map(|value| (value.to_string(), end));
+    }
+
+    if text.starts_with("$(") {
+        return command_substitution(&text[2..text.len() - 1], shell).map(|output| (output, end));
+    }
+
+    if text.starts_with("${") {
+        return expand_braced(&text[2..text.len() - 1], shell).map(|value| (value, end));
+    }
+
+    let first = chars[start + 1];
+    if !(first.is_ascii_alphabetic() || first == '_') {
+        return Ok((lookup_param(&first.to_string(), shell).unwrap_or_default(), start + 2));
+    }
+
+    let mut name_end = start + 1;
+    while name_end < chars.len() && (chars[name_end].is_ascii_alphanumeric() || chars[name_end] == '_') {
+        name_end += 1;
+    }
+    let name: String = chars[start + 1..name_end].iter().collect();
+
+    Ok((lookup_param(&name, shell).unwrap_or_default(), name_end))
+}
+
+fn lookup_param(name: &str, shell: &Shell) -> Option<String> {
+    match name {
+        "?" => Some(shell.last_status.to_string()),
+        "$" => Some(process::id().to_string()),
+        "0" => Some(shell.name.clone()),
+        "#" => Some(shell.positional.len().to_string()),
+        "@" | "*" => Some(shell.positional.join(" ")),
+        name if name.chars().all(|c| c.is_ascii_digit()) => name
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| n.checked_sub(1))
+            .and_then(|n| shell.positional.get(n).cloned()),
+        name => shell.get_var(name).or_else(|| shell.arrays.get(name).and_then(|array| array.first().cloned())),
+    }
+}
+
+fn array_values(name: &str, shell: &Shell) -> Vec<String> {
+    match shell.arrays.get(name) {
+        Some(array) => array.clone(),
+        None => shell.get_var(name).into_iter().collect(),
+    }
+}
+
+/// Expands `${name[index]}`, `${name[@]}` and `${#name[@]}` subscripts.
+fn expand_subscript(name: &str, index: &str, length: bool, shell: &mut Shell) -> Result<String, ShellError> {
+    let values = array_values(name, shell);
+
+    if index == "@" || index == "*" {
+        return Ok(if length { values.len().to_string() } else { values.join(" ") });
+    }
+
+    let index = expand_arithmetic(index, shell)?;
+    let position = if index < 0 { values.len() as i64 + index } else { index };
+    let value = usize::try_from(position).ok().and_then(|i| values.get(i)).cloned().unwrap_or_default();
+
+    Ok(if length { value.chars().count().to_string() } else { value })
+}
+
+/// Expands the content of a `${...}` parameter expansion.
+fn expand_braced(content: &str, shell: &mut Shell) -> Result<String, ShellError> {
+    let (length, subject) = match content.strip_prefix('#') {
+        Some(name) if !name.is_empty() => (true, name),
+        _ => (false, content),
+    };
+
+    if let Some((name, index)) = subject.strip_suffix(']').and_then(|s| s.split_once('[')) {
+        if is_name(name) {
+            return expand_subscript(name, index, length, shell);
+        }
+    }
+
+    if length {
+        return Ok(lookup_param(subject, shell).unwrap_or_default().chars().count().to_string());
+    }
+
+    let name_len = match content.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => content
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(content.len()),
+        Some(c) if c.is_ascii_digit() => content.find(|c: char| !c.is_ascii_digit()).unwrap_or(content.len()),
+        Some(c) if is_param_start(c) => c.len_utf8(),
+        _ => return Err(ShellError::InvalidArgument(format!("rshell: ${{{}}}: bad substitution", content))),
+    };
+
+    let (name, rest) = content.split_at(name_len);
+    let value = lookup_param(name, shell);
+
+    if rest.is_empty() {
+        return Ok(value.unwrap_or_default());
+    }
+
+    let (colon, operation) = match rest.strip_prefix(':') {
+        Some(operation) => (true, operation),
+        None => (false, rest),
+    };
+    // With a colon, an empty value is treated like an unset one.
+    let set = value.as_ref().is_some_and(|v| !(colon && v.is_empty()));
+
+    let mut operators = operation.chars();
+    let operator = operators.next();
+    let argument = operators.as_str();
+
+    match operator {
+        Some('-') => if set { Ok(value.unwrap_or_default()) } else { expand_word(argument, shell) },
+        Some('=') => {
+            if set {
+                return Ok(value.unwrap_or_default());
+            }
+            let assigned = expand_word(argument, shell)?;
+            shell.set_var(name, &assigned);
+            Ok(assigned)
+        },
+        Some('+') => if set { expand_word(argument, shell) } else { Ok(String::new()) },
+        Some('?') => {
+            if set {
+                return Ok(value.unwrap_or_default());
+            }
+            let message = expand_word(argument, shell)?;
+            let message = if message.is_empty() { "parameter null or not set".to_string() } else { message };
+            Err(ShellError::InvalidArgument(format!("rshell: {}: {}", name, message)))
+        },
+        Some('#') | Some('%') if !colon => {
+            let value = value.unwrap_or_default();
+            let longest = argument.starts_with(operator.unwrap());
+            let pattern = expand_pattern(if longest { &argument[1..] } else { argument }, shell)?;
+            Ok(remove_affix(&value, &pattern, operator == Some('#'), longest))
+        },
+        _ => Err(ShellError::InvalidArgument(format!("rshell: ${{{}}}: bad substitution", content))),
+    }
+}
+
+fn remove_affix(value: &str, pattern: &str, prefix: bool, longest: bool) -> String {
+    let mut boundaries: Vec<usize> = value.char_indices().map(|(i, _)| i).chain([value.len()]).collect();
+    if longest == prefix {
+        boundaries.reverse();
+    }
+
+    for i in boundaries {
+        let (affix, rest) = if prefix { (&value[..i], &value[i..]) } else { (&value[i..], &value[..i]) };
+        if glob_match(pattern, affix) {
+            return rest.to_string();
+        }
+    }
+
+    value.to_string()
+}
+
+/// Matches `text` against a shell glob pattern supporting `*`, `?`,
+/// bracket expressions and backslash escapes.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        let step = match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+                continue;
+            },
+            Some('?') => Some(1),
+            Some('[') => match match_bracket(&pattern, p, text[t]) {
+                Some((true, next)) => Some(next - p),
+                Some((false, _)) => None,
+                None => (text[t] == '[').then_some(1),
+            },
+            Some('\\') if p + 1 < pattern.len() => (pattern[p + 1] == text[t]).then_some(2),
+            Some(c) => (*c == text[t]).then_some(1),
+            None => None,
+        };
+
+        match (step, backtrack) {
+            (Some(step), _) => {
+                p += step;
+                t += 1;
+            },
+            (None, Some((star, matched))) => {
+                p = star + 1;
+                t = matched + 1;
+                backtrack = Some((star, matched + 1));
+            },
+            (None, None) => return false,
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Matches `c` against the bracket expression starting at `start`. Returns
+/// whether it matched and the index after the closing `]`, or `None` when
+/// the bracket is not closed.
+fn match_bracket(pattern: &[char], start: usize, c: char) -> Option<(bool, usize)> {
+    let mut i = start + 1;
+    let negated = matches!(pattern.get(i), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+
+    while i < pattern.len() {
+        let current = pattern[i];
+
+        if current == ']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+        first = false;
+
+        if current == '[' && pattern.get(i + 1) == Some(&':') {
+            let rest: String = pattern[i + 2..].iter().collect();
+            if let Some(end) = rest.find(":]") {
+                matched |= match &rest[..end] {
+                    "alpha" => c.is_alphabetic(),
+                    "digit" => c.is_ascii_digit(),
+                    "alnum" => c.is_alphanumeric(),
+                    "upper" => c.is_uppercase(),
+                    "lower" => c.is_lowercase(),
+                    "space" => c.is_whitespace(),
+                    "blank" => c == ' ' || c == '\t',
+                    "punct" => c.is_ascii_punctuation(),
+                    "xdigit" => c.is_ascii_hexdigit(),
+                    _ => false,
+                };
+                i += 2 + rest[..end].chars().count() + 2;
+                continue;
+            }
+        }
+
+        let low = if current == '\\' && i + 1 < pattern.len() {
+            i += 1;
+            pattern[i]
+        } else {
+            current
+        };
+
+        if pattern.get(i + 1) == Some(&'-') && pattern.get(i + 2).is_some_and(|c| *c != ']') {
+            let high = pattern[i + 2];
+            matched |= low <= c && c <= high;
+            i += 3;
+        } else {
+            matched |= low == c;
+            i += 1;
+        }
+    }
+
+    None
+}
+
+/// Expands a glob pattern into the sorted list of matching paths.
+fn glob_paths(pattern: &str) -> Vec<String> {
+    let directories_only = pattern.ends_with('/');
+    let mut paths = vec![if pattern.starts_with('/') { "/".to_string() } else { String::new() }];
+
+    for component in pattern.split('/').filter(|c| !c.is_empty()) {
+        let mut next = vec![];
+
+        for base in paths {
+            if !has_glob_chars(component) {
+                next.push(join_path(&base, &unescape_glob(component)));
+                continue;
+            }
+
+            let directory = if base.is_empty() { "." } else { base.as_str() };
+            let entries = match fs::read_dir(directory) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if name.starts_with('.') && !component.starts_with('.') {
+                    continue;
+                }
+                if glob_match(component, &name) {
+                    next.push(join_path(&base, &name));
+                }
+            }
+        }
+
+        paths = next;
+    }
+
+    let mut paths: Vec<String> = paths
+        .into_iter()
+        .filter(|path| fs::symlink_metadata(path).is_ok())
+        .filter(|path| !directories_only || Path::new(path).is_dir())
+        .map(|path| if directories_only { format!("{}/", path) } else { path })
+        .collect();
+    paths.sort();
+    paths
+}
+
+fn join_path(base: &str, name: &str) -> String {
+    if base.is_empty() {
+        name.to_string()
+    } else if base.ends_with('/') {
+        format!("{}{}", base, name)
+    } else {
+        format!("{}/{}", base, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(words: &[&str]) -> Vec<String> {
+        let mut shell = Shell::new();
+        shell.set_var("x", "a  b");
+        shell.set_var("empty", "");
+        shell.set_var("IFS", " \t\n");
+        let words: Vec<String> = words.iter().map(|word| word.to_string()).collect();
+        expand_words(&words, &mut shell).unwrap()
+    }
+
+    #[test]
+    fn unquoted_expansions_are_split() {
+        assert_eq!(expand(&["$x"]), ["a", "b"]);
+        assert_eq!(expand(&["\"$x\""]), ["a  b"]);
+        assert_eq!(expand(&["'$x'"]), ["$x"]);
+        assert_eq!(expand(&["pre$x\"post\""]), ["prea", "bpost"]);
+    }
+
+    #[test]
+    fn empty_expansions() {
+        assert_eq!(expand(&["$empty"]), Vec::<String>::new());
+        assert_eq!(expand(&["\"$empty\""]), [""]);
+        assert_eq!(expand(&["''", "$empty", "b"]), ["", "b"]);
+    }
+
+    #[test]
+    fn quotes_and_escapes_are_removed() {
+        assert_eq!(expand(&[r"a\ b", r#""a\"b""#, r#"'a\b'"#, r#""\$x""#]), ["a b", "a\"b", r"a\b", "$x"]);
+        assert_eq!(expand(&["\"$((1 + 2))\"", "${x}"]), ["3", "a", "b"]);
+    }
+
+    #[test]
+    fn quote_word_reads_back_as_one_word() {
+        for text in ["plain", "two words", "it's", "", "$HOME", "a\nb"] {
+            assert_eq!(expand(&[&quote_word(text)]), [text]);
+        }
+        assert_eq!(quote_word("plain/path.txt"), "plain/path.txt");
+    }
+
+    #[test]
+    fn glob_patterns() {
+        assert!(glob_match("a*c", "abbc"));
+        assert!(glob_match("a?c", "abc"));
+        assert!(glob_match("[a-c]x", "bx"));
+        assert!(!glob_match("[!a-c]x", "bx"));
+        assert!(glob_match(r"a\*", "a*"));
+        assert!(!glob_match(r"a\*", "ab"));
+    }
+}
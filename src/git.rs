@@ -0,0 +1,859 @@
+use std::cell::RefCell;
+use std::collections::{BinaryHeap, HashMap};
+use std::fs;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::gitobject::{blob_id, parse_oid, read_u32, to_hex, Kind, Objects, Oid};
+
+/// Commits visited at most to count how far a branch is from its upstream.
+const MAX_WALK: usize = 10000;
+
+/// The repository around a directory.
+struct Repository {
+    git_dir: PathBuf,
+    /// Where refs, objects and the configuration live: the main git
+    /// directory of a worktree.
+    common_dir: PathBuf,
+    work_tree: Option<PathBuf>,
+    inside_git_dir: bool
+}
+
+/// What the prompt shows of a repository.
+#[derive(Clone, Default)]
+struct Status {
+    name: String,
+    bare: bool,
+    inside_git_dir: bool,
+    state: Option<String>,
+    staged: bool,
+    dirty: bool,
+    divergence: Option<(usize, usize)>
+}
+
+/// Results worth keeping between prompts, valid as long as the commits
+/// and index they were computed from don't change.
+struct Cached {
+    key: (Option<Oid>, Option<Oid>, i64, i64, u64),
+    staged: bool,
+    divergence: Option<(usize, usize)>,
+    /// Files found unchanged by hashing, with the modification time and
+    /// size they had, so that they are not read again at every prompt.
+    verified: HashMap<String, (i64, i64, u64)>
+}
+
+thread_local! {
+    static CACHE: RefCell<HashMap<PathBuf, Cached>> = RefCell::new(HashMap::new());
+}
+
+/// The `\g` prompt escape: ` (branch *+ ↑1↓2|MERGING)` in a repository,
+/// nothing elsewhere. `*` marks changes in the worktree, `+` changes
+/// staged, and the arrows the commits ahead of and behind the upstream.
+pub fn prompt_segment(cwd: &Path) -> String {
+    let Some(repository) = discover(cwd) else {
+        return String::new();
+    };
+    let status = repository.status();
+
+    if status.inside_git_dir {
+        return " (GIT_DIR!)".to_string();
+    }
+
+    let mut segment = format!(" ({}{}", if status.bare { "BARE:" } else { "" }, status.name);
+    if status.dirty || status.staged {
+        segment.push(' ');
+        if status.dirty {
+            segment.push('*');
+        }
+        if status.staged {
+            segment.push('+');
+        }
+    }
+    if let Some((ahead, behind)) = status.divergence.filter(|divergence| *divergence != (0, 0)) {
+        segment.push(' ');
+        if ahead > 0 {
+            segment.push_str(&format!("↑{}", ahead));
+        }
+        if behind > 0 {
+            segment.push_str(&format!("↓{}", behind));
+        }
+    }
+    if let Some(state) = status.state {
+        segment.push('|');
+        segment.push_str(&state);
+    }
+    segment.push(')');
+    segment
+}
+
+/// Names of the refs under `refs/<namespace>/` in the repository containing
+/// `cwd`, loose and packed: the branches for `heads`, for instance.
+pub fn ref_names(cwd: &Path, namespace: &str) -> Vec<String> {
+    let Some(repository) = discover(cwd) else {
+        return vec![];
+    };
+
+    let mut loose = vec![];
+    list_loose_refs(&repository.common_dir.join("refs").join(namespace), "", &mut loose);
+    let prefix = format!("refs/{}/", namespace);
+    let packed = repository.packed_refs().into_iter().filter_map(|(name, _, _)| name.strip_prefix(&prefix).map(String::from));
+
+    let mut names: Vec<String> = loose.into_iter().map(|(name, _)| name).chain(packed).collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Remotes of the repository containing `cwd`.
+pub fn remotes(cwd: &Path) -> Vec<String> {
+    let config = discover(cwd)
+        .and_then(|repository| fs::read_to_string(repository.common_dir.join("config")).ok())
+        .unwrap_or_default();
+    config_names(&config, "remote", true)
+}
+
+/// Aliases defined in the repository containing `cwd` and in the user's
+/// configuration under `home`.
+pub fn aliases(cwd: &Path, home: Option<&Path>) -> Vec<String> {
+    let repository = discover(cwd).map(|repository| repository.common_dir.join("config"));
+    let user = home.map(|home| home.join(".gitconfig"));
+
+    let mut names: Vec<String> = repository
+        .into_iter()
+        .chain(user)
+        .filter_map(|file| fs::read_to_string(file).ok())
+        .flat_map(|config| config_names(&config, "alias", false))
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Finds the repository containing `cwd`, walking up to the root: a
+/// `.git` directory, a `.git` file pointing to the git directory of a
+/// worktree or submodule, or a bare repository.
+fn discover(cwd: &Path) -> Option<Repository> {
+    for directory in cwd.ancestors() {
+        let dot_git = directory.join(".git");
+
+        let git_dir = if dot_git.is_file() {
+            let content = fs::read_to_string(&dot_git).ok()?;
+            let target = content.trim().strip_prefix("gitdir:")?.trim();
+            Some(directory.join(target))
+        } else if is_git_dir(&dot_git) {
+            Some(dot_git)
+        } else {
+            None
+        };
+
+        if let Some(git_dir) = git_dir {
+            return Some(Repository::new(git_dir, Some(directory.to_path_buf()), false));
+        }
+
+        if is_git_dir(directory) {
+            // A bare repository, or the inside of a `.git` directory.
+            let inside = directory.file_name().is_some_and(|name| name == ".git");
+            let work_tree = inside.then(|| directory.parent().map(Path::to_path_buf)).flatten();
+            return Some(Repository::new(directory.to_path_buf(), work_tree, inside));
+        }
+    }
+    None
+}
+
+fn is_git_dir(path: &Path) -> bool {
+    path.join("HEAD").is_file() && (path.join("objects").is_dir() || path.join("commondir").is_file())
+}
+
+impl Repository {
+    fn new(git_dir: PathBuf, work_tree: Option<PathBuf>, inside_git_dir: bool) -> Repository {
+        let common_dir = match fs::read_to_string(git_dir.join("commondir")) {
+            Ok(common) => git_dir.join(common.trim()),
+            Err(_) => git_dir.clone(),
+        };
+        Repository { git_dir, common_dir, work_tree, inside_git_dir }
+    }
+
+    fn status(&self) -> Status {
+        let config = fs::read_to_string(self.common_dir.join("config")).unwrap_or_default();
+        let bare = config_value(&config, "core", None, "bare").is_some_and(|value| value == "true");
+
+        let mut status = Status {
+            bare: bare || self.work_tree.is_none(),
+            inside_git_dir: self.inside_git_dir,
+            state: self.state(),
+            ..Default::default()
+        };
+
+        let head = fs::read_to_string(self.git_dir.join("HEAD")).unwrap_or_default();
+        let branch = match head.trim().strip_prefix("ref: ") {
+            Some(reference) => Some(reference.to_string()),
+            // A rebase detaches HEAD from the branch being rebased.
+            None => fs::read_to_string(self.git_dir.join("rebase-merge/head-name"))
+                .or_else(|_| fs::read_to_string(self.git_dir.join("rebase-apply/head-name")))
+                .ok()
+                .map(|name| name.trim().to_string())
+                .filter(|name| name.starts_with("refs/")),
+        };
+        let head_id = self.resolve("HEAD");
+
+        status.name = match (&branch, head_id) {
+            (Some(branch), _) => branch.strip_prefix("refs/heads/").unwrap_or(branch).to_string(),
+            (None, Some(id)) => match self.tag_pointing_at(&id) {
+                Some(tag) => format!("({})", tag),
+                None => format!("({}...)", &to_hex(&id)[..7]),
+            },
+            (None, None) => "(unknown)".to_string(),
+        };
+
+        if status.bare || status.inside_git_dir {
+            return status;
+        }
+
+        let upstream = branch
+            .as_deref()
+            .and_then(|branch| branch.strip_prefix("refs/heads/"))
+            .and_then(|branch| upstream_ref(&config, branch))
+            .and_then(|upstream| self.resolve(&upstream));
+
+        let index_path = self.git_dir.join("index");
+        let index = fs::read(&index_path).ok().and_then(|data| parse_index(&data));
+        let index_stamp = fs::metadata(&index_path).map(|m| (m.mtime(), m.mtime_nsec(), m.len())).unwrap_or_default();
+
+        let key = (head_id, upstream, index_stamp.0, index_stamp.1, index_stamp.2);
+        CACHE.with(|cache| {
+            let mut cache = cache.borrow_mut();
+            if cache.get(&self.git_dir).map_or(true, |cached| cached.key != key) {
+                let objects = Objects::open(&self.common_dir.join("objects"));
+                let staged = index.as_ref().is_some_and(|index| has_staged_changes(index, head_id, &objects));
+                let divergence = head_id.zip(upstream).and_then(|(head, upstream)| divergence(&objects, head, upstream));
+                cache.insert(self.git_dir.clone(), Cached { key, staged, divergence, verified: HashMap::new() });
+            }
+            let Some(cached) = cache.get_mut(&self.git_dir) else { return };
+
+            if let (Some(index), Some(work_tree)) = (&index, &self.work_tree) {
+                let file_mode = config_value(&config, "core", None, "filemode").map_or(true, |value| value != "false");
+                status.dirty = is_dirty(index, work_tree, (index_stamp.0, index_stamp.1), file_mode, &mut cached.verified);
+            }
+            status.staged = cached.staged;
+            status.divergence = cached.divergence;
+        });
+        status
+    }
+
+    /// An operation in progress, such as a merge or a rebase.
+    fn state(&self) -> Option<String> {
+        let exists = |name: &str| self.git_dir.join(name).exists();
+
+        let state = if exists("rebase-merge") {
+            if exists("rebase-merge/interactive") { "REBASE-i" } else { "REBASE-m" }
+        } else if exists("rebase-apply") {
+            if exists("rebase-apply/rebasing") {
+                "REBASE"
+            } else if exists("rebase-apply/applying") {
+                "AM"
+            } else {
+                "AM/REBASE"
+            }
+        } else if exists("MERGE_HEAD") {
+            "MERGING"
+        } else if exists("CHERRY_PICK_HEAD") {
+            "CHERRY-PICKING"
+        } else if exists("REVERT_HEAD") {
+            "REVERTING"
+        } else if exists("BISECT_LOG") {
+            "BISECTING"
+        } else {
+            return None;
+        };
+        Some(state.to_string())
+    }
+
+    /// The commit a ref points to, following symbolic refs. Loose refs are
+    /// looked up in the worktree's directory first, then packed refs.
+    fn resolve(&self, name: &str) -> Option<Oid> {
+        let mut name = name.to_string();
+
+        for _ in 0..10 {
+            let loose = [&self.git_dir, &self.common_dir]
+                .iter()
+                .find_map(|directory| fs::read_to_string(directory.join(&name)).ok());
+
+            match loose {
+                Some(content) => match content.trim().strip_prefix("ref: ") {
+                    Some(target) => name = target.to_string(),
+                    None => return parse_oid(&content),
+                },
+                None => return self.packed_refs().into_iter().find(|(reference, _, _)| *reference == name).map(|(_, id, _)| id),
+            }
+        }
+        None
+    }
+
+    /// Refs of `packed-refs`, with the commit of annotated tags.
+    fn packed_refs(&self) -> Vec<(String, Oid, Option<Oid>)> {
+        let content = fs::read_to_string(self.common_dir.join("packed-refs")).unwrap_or_default();
+        let mut refs: Vec<(String, Oid, Option<Oid>)> = vec![];
+
+        for line in content.lines() {
+            if let Some(peeled) = line.strip_prefix('^') {
+                if let Some(last) = refs.last_mut() {
+                    last.2 = parse_oid(peeled);
+                }
+            } else if let Some((id, name)) = line.split_once(' ').filter(|_| !line.starts_with('#')) {
+                if let Some(id) = parse_oid(id) {
+                    refs.push((name.to_string(), id, None));
+                }
+            }
+        }
+        refs
+    }
+
+    /// A tag naming the commit HEAD is detached at.
+    fn tag_pointing_at(&self, commit: &Oid) -> Option<String> {
+        let mut objects = None;
+        let mut peel = |id: Oid| -> Oid {
+            let objects = objects.get_or_insert_with(|| Objects::open(&self.common_dir.join("objects")));
+            match objects.read(&id) {
+                Some((Kind::Tag, data)) => String::from_utf8_lossy(&data)
+                    .lines()
+                    .find_map(|line| line.strip_prefix("object ").and_then(parse_oid))
+                    .unwrap_or(id),
+                _ => id,
+            }
+        };
+
+        let mut loose = vec![];
+        list_loose_refs(&self.common_dir.join("refs/tags"), "", &mut loose);
+        for (name, id) in loose {
+            if peel(id) == *commit {
+                return Some(name);
+            }
+        }
+
+        self.packed_refs()
+            .into_iter()
+            .filter_map(|(name, id, peeled)| Some((name.strip_prefix("refs/tags/")?.to_string(), peeled.unwrap_or(id))))
+            .find(|(_, id)| id == commit)
+            .map(|(name, _)| name)
+    }
+}
+
+fn list_loose_refs(directory: &Path, prefix: &str, refs: &mut Vec<(String, Oid)>) {
+    let Ok(entries) = fs::read_dir(directory) else { return };
+    for entry in entries.flatten() {
+        let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        if entry.path().is_dir() {
+            list_loose_refs(&entry.path(), &format!("{}/", name), refs);
+        } else if let Some(id) = fs::read_to_string(entry.path()).ok().and_then(|content| parse_oid(&content)) {
+            refs.push((name, id));
+        }
+    }
+}
+
+/// Reads a value from git's configuration format. Section and key names
+/// ignore case, subsections don't.
+fn config_value(config: &str, section: &str, subsection: Option<&str>, key: &str) -> Option<String> {
+    let mut in_section = false;
+    let mut value = None;
+
+    for line in config.lines().map(str::trim) {
+        if line.starts_with('[') {
+            let header = line.trim_start_matches('[').split(']').next().unwrap_or_default();
+            let (name, sub) = match header.split_once(' ') {
+                Some((name, sub)) => (name, Some(sub.trim().trim_matches('"'))),
+                None => (header, None),
+            };
+            in_section = name.eq_ignore_ascii_case(section) && sub == subsection;
+        } else if in_section {
+            let (name, found) = line.split_once('=').map_or((line, "true"), |(name, found)| (name, found));
+            if name.trim().eq_ignore_ascii_case(key) {
+                value = Some(found.split(['#', ';']).next().unwrap_or_default().trim().trim_matches('"').to_string());
+            }
+        }
+    }
+    value
+}
+
+/// Subsections of `section` in git's configuration format, such as the
+/// names of remotes, or the keys of the section itself.
+fn config_names(config: &str, section: &str, subsections: bool) -> Vec<String> {
+    let mut in_section = false;
+    let mut names = vec![];
+
+    for line in config.lines().map(str::trim) {
+        if line.starts_with('[') {
+            let header = line.trim_start_matches('[').split(']').next().unwrap_or_default();
+            let (name, sub) = match header.split_once(' ') {
+                Some((name, sub)) => (name, Some(sub.trim().trim_matches('"'))),
+                None => (header, None),
+            };
+            in_section = name.eq_ignore_ascii_case(section) && sub.is_none();
+            if let Some(sub) = sub.filter(|_| subsections && name.eq_ignore_ascii_case(section)) {
+                names.push(sub.to_string());
+            }
+        } else if in_section && !subsections && !line.starts_with(['#', ';']) {
+            if let Some(key) = line.split('=').next().map(str::trim).filter(|key| !key.is_empty()) {
+                names.push(key.to_string());
+            }
+        }
+    }
+    names
+}
+
+/// The remote-tracking ref `branch` is set to follow.
+fn upstream_ref(config: &str, branch: &str) -> Option<String> {
+    let remote = config_value(config, "branch", Some(branch), "remote")?;
+    let merge = config_value(config, "branch", Some(branch), "merge")?;
+    if remote == "." {
+        return Some(merge);
+    }
+    Some(format!("refs/remotes/{}/{}", remote, merge.strip_prefix("refs/heads/").unwrap_or(&merge)))
+}
+
+struct IndexEntry {
+    path: String,
+    id: Oid,
+    mode: u32,
+    mtime: (i64, i64),
+    size: u32,
+    stage: u16,
+    /// Entries marked assume-unchanged, skip-worktree or intent-to-add are
+    /// not compared with the worktree.
+    skip: bool
+}
+
+/// Parses the entries of an index of version 2 to 4.
+fn parse_index(data: &[u8]) -> Option<Vec<IndexEntry>> {
+    if !data.starts_with(b"DIRC") {
+        return None;
+    }
+    let version = read_u32(data, 4)?;
+    let count = read_u32(data, 8)? as usize;
+    if !(2..=4).contains(&version) {
+        return None;
+    }
+
+    let mut entries: Vec<IndexEntry> = Vec::with_capacity(count);
+    let mut pos = 12;
+    for _ in 0..count {
+        let start = pos;
+        let field = |i: usize| read_u32(data, start + 4 * i);
+        let mtime = (field(2)? as i64, field(3)? as i64);
+        let mode = field(6)?;
+        let size = field(9)?;
+        let id: Oid = data.get(start + 40..start + 60)?.try_into().ok()?;
+        let flags = u16::from_be_bytes(data.get(start + 60..start + 62)?.try_into().ok()?);
+        pos = start + 62;
+
+        let mut skip = flags & 0x8000 != 0;
+        if version >= 3 && flags & 0x4000 != 0 {
+            let extended = u16::from_be_bytes(data.get(pos..pos + 2)?.try_into().ok()?);
+            skip |= extended & 0x6000 != 0;
+            pos += 2;
+        }
+
+        let path = if version == 4 {
+            // The path replaces the end of the previous one.
+            let mut byte = *data.get(pos)?;
+            let mut strip = (byte & 0x7f) as usize;
+            pos += 1;
+            while byte & 0x80 != 0 {
+                byte = *data.get(pos)?;
+                strip = ((strip + 1) << 7) | (byte & 0x7f) as usize;
+                pos += 1;
+            }
+            let previous = entries.last().map(|entry| entry.path.as_bytes()).unwrap_or_default();
+            let mut path = previous.get(..previous.len().checked_sub(strip)?)?.to_vec();
+            let end = pos + data.get(pos..)?.iter().position(|byte| *byte == 0)?;
+            path.extend_from_slice(&data[pos..end]);
+            pos = end + 1;
+            path
+        } else {
+            let end = pos + data.get(pos..)?.iter().position(|byte| *byte == 0)?;
+            let path = data[pos..end].to_vec();
+            // Entries are padded with NULs to a multiple of 8 bytes.
+            pos = start + (end - start + 8) / 8 * 8;
+            path
+        };
+
+        entries.push(IndexEntry {
+            path: String::from_utf8(path).ok()?,
+            id,
+            mode,
+            mtime,
+            size,
+            stage: (flags >> 12) & 3,
+            skip
+        });
+    }
+    Some(entries)
+}
+
+/// Whether a tracked file differs from the index. Files whose size and
+/// modification time match are taken as unchanged, unless they were
+/// modified in the same second the index was written; the others are
+/// hashed, once for as long as they keep the same time and size in
+/// `verified`.
+fn is_dirty(
+    index: &[IndexEntry],
+    work_tree: &Path,
+    index_mtime: (i64, i64),
+    file_mode: bool,
+    verified: &mut HashMap<String, (i64, i64, u64)>
+) -> bool {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |now| now.as_secs() as i64);
+
+    const SYMLINK: u32 = 0o120000;
+    const GITLINK: u32 = 0o160000;
+
+    index.iter().any(|entry| {
+        if entry.stage != 0 {
+            return true;
+        }
+        if entry.skip || entry.mode & 0o170000 == GITLINK {
+            return false;
+        }
+
+        let path = work_tree.join(&entry.path);
+        let Ok(metadata) = fs::symlink_metadata(&path) else { return true };
+        let is_symlink = entry.mode & 0o170000 == SYMLINK;
+        if metadata.file_type().is_symlink() != is_symlink || !(metadata.is_file() || is_symlink) {
+            return true;
+        }
+        if file_mode && !is_symlink && (metadata.permissions().mode() & 0o100 != 0) != (entry.mode & 0o100 != 0) {
+            return true;
+        }
+        if metadata.len() as u32 != entry.size {
+            return true;
+        }
+
+        let mtime = (metadata.mtime(), metadata.mtime_nsec());
+        if mtime == entry.mtime && entry.mtime < index_mtime {
+            return false;
+        }
+        let stamp = (mtime.0, mtime.1, metadata.len());
+        if verified.get(&entry.path) == Some(&stamp) {
+            return false;
+        }
+
+        let content = if is_symlink {
+            fs::read_link(&path).map(|target| target.into_os_string().into_encoded_bytes())
+        } else {
+            fs::read(&path)
+        };
+        let dirty = content.map_or(true, |content| blob_id(&content) != entry.id);
+        // A file changed again within the second would keep its time.
+        if !dirty && mtime.0 < now {
+            verified.insert(entry.path.clone(), stamp);
+        }
+        dirty
+    })
+}
+
+/// Whether the index differs from the tree of HEAD.
+fn has_staged_changes(index: &[IndexEntry], head: Option<Oid>, objects: &Objects) -> bool {
+    let mut tree = HashMap::new();
+    if let Some(head) = head {
+        let root = match objects.read(&head) {
+            Some((Kind::Commit, data)) => String::from_utf8_lossy(&data)
+                .lines()
+                .next()
+                .and_then(|line| line.strip_prefix("tree "))
+                .and_then(parse_oid),
+            _ => None,
+        };
+        // Without the objects, nothing can be said.
+        let Some(root) = root else { return false };
+        if !flatten_tree(objects, &root, "", &mut tree) {
+            return false;
+        }
+    }
+
+    let staged: Vec<&IndexEntry> = index.iter().filter(|entry| !(entry.skip && entry.id == [0; 20])).collect();
+    staged.len() != tree.len()
+        || staged.iter().any(|entry| entry.stage != 0 || tree.get(entry.path.as_str()) != Some(&(entry.id, entry.mode)))
+}
+
+/// Collects the files of a tree by path, with their id and mode.
+fn flatten_tree(objects: &Objects, id: &Oid, prefix: &str, files: &mut HashMap<String, (Oid, u32)>) -> bool {
+    let Some((Kind::Tree, data)) = objects.read(id) else { return false };
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let Some(space) = data[pos..].iter().position(|byte| *byte == b' ') else { return false };
+        let Some(nul) = data[pos..].iter().position(|byte| *byte == 0) else { return false };
+        let mode = u32::from_str_radix(&String::from_utf8_lossy(&data[pos..pos + space]), 8).unwrap_or_default();
+        let name = String::from_utf8_lossy(&data[pos + space + 1..pos + nul]).to_string();
+        let Some(entry_id) = data.get(pos + nul + 1..pos + nul + 21).and_then(|bytes| Oid::try_from(bytes).ok()) else {
+            return false;
+        };
+        pos += nul + 21;
+
+        let path = format!("{}{}", prefix, name);
+        if mode == 0o40000 {
+            if !flatten_tree(objects, &entry_id, &format!("{}/", path), files) {
+                return false;
+            }
+        } else {
+            files.insert(path, (entry_id, mode));
+        }
+    }
+    true
+}
+
+/// Parents and committer time of a commit.
+fn read_commit(objects: &Objects, id: &Oid) -> Option<(Vec<Oid>, i64)> {
+    let (kind, data) = objects.read(id)?;
+    if kind != Kind::Commit {
+        return None;
+    }
+
+    let text = String::from_utf8_lossy(&data);
+    let mut parents = vec![];
+    let mut time = 0;
+    for line in text.lines().take_while(|line| !line.is_empty()) {
+        if let Some(parent) = line.strip_prefix("parent ") {
+            parents.push(parse_oid(parent)?);
+        } else if let Some(committer) = line.strip_prefix("committer ") {
+            time = committer.rsplit(' ').nth(1).and_then(|time| time.parse().ok()).unwrap_or_default();
+        }
+    }
+    Some((parents, time))
+}
+
+/// Commits reachable from `head` but not `upstream`, and the reverse. Both
+/// histories are walked from the most recent commit until only common
+/// ancestors are left, as git does.
+fn divergence(objects: &Objects, head: Oid, upstream: Oid) -> Option<(usize, usize)> {
+    const HEAD: u8 = 1;
+    const UPSTREAM: u8 = 2;
+    const BOTH: u8 = HEAD | UPSTREAM;
+
+    if head == upstream {
+        return Some((0, 0));
+    }
+
+    let mut flags: HashMap<Oid, u8> = HashMap::new();
+    let mut queue = BinaryHeap::new();
+    for (id, flag) in [(head, HEAD), (upstream, UPSTREAM)] {
+        let (_, time) = read_commit(objects, &id)?;
+        flags.insert(id, flag);
+        queue.push((time, id));
+    }
+
+    let mut visited = 0;
+    while let Some((_, id)) = queue.pop() {
+        visited += 1;
+        if visited > MAX_WALK {
+            return None;
+        }
+
+        let flag = flags[&id];
+        let (parents, _) = read_commit(objects, &id)?;
+        for parent in parents {
+            let previous = flags.get(&parent).copied().unwrap_or(0);
+            if previous | flag != previous {
+                flags.insert(parent, previous | flag);
+                let (_, time) = read_commit(objects, &parent)?;
+                queue.push((time, parent));
+            }
+        }
+
+        if queue.iter().all(|(_, id)| flags[id] == BOTH) {
+            break;
+        }
+    }
+
+    let ahead = flags.values().filter(|flag| **flag == HEAD).count();
+    let behind = flags.values().filter(|flag| **flag == UPSTREAM).count();
+    Some((ahead, behind))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An index entry of version 2 or 3, without its path padding.
+    fn entry(path: &str, id: Oid, mode: u32, size: u32, flags: u16) -> Vec<u8> {
+        let mut data = vec![];
+        for field in [0, 0, 100, 5, 0, 0, mode, 0, 0, size] {
+            data.extend_from_slice(&u32::to_be_bytes(field));
+        }
+        data.extend_from_slice(&id);
+        data.extend_from_slice(&(flags | path.len() as u16).to_be_bytes());
+        data
+    }
+
+    fn index(version: u32, entries: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = b"DIRC".to_vec();
+        data.extend_from_slice(&version.to_be_bytes());
+        data.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+        for entry in entries {
+            data.extend_from_slice(entry);
+        }
+        data.extend_from_slice(&[0; 20]);
+        data
+    }
+
+    fn padded(mut entry: Vec<u8>, path: &str) -> Vec<u8> {
+        entry.extend_from_slice(path.as_bytes());
+        let length = (entry.len() + 8) / 8 * 8;
+        entry.resize(length, 0);
+        entry
+    }
+
+    #[test]
+    fn parse_index_version_2() {
+        let data = index(2, &[
+            padded(entry("README.md", [1; 20], 0o100644, 12, 0), "README.md"),
+            padded(entry("src/main.rs", [2; 20], 0o100755, 34, 2 << 12), "src/main.rs"),
+            padded(entry("x", [3; 20], 0o100644, 0, 0x8000), "x"),
+        ]);
+        let entries = parse_index(&data).unwrap();
+        let paths: Vec<&str> = entries.iter().map(|entry| entry.path.as_str()).collect();
+        assert_eq!(paths, ["README.md", "src/main.rs", "x"]);
+        assert_eq!(entries[0].id, [1; 20]);
+        assert_eq!(entries[0].mtime, (100, 5));
+        assert_eq!(entries[0].size, 12);
+        assert_eq!(entries[1].mode, 0o100755);
+        assert_eq!(entries[1].stage, 2);
+        assert!(!entries[1].skip);
+        assert!(entries[2].skip);
+    }
+
+    #[test]
+    fn parse_index_version_3_extended_flags() {
+        let mut skipped = entry("a", [1; 20], 0o100644, 1, 0x4000);
+        skipped.extend_from_slice(&0x4000u16.to_be_bytes());
+        let data = index(3, &[padded(skipped, "a"), padded(entry("b", [2; 20], 0o100644, 1, 0), "b")]);
+        let entries = parse_index(&data).unwrap();
+        assert!(entries[0].skip);
+        assert_eq!(entries[1].path, "b");
+        assert!(!entries[1].skip);
+    }
+
+    #[test]
+    fn parse_index_version_4_prefix_compression() {
+        let mut first = entry("src/git.rs", [1; 20], 0o100644, 1, 0);
+        first.extend_from_slice(b"\0src/git.rs\0");
+        let mut second = entry("src/gitobject.rs", [2; 20], 0o100644, 1, 0);
+        // Strip ".rs" and append "object.rs".
+        second.extend_from_slice(b"\x03object.rs\0");
+        let entries = parse_index(&index(4, &[first, second])).unwrap();
+        assert_eq!(entries[0].path, "src/git.rs");
+        assert_eq!(entries[1].path, "src/gitobject.rs");
+    }
+
+    #[test]
+    fn parse_index_rejects_bad_data() {
+        assert!(parse_index(b"DIRX").is_none());
+        assert!(parse_index(&index(5, &[])).is_none());
+        let mut truncated = index(2, &[padded(entry("a", [1; 20], 0o100644, 1, 0), "a")]);
+        truncated.truncate(40);
+        assert!(parse_index(&truncated).is_none());
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rshell-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn index_entry(path: &str, content: &[u8]) -> IndexEntry {
+        IndexEntry { path: path.to_string(), id: blob_id(content), mode: 0o100644, mtime: (0, 0), size: content.len() as u32, stage: 0, skip: false }
+    }
+
+    #[test]
+    fn is_dirty_compares_content() {
+        let dir = temp_dir("dirty");
+        fs::write(dir.join("a.txt"), "hello\n").unwrap();
+        let index = [index_entry("a.txt", b"hello\n")];
+        assert!(!is_dirty(&index, &dir, (0, 0), true, &mut HashMap::new()));
+
+        // Same size, other content.
+        fs::write(dir.join("a.txt"), "world\n").unwrap();
+        assert!(is_dirty(&index, &dir, (0, 0), true, &mut HashMap::new()));
+
+        fs::remove_file(dir.join("a.txt")).unwrap();
+        assert!(is_dirty(&index, &dir, (0, 0), true, &mut HashMap::new()));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn is_dirty_hashes_a_file_once_while_it_keeps_its_time_and_size() {
+        let dir = temp_dir("dirty-verified");
+        let path = dir.join("a.txt");
+        let old = UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+        fs::write(&path, "hello\n").unwrap();
+        fs::File::options().write(true).open(&path).unwrap().set_modified(old).unwrap();
+
+        let index = [index_entry("a.txt", b"hello\n")];
+        let mut verified = HashMap::new();
+        assert!(!is_dirty(&index, &dir, (0, 0), true, &mut verified));
+        assert_eq!(verified.get("a.txt"), Some(&(1_000_000, 0, 6)));
+
+        // Not read again: the same time and size are taken as unchanged.
+        fs::write(&path, "world\n").unwrap();
+        fs::File::options().write(true).open(&path).unwrap().set_modified(old).unwrap();
+        assert!(!is_dirty(&index, &dir, (0, 0), true, &mut verified));
+
+        fs::File::options().write(true).open(&path).unwrap().set_modified(old + std::time::Duration::from_secs(1)).unwrap();
+        assert!(is_dirty(&index, &dir, (0, 0), true, &mut verified));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn is_dirty_skips_flagged_entries_and_reports_conflicts() {
+        let dir = temp_dir("dirty-flags");
+        let mut skipped = index_entry("missing", b"x");
+        skipped.skip = true;
+        assert!(!is_dirty(&[skipped], &dir, (0, 0), true, &mut HashMap::new()));
+
+        let mut conflict = index_entry("missing", b"x");
+        conflict.stage = 1;
+        assert!(is_dirty(&[conflict], &dir, (0, 0), true, &mut HashMap::new()));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    fn write_object(objects: &Path, id: Oid, kind: &str, content: &str) {
+        use std::io::Write;
+        let mut encoder = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+        write!(encoder, "{} {}\0{}", kind, content.len(), content).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let hex = to_hex(&id);
+        fs::create_dir_all(objects.join(&hex[..2])).unwrap();
+        fs::write(objects.join(&hex[..2]).join(&hex[2..]), compressed).unwrap();
+    }
+
+    fn write_commit(objects: &Path, id: u8, parents: &[u8], time: i64) -> Oid {
+        let mut content = format!("tree {}\n", to_hex(&[0; 20]));
+        for parent in parents {
+            content.push_str(&format!("parent {}\n", to_hex(&[*parent; 20])));
+        }
+        content.push_str(&format!("author A <a@b> {} +0000\ncommitter A <a@b> {} +0000\n\nmessage\n", time, time));
+        write_object(objects, [id; 20], "commit", &content);
+        [id; 20]
+    }
+
+    #[test]
+    fn divergence_counts_commits_on_each_side() {
+        let dir = temp_dir("divergence");
+        let base = write_commit(&dir, 1, &[], 100);
+        write_commit(&dir, 2, &[1], 200);
+        let head = write_commit(&dir, 3, &[2], 300);
+        let upstream = write_commit(&dir, 4, &[1], 250);
+        let merge = write_commit(&dir, 5, &[3, 4], 400);
+        let objects = Objects::open(&dir);
+
+        assert_eq!(divergence(&objects, head, upstream), Some((2, 1)));
+        assert_eq!(divergence(&objects, upstream, head), Some((1, 2)));
+        assert_eq!(divergence(&objects, head, head), Some((0, 0)));
+        assert_eq!(divergence(&objects, head, base), Some((2, 0)));
+        assert_eq!(divergence(&objects, merge, upstream), Some((3, 0)));
+        assert_eq!(divergence(&objects, head, [9; 20]), None);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
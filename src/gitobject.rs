@@ -0,0 +1,440 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+use flate2::bufread::ZlibDecoder;
+use sha1::{Digest, Sha1};
+
+/// Object id of a SHA-1 repository.
+pub type Oid = [u8; 20];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Commit,
+    Tree,
+    Blob,
+    Tag
+}
+
+impl Kind {
+    fn from_code(code: u8) -> Option<Kind> {
+        match code {
+            1 => Some(Kind::Commit),
+            2 => Some(Kind::Tree),
+            3 => Some(Kind::Blob),
+            4 => Some(Kind::Tag),
+            _ => None,
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Kind> {
+        match name {
+            "commit" => Some(Kind::Commit),
+            "tree" => Some(Kind::Tree),
+            "blob" => Some(Kind::Blob),
+            "tag" => Some(Kind::Tag),
+            _ => None,
+        }
+    }
+}
+
+pub fn parse_oid(hex: &str) -> Option<Oid> {
+    let hex = hex.trim();
+    if hex.len() != 40 {
+        return None;
+    }
+
+    let mut oid = [0u8; 20];
+    for (i, byte) in oid.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(2 * i..2 * i + 2)?, 16).ok()?;
+    }
+    Some(oid)
+}
+
+pub fn to_hex(oid: &Oid) -> String {
+    oid.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The object database of a repository: loose objects and packs, in its
+/// `objects` directory and the alternates it lists.
+pub struct Objects {
+    directories: Vec<PathBuf>,
+    packs: Vec<Pack>
+}
+
+struct Pack {
+    index: Vec<u8>,
+    data: fs::File
+}
+
+impl Objects {
+    pub fn open(directory: &Path) -> Objects {
+        let mut directories = vec![directory.to_path_buf()];
+        if let Ok(alternates) = fs::read_to_string(directory.join("info/alternates")) {
+            for line in alternates.lines().filter(|line| !line.is_empty() && !line.starts_with('#')) {
+                directories.push(directory.join(line));
+            }
+        }
+
+        let mut packs = vec![];
+        for directory in &directories {
+            let Ok(entries) = fs::read_dir(directory.join("pack")) else { continue };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.extension().is_some_and(|extension| extension == "idx") {
+                    let pack = fs::read(&path).ok().zip(fs::File::open(path.with_extension("pack")).ok());
+                    if let Some((index, data)) = pack.filter(|(index, _)| index.starts_with(b"\xfftOc\0\0\0\x02")) {
+                        packs.push(Pack { index, data });
+                    }
+                }
+            }
+        }
+
+        Objects { directories, packs }
+    }
+
+    /// Reads an object, or `None` when it is missing or cannot be decoded.
+    pub fn read(&self, oid: &Oid) -> Option<(Kind, Vec<u8>)> {
+        self.read_object(oid, 0)
+    }
+
+    fn read_object(&self, oid: &Oid, depth: usize) -> Option<(Kind, Vec<u8>)> {
+        for pack in &self.packs {
+            if let Some(offset) = pack.find(oid) {
+                return self.read_packed(pack, offset, depth);
+            }
+        }
+
+        let hex = to_hex(oid);
+        for directory in &self.directories {
+            let Ok(compressed) = fs::read(directory.join(&hex[..2]).join(&hex[2..])) else { continue };
+            let raw = inflate(compressed.as_slice(), 0)?;
+            let nul = raw.iter().position(|byte| *byte == 0)?;
+            let header = std::str::from_utf8(&raw[..nul]).ok()?;
+            let kind = Kind::from_name(header.split(' ').next()?)?;
+            return Some((kind, raw[nul + 1..].to_vec()));
+        }
+        None
+    }
+
+    fn read_packed(&self, pack: &Pack, offset: usize, depth: usize) -> Option<(Kind, Vec<u8>)> {
+        const OFS_DELTA: u8 = 6;
+        const REF_DELTA: u8 = 7;
+
+        if depth > 1000 {
+            return None;
+        }
+
+        let mut reader = BufReader::new(PackReader { file: &pack.data, pos: offset as u64 });
+        let mut byte = read_byte(&mut reader)?;
+        let code = (byte >> 4) & 7;
+        let mut size = (byte & 15) as usize;
+        let mut shift = 4;
+        while byte & 0x80 != 0 {
+            byte = read_byte(&mut reader)?;
+            size |= ((byte & 0x7f) as usize).checked_shl(shift)?;
+            shift += 7;
+        }
+
+        let (kind, base) = match code {
+            OFS_DELTA => {
+                let mut byte = read_byte(&mut reader)?;
+                let mut distance = (byte & 0x7f) as usize;
+                while byte & 0x80 != 0 {
+                    byte = read_byte(&mut reader)?;
+                    distance = ((distance + 1) << 7) | (byte & 0x7f) as usize;
+                }
+                self.read_packed(pack, offset.checked_sub(distance)?, depth + 1)?
+            },
+            REF_DELTA => {
+                let mut base: Oid = [0; 20];
+                reader.read_exact(&mut base).ok()?;
+                self.read_object(&base, depth + 1)?
+            },
+            code => return Some((Kind::from_code(code)?, inflate(reader, size)?)),
+        };
+
+        let delta = inflate(reader, size)?;
+        Some((kind, apply_delta(&base, &delta)?))
+    }
+}
+
+/// Reads a pack from a position of its own, with positioned reads that
+/// leave the file's offset alone.
+struct PackReader<'a> {
+    file: &'a fs::File,
+    pos: u64
+}
+
+impl Read for PackReader<'_> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let count = self.file.read_at(buffer, self.pos)?;
+        self.pos += count as u64;
+        Ok(count)
+    }
+}
+
+fn read_byte<R: Read>(reader: &mut R) -> Option<u8> {
+    let mut byte = [0];
+    reader.read_exact(&mut byte).ok()?;
+    Some(byte[0])
+}
+
+impl Pack {
+    /// Offset in the pack of an object, from the version 2 index.
+    fn find(&self, oid: &Oid) -> Option<usize> {
+        let index = &self.index;
+        let fanout = |i: usize| read_u32(index, 8 + 4 * i).map(|n| n as usize);
+        let count = fanout(255)?;
+        let mut low = if oid[0] == 0 { 0 } else { fanout(oid[0] as usize - 1)? };
+        let mut high = fanout(oid[0] as usize)?;
+
+        let names = 8 + 1024;
+        while low < high {
+            let middle = (low + high) / 2;
+            let name = index.get(names + 20 * middle..names + 20 * middle + 20)?;
+            match name.cmp(oid.as_slice()) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => {
+                    let offsets = names + 24 * count;
+                    let offset = read_u32(index, offsets + 4 * middle)?;
+                    if offset & 0x8000_0000 == 0 {
+                        return Some(offset as usize);
+                    }
+                    let large = offsets + 4 * count + 8 * (offset & 0x7fff_ffff) as usize;
+                    let bytes: [u8; 8] = index.get(large..large + 8)?.try_into().ok()?;
+                    return Some(u64::from_be_bytes(bytes) as usize);
+                },
+            }
+        }
+        None
+    }
+}
+
+pub fn read_u32(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+/// Rebuilds an object from its base and a delta of copy and insert
+/// instructions.
+fn apply_delta(base: &[u8], delta: &[u8]) -> Option<Vec<u8>> {
+    let mut pos = 0;
+    let mut varint = || {
+        let mut value = 0usize;
+        let mut shift = 0;
+        loop {
+            let byte = *delta.get(pos)?;
+            pos += 1;
+            value |= ((byte & 0x7f) as usize) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+    };
+
+    if varint()? != base.len() {
+        return None;
+    }
+    let size = varint()?;
+    let mut result = Vec::with_capacity(size);
+
+    while pos < delta.len() {
+        let op = delta[pos];
+        pos += 1;
+        if op & 0x80 != 0 {
+            let mut field = |bits: u8, shifts: &[usize]| -> Option<usize> {
+                let mut value = 0;
+                for (i, shift) in shifts.iter().enumerate() {
+                    if bits & (1 << i) != 0 {
+                        value |= (*delta.get(pos)? as usize) << shift;
+                        pos += 1;
+                    }
+                }
+                Some(value)
+            };
+            let offset = field(op & 0x0f, &[0, 8, 16, 24])?;
+            let length = match field((op >> 4) & 0x07, &[0, 8, 16])? {
+                0 => 0x10000,
+                length => length,
+            };
+            result.extend_from_slice(base.get(offset..offset + length)?);
+        } else if op != 0 {
+            result.extend_from_slice(delta.get(pos..pos + op as usize)?);
+            pos += op as usize;
+        } else {
+            return None;
+        }
+    }
+
+    (result.len() == size).then_some(result)
+}
+
+/// Decompresses a zlib stream. `size` is a hint of the result's length.
+pub fn inflate<R: BufRead>(input: R, size: usize) -> Option<Vec<u8>> {
+    // The size comes from the object, so it is not trusted too far.
+    let mut output = Vec::with_capacity(size.min(1 << 20));
+    ZlibDecoder::new(input).read_to_end(&mut output).ok()?;
+    Some(output)
+}
+
+/// SHA-1 of a blob with this content, as git names it.
+pub fn blob_id(content: &[u8]) -> Oid {
+    let mut hasher = Sha1::new();
+    hasher.update(format!("blob {}\0", content.len()));
+    hasher.update(content);
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(oid: Oid) -> String {
+        to_hex(&oid)
+    }
+
+    #[test]
+    fn blob_ids_match_git() {
+        assert_eq!(hex(blob_id(b"")), "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391");
+        assert_eq!(hex(blob_id(b"hello\n")), "ce013625030ba8dba906f756967f9e9ca394464a");
+        let large: Vec<u8> = (0..70000u32).map(|i| (i * 7 % 251) as u8).collect();
+        assert_eq!(hex(blob_id(&large)), "57703b7c15e6cd2554c7b8951d902564bdfb8e35");
+    }
+
+    #[test]
+    fn oids_round_trip_through_hex() {
+        let hex = "ce013625030ba8dba906f756967f9e9ca394464a";
+        assert_eq!(to_hex(&parse_oid(hex).unwrap()), hex);
+        assert_eq!(parse_oid("ce01"), None);
+        assert_eq!(parse_oid(&"g".repeat(40)), None);
+    }
+
+    #[test]
+    fn inflate_stored_block() {
+        let compressed = [
+            0x78, 0x01, 0x01, 0x14, 0x00, 0xeb, 0xff, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x2c, 0x20, 0x73, 0x74, 0x6f, 0x72,
+            0x65, 0x64, 0x20, 0x62, 0x6c, 0x6f, 0x63, 0x6b, 0x0a, 0x4d, 0xf5, 0x07, 0x27,
+        ];
+        assert_eq!(inflate(compressed.as_slice(), 0).unwrap(), b"hello, stored block\n");
+    }
+
+    #[test]
+    fn inflate_fixed_block() {
+        let compressed = [
+            0x78, 0x01, 0x4b, 0x4c, 0x4a, 0x4e, 0x84, 0x21, 0x85, 0xb4, 0xcc, 0x8a, 0xd4, 0x14, 0x2e, 0x00, 0x47, 0x41,
+            0x06, 0xd3,
+        ];
+        assert_eq!(inflate(compressed.as_slice(), 0).unwrap(), b"abcabcabcabc fixed\n");
+    }
+
+    #[test]
+    fn inflate_dynamic_block() {
+        let compressed = [
+            0x78, 0xda, 0x45, 0x8e, 0x5b, 0x0a, 0x00, 0x31, 0x08, 0x03, 0xff, 0xbd, 0x65, 0x1e, 0xf7, 0x3f, 0xc3, 0x26,
+            0xb6, 0x65, 0x15, 0x8a, 0x1d, 0x06, 0x0d, 0x00, 0x01, 0x43, 0x80, 0xe9, 0x5b, 0x3c, 0x44, 0x0a, 0x5b, 0xc8,
+            0x39, 0x38, 0x3d, 0xd1, 0x49, 0xf5, 0xab, 0x43, 0x62, 0x39, 0xa8, 0xa2, 0x21, 0xaf, 0x47, 0x68, 0xf7, 0xb1,
+            0xac, 0xaf, 0x60, 0xd2, 0xd3, 0x21, 0xaa, 0x89, 0xe9, 0x02, 0xf5, 0x48, 0x54, 0xe5, 0x9c, 0x1a, 0xe4, 0xe5,
+            0xf0, 0xc6, 0xfa, 0x73, 0x0d, 0xd6, 0x58, 0xd8, 0xaa, 0x3a, 0x7c, 0x69, 0x73, 0xee, 0x03, 0x91, 0x7d, 0x48,
+            0x84,
+        ];
+        let content = inflate(compressed.as_slice(), 0).unwrap();
+        assert_eq!(content.len(), 200);
+        assert!(content.starts_with(b"aaacaa\nbaababaaaaaaa"));
+        assert_eq!(hex(blob_id(&content)), "f935c32f2ca1a756e3719ceff9f9f0fd0830d866");
+    }
+
+    #[test]
+    fn inflate_rejects_bad_input() {
+        assert_eq!(inflate([].as_slice(), 0), None);
+        assert_eq!(inflate([0x78, 0x02, 0x01].as_slice(), 0), None);
+        // A stored block longer than the data.
+        assert_eq!(inflate([0x78, 0x01, 0x01, 0x14, 0x00, 0xeb, 0xff, 0x68].as_slice(), 0), None);
+    }
+
+    #[test]
+    fn apply_delta_copies_and_inserts() {
+        let base = b"0123456789";
+        // Base size 10, result size 9: copy 4 bytes from 3, insert "xyz",
+        // copy 2 bytes from 0.
+        let delta = [10, 9, 0x91, 3, 4, 3, b'x', b'y', b'z', 0x90, 2];
+        assert_eq!(apply_delta(base, &delta).unwrap(), b"3456xyz01");
+    }
+
+    #[test]
+    fn apply_delta_copy_of_length_zero_is_64_kib() {
+        let base: Vec<u8> = (0..0x10010u32).map(|i| i as u8).collect();
+        // Varints for 0x10010 and 0x10000, then a copy with no offset or
+        // length bytes.
+        let delta = [0x90, 0x80, 0x04, 0x80, 0x80, 0x04, 0x80];
+        assert_eq!(apply_delta(&base, &delta).unwrap(), &base[..0x10000]);
+    }
+
+    #[test]
+    fn apply_delta_rejects_inconsistent_deltas() {
+        let base = b"0123456789";
+        assert_eq!(apply_delta(base, &[9, 1, 1, b'x']), None);
+        assert_eq!(apply_delta(base, &[10, 2, 1, b'x']), None);
+        assert_eq!(apply_delta(base, &[10, 1, 0]), None);
+        assert_eq!(apply_delta(base, &[10, 4, 0x91, 8, 4]), None);
+    }
+
+    #[test]
+    fn pack_index_lookup() {
+        let small = [0x10; 20];
+        let large = [0x80; 20];
+        let mut index = b"\xfftOc\0\0\0\x02".to_vec();
+        for byte in 0..256 {
+            let count = (byte >= 0x10) as u32 + (byte >= 0x80) as u32;
+            index.extend_from_slice(&count.to_be_bytes());
+        }
+        index.extend_from_slice(&small);
+        index.extend_from_slice(&large);
+        index.extend_from_slice(&[0; 8]);
+        index.extend_from_slice(&12u32.to_be_bytes());
+        index.extend_from_slice(&0x8000_0000u32.to_be_bytes());
+        index.extend_from_slice(&5_000_000_000u64.to_be_bytes());
+
+        let path = std::env::temp_dir().join(format!("rshell-pack-test-{}", std::process::id()));
+        fs::write(&path, b"PACK").unwrap();
+        let pack = Pack { index, data: fs::File::open(&path).unwrap() };
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(pack.find(&small), Some(12));
+        assert_eq!(pack.find(&large), Some(5_000_000_000));
+        assert_eq!(pack.find(&[0x11; 20]), None);
+        assert_eq!(pack.find(&[0xff; 20]), None);
+    }
+
+    fn compress(data: &[u8]) -> Vec<u8> {
+        use std::io::Write;
+        let mut encoder = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn packed_objects_and_deltas() {
+        let blob = blob_id(b"hello\n");
+        let mut data = b"PACK\0\0\0\x02\0\0\0\x02".to_vec();
+        // A blob of 6 bytes, then a delta of 12 bytes against it.
+        data.push(0x36);
+        data.extend_from_slice(&compress(b"hello\n"));
+        let delta_offset = data.len();
+        data.extend_from_slice(&[0x6c, (delta_offset - 12) as u8]);
+        data.extend_from_slice(&compress(&[6, 12, 0x90, 5, 7, b' ', b'w', b'o', b'r', b'l', b'd', b'\n']));
+
+        let path = std::env::temp_dir().join(format!("rshell-pack-objects-test-{}", std::process::id()));
+        fs::write(&path, &data).unwrap();
+        let pack = Pack { index: vec![], data: fs::File::open(&path).unwrap() };
+        let _ = fs::remove_file(&path);
+        let objects = Objects { directories: vec![], packs: vec![] };
+
+        assert_eq!(objects.read_packed(&pack, 12, 0), Some((Kind::Blob, b"hello\n".to_vec())));
+        assert_eq!(objects.read_packed(&pack, delta_offset, 0), Some((Kind::Blob, b"hello world\n".to_vec())));
+        assert_eq!(objects.read_packed(&pack, data.len(), 0), None);
+        assert_eq!(objects.read(&blob), None);
+    }
+}
@@ -0,0 +1,278 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read};
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use regex::Regex;
+
+/// How long a command may take to print its help.
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// An option of an external command, as listed by its `--help`.
+#[derive(Clone)]
+pub struct Flag {
+    /// The option, ending with `=` when it takes a value written that way.
+    pub name: String,
+    pub description: String
+}
+
+thread_local! {
+    static CACHE: RefCell<HashMap<PathBuf, (SystemTime, Vec<Flag>)>> = RefCell::new(HashMap::new());
+}
+
+/// The options of the program at `binary`. Its `--help` is run once per
+/// version of the file: the result is kept in memory and in `cache_dir`.
+pub fn command_flags(binary: &Path, cache_dir: Option<&Path>) -> Vec<Flag> {
+    let Ok(modified) = fs::metadata(binary).and_then(|metadata| metadata.modified()) else {
+        return vec![];
+    };
+
+    let cached = CACHE.with(|cache| {
+        cache.borrow().get(binary).filter(|(time, _)| *time == modified).map(|(_, flags)| flags.clone())
+    });
+    if let Some(flags) = cached {
+        return flags;
+    }
+
+    let cache_file = cache_dir.map(|dir| dir.join(binary.to_string_lossy().replace('/', "%")));
+    let flags = match cache_file.as_deref().and_then(|file| read_cache(file, modified)) {
+        Some(flags) => flags,
+        None => {
+            // A run that timed out is not remembered, so it is tried again.
+            let Some(help) = run_help(binary) else {
+                return vec![];
+            };
+            let flags = parse_help(&help);
+            if let Some(file) = &cache_file {
+                write_cache(file, modified, &flags);
+            }
+            flags
+        },
+    };
+
+    CACHE.with(|cache| cache.borrow_mut().insert(binary.to_path_buf(), (modified, flags.clone())));
+    flags
+}
+
+/// Runs `binary --help` with nothing to read and no pager, in its own
+/// process group so that it is killed with its children if it hangs.
+/// Returns what it printed on both streams, or `None` if it could not be
+/// run or did not finish in time, including when a process it left behind
+/// keeps its output open.
+fn run_help(binary: &Path) -> Option<String> {
+    let child = Command::new(binary)
+        .arg("--help")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .env("PAGER", "cat")
+        .env("MANPAGER", "cat")
+        .env("GIT_PAGER", "cat")
+        .env("TERM", "dumb")
+        .env("NO_COLOR", "1")
+        .process_group(0)
+        .spawn();
+    let mut child = child.ok()?;
+    let group = child.id() as libc::pid_t;
+
+    let deadline = Instant::now() + TIMEOUT;
+    let mut streams: Vec<(fs::File, Vec<u8>)> = [child.stdout.take().map(OwnedFd::from), child.stderr.take().map(OwnedFd::from)]
+        .into_iter()
+        .flatten()
+        .map(|fd| (fs::File::from(fd), vec![]))
+        .collect();
+
+    if !read_streams(&mut streams, deadline) || !wait_until(&mut child, deadline) {
+        unsafe { libc::killpg(group, libc::SIGKILL) };
+        let _ = child.wait();
+        return None;
+    }
+
+    let output: Vec<String> = streams.iter().map(|(_, text)| String::from_utf8_lossy(text).to_string()).collect();
+    Some(output.join("\n"))
+}
+
+/// Reads `streams` to their end as data comes on any of them, waiting with
+/// `poll` until `deadline`. Returns whether they all ended in time.
+fn read_streams(streams: &mut [(fs::File, Vec<u8>)], deadline: Instant) -> bool {
+    let mut open: Vec<usize> = (0..streams.len()).collect();
+    let mut buffer = [0u8; 4096];
+
+    while !open.is_empty() {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return false;
+        }
+
+        let mut fds: Vec<libc::pollfd> = open
+            .iter()
+            .map(|&i| libc::pollfd { fd: streams[i].0.as_raw_fd(), events: libc::POLLIN, revents: 0 })
+            .collect();
+        let timeout = remaining.as_millis().clamp(1, i32::MAX as u128) as libc::c_int;
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) } < 0 {
+            if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return false;
+        }
+
+        let mut closed = vec![];
+        for (fd, &i) in fds.iter().zip(&open) {
+            if fd.revents == 0 {
+                continue;
+            }
+            let (file, text) = &mut streams[i];
+            match file.read(&mut buffer) {
+                Ok(0) => closed.push(i),
+                Ok(count) => text.extend_from_slice(&buffer[..count]),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {},
+                Err(_) => closed.push(i),
+            }
+        }
+        open.retain(|i| !closed.contains(i));
+    }
+    true
+}
+
+fn wait_until(child: &mut Child, deadline: Instant) -> bool {
+    loop {
+        match child.try_wait() {
+            Ok(Some(_)) => return true,
+            Ok(None) if Instant::now() < deadline => thread::sleep(Duration::from_millis(10)),
+            _ => return false,
+        }
+    }
+}
+
+/// Reads the options listed in a help text: lines starting with one or
+/// more options, followed by their description on the same line or the
+/// next one.
+fn parse_help(text: &str) -> Vec<Flag> {
+    let Ok(line_pattern) = Regex::new(r"^\s{0,30}(-[^\s].*?)(?:\s{2,}|\t|$)(.*)$") else {
+        return vec![];
+    };
+    let Ok(option_pattern) = Regex::new(r"(?:^|[\s,|/])(--?[A-Za-z0-9?][A-Za-z0-9_.+-]*)(=|\[=)?") else {
+        return vec![];
+    };
+
+    let lines: Vec<&str> = text.lines().collect();
+    let mut flags: Vec<Flag> = vec![];
+
+    for (i, line) in lines.iter().enumerate() {
+        let Some(captures) = line_pattern.captures(line) else { continue };
+        let options = &captures[1];
+        let mut description = captures[2].trim().to_string();
+        if description.is_empty() {
+            // The description may start on the next line.
+            description = lines
+                .get(i + 1)
+                .filter(|next| next.starts_with(char::is_whitespace) && !next.trim_start().starts_with('-'))
+                .map(|next| next.trim().to_string())
+                .unwrap_or_default();
+        }
+
+        for option in option_pattern.captures_iter(options) {
+            let mut name = option[1].to_string();
+            if name == "--" || name.ends_with('-') {
+                continue;
+            }
+            if option.get(2).is_some_and(|sign| sign.as_str() == "=") {
+                name.push('=');
+            }
+            if !flags.iter().any(|flag| flag.name == name) {
+                flags.push(Flag { name, description: description.clone() });
+            }
+        }
+    }
+
+    flags
+}
+
+fn read_cache(file: &Path, modified: SystemTime) -> Option<Vec<Flag>> {
+    let text = fs::read_to_string(file).ok()?;
+    let mut lines = text.lines();
+    if lines.next()? != timestamp(modified) {
+        return None;
+    }
+
+    Some(lines
+        .filter_map(|line| line.split_once('\t'))
+        .map(|(name, description)| Flag { name: name.to_string(), description: description.to_string() })
+        .collect())
+}
+
+fn write_cache(file: &Path, modified: SystemTime, flags: &[Flag]) {
+    let mut text = timestamp(modified);
+    for flag in flags {
+        text.push_str(&format!("\n{}\t{}", flag.name, flag.description.replace(['\t', '\n'], " ")));
+    }
+
+    if let Some(dir) = file.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    let _ = fs::write(file, text);
+}
+
+fn timestamp(time: SystemTime) -> String {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!("{}.{:09}", since.as_secs(), since.subsec_nanos())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn script(name: &str, body: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rshell-help-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join(name);
+        fs::write(&file, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o755)).unwrap();
+        file
+    }
+
+    #[test]
+    fn parse_help_reads_options_and_descriptions() {
+        let flags = parse_help("Usage: tool [OPTION]\n  -a, --all        show everything\n      --color=WHEN  colorize\n");
+        let names: Vec<&str> = flags.iter().map(|flag| flag.name.as_str()).collect();
+        assert_eq!(names, ["-a", "--all", "--color="]);
+        assert_eq!(flags[1].description, "show everything");
+    }
+
+    #[test]
+    fn finished_runs_are_cached() {
+        let binary = script("quick", "echo '  --fast  go quickly'");
+        let cache_dir = binary.with_file_name("cache-quick");
+        assert_eq!(command_flags(&binary, Some(&cache_dir))[0].name, "--fast");
+        assert!(fs::read_dir(&cache_dir).unwrap().next().is_some());
+    }
+
+    #[test]
+    fn timed_out_runs_are_not_cached() {
+        let cache_dir = std::env::temp_dir().join(format!("rshell-help-{}", std::process::id())).join("cache-slow");
+        for (name, body) in [("hang", "sleep 5"), ("linger", "echo '  --fast  go quickly'\nsleep 5 &")] {
+            let binary = script(name, body);
+            let started = Instant::now();
+            assert!(command_flags(&binary, Some(&cache_dir)).is_empty());
+            assert!(started.elapsed() < Duration::from_secs(3));
+            assert!(CACHE.with(|cache| !cache.borrow().contains_key(&binary)));
+        }
+        assert!(!cache_dir.exists());
+    }
+
+    #[test]
+    fn both_streams_are_read_while_the_command_runs() {
+        // More than a pipe holds, on each stream.
+        let binary = script("verbose", "i=0\nwhile [ $i -lt 3000 ]; do echo \"  --opt$i  option $i\"; echo padding padding padding >&2; i=$((i + 1)); done");
+        let help = run_help(&binary).unwrap();
+        assert_eq!(parse_help(&help).len(), 3000);
+        assert!(help.ends_with("padding padding padding\n"));
+    }
+}
@@ -0,0 +1,400 @@
+use crate::lexer::{is_name, scan_backtick, scan_dollar, scan_double_quote, scan_single_quote, Lexer, Token};
+use crate::parser::is_reserved;
+
+/// Colours of the line editor, as SGR parameters. They can be changed with
+/// `RSHELL_COLORS`, e.g. `command=1;32:unknown=4;31`.
+#[derive(Clone)]
+pub struct Styles {
+    pub enabled: bool,
+    pub command: String,
+    pub unknown: String,
+    pub keyword: String,
+    pub string: String,
+    pub variable: String,
+    pub operator: String,
+    pub redirect: String,
+    pub comment: String,
+    pub matching: String,
+    pub hint: String
+}
+
+impl Styles {
+    /// Default styles, adjusted by `RSHELL_COLORS` and turned off when
+    /// `NO_COLOR` is set to a non-empty value or the terminal is dumb.
+    pub fn from_env(no_color: Option<&str>, term: Option<&str>, colors: Option<&str>) -> Styles {
+        let mut styles = Styles {
+            enabled: no_color.filter(|value| !value.is_empty()).is_none() && !matches!(term, None | Some("" | "dumb")),
+            command: "32".to_string(),
+            unknown: "31".to_string(),
+            keyword: "35".to_string(),
+            string: "33".to_string(),
+            variable: "36".to_string(),
+            operator: "1".to_string(),
+            redirect: "34".to_string(),
+            comment: "90".to_string(),
+            matching: "7".to_string(),
+            hint: "2".to_string()
+        };
+
+        for setting in colors.unwrap_or_default().split(':') {
+            let Some((name, value)) = setting.split_once('=') else { continue };
+            let slot = match name {
+                "command" => &mut styles.command,
+                "unknown" => &mut styles.unknown,
+                "keyword" => &mut styles.keyword,
+                "string" => &mut styles.string,
+                "variable" => &mut styles.variable,
+                "operator" => &mut styles.operator,
+                "redirect" => &mut styles.redirect,
+                "comment" => &mut styles.comment,
+                "match" => &mut styles.matching,
+                "hint" => &mut styles.hint,
+                _ => continue,
+            };
+            *slot = value.to_string();
+        }
+
+        styles
+    }
+
+    pub fn paint(&self, style: &str, text: &str) -> String {
+        if !self.enabled || style.is_empty() {
+            return text.to_string();
+        }
+        format!("\x1b[{}m{}\x1b[0m", style, text)
+    }
+}
+
+/// Colours `line` from the tokens of the shell lexer. `is_command` tells
+/// whether a word in command position names something that can run. The
+/// bracket or quote under the cursor at `pos` and its match are marked.
+/// `previous` holds the lines already read of an unfinished command, which
+/// give the context of `line` but are not part of the result.
+pub fn highlight<F: Fn(&str) -> bool>(previous: &str, line: &str, pos: usize, styles: &Styles, is_command: F) -> String {
+    let offset = if previous.is_empty() { 0 } else { previous.chars().count() + 1 };
+    let text = if previous.is_empty() { line.to_string() } else { format!("{}\n{}", previous, line) };
+    let pos = offset + line[..pos.min(line.len())].chars().count();
+
+    let chars: Vec<char> = text.chars().collect();
+    let mut marks: Vec<Option<&str>> = vec![None; chars.len()];
+    let mut lexer = Lexer::new(&text);
+
+    let mut command_position = true;
+    let mut redirect_target = false;
+    let mut here_document: Option<bool> = None;
+    let mut covered = 0;
+
+    loop {
+        let (token, start, end) = match lexer.next_spanned() {
+            Ok((Token::Eof, _, _)) => break,
+            Ok(spanned) => spanned,
+            Err(_) => {
+                // Unfinished input: colour what is left as a word.
+                let start = chars[covered..].iter().position(|c| !c.is_whitespace()).map_or(chars.len(), |i| covered + i);
+                mark_word(&chars, start, chars.len(), styles, &mut marks);
+                break;
+            },
+        };
+        mark_comments(&chars, covered, start, styles, &mut marks);
+        covered = end;
+
+        match token {
+            Token::Word(word) => {
+                if redirect_target {
+                    redirect_target = false;
+                    mark_word(&chars, start, end, styles, &mut marks);
+                    if let Some(strip_tabs) = here_document.take() {
+                        // The body is skipped with the next newline.
+                        let _ = lexer.read_here_document(&word, strip_tabs);
+                    }
+                } else if command_position {
+                    let (style, next) = command_style(&word, styles, &is_command);
+                    if style.is_empty() {
+                        mark_word(&chars, start, end, styles, &mut marks);
+                    } else {
+                        fill(&mut marks, start, end, style);
+                    }
+                    command_position = next;
+                } else {
+                    mark_word(&chars, start, end, styles, &mut marks);
+                }
+            },
+            Token::Operator(op) => {
+                fill(&mut marks, start, end, &styles.operator);
+                if op == "((" {
+                    // Arithmetic is not made of words.
+                    if lexer.read_arithmetic().is_err() {
+                        break;
+                    }
+                    covered = lexer.offset();
+                    fill(&mut marks, covered - 2, covered, &styles.operator);
+                    command_position = false;
+                    continue;
+                }
+                command_position = true;
+            },
+            Token::Redirect(op) => {
+                fill(&mut marks, start, end, &styles.redirect);
+                redirect_target = true;
+                if op.ends_with("<<") || op.ends_with("<<-") {
+                    here_document = Some(op.ends_with('-'));
+                }
+            },
+            Token::Newline => {
+                fill(&mut marks, start + 1, end, &styles.string);
+                command_position = true;
+            },
+            Token::Eof => break,
+        }
+    }
+    mark_comments(&chars, covered, chars.len(), styles, &mut marks);
+
+    for index in matching_pair(&chars, pos) {
+        marks[index] = Some(&styles.matching);
+    }
+
+    render(&chars[offset..], &marks[offset..], styles)
+}
+
+/// Style of a word in command position, and whether the next word is in
+/// command position too.
+fn command_style<'s, F: Fn(&str) -> bool>(word: &str, styles: &'s Styles, is_command: &F) -> (&'s str, bool) {
+    if is_reserved(word) || word == "!" {
+        let next = !matches!(word, "for" | "case" | "function" | "[[" | "in");
+        return (&styles.keyword, next);
+    }
+    if word.split_once('=').is_some_and(|(name, _)| is_name(name)) {
+        return ("", true);
+    }
+    if word.contains(['$', '\'', '"', '`', '\\']) {
+        return ("", false);
+    }
+
+    if is_command(word) { (&styles.command, false) } else { (&styles.unknown, false) }
+}
+
+/// Marks the quoted strings and expansions inside the word at `start..end`.
+fn mark_word<'s>(chars: &[char], start: usize, end: usize, styles: &'s Styles, marks: &mut [Option<&'s str>]) {
+    let mut i = start;
+
+    while i < end {
+        match chars[i] {
+            '\\' => i += 2,
+            '\'' => {
+                let close = scan_single_quote(chars, i + 1).map_or(end, |close| close + 1).min(end);
+                fill(marks, i, close, &styles.string);
+                i = close;
+            },
+            '"' => {
+                let close = scan_double_quote(chars, i + 1).map_or(end, |close| close + 1).min(end);
+                fill(marks, i, close, &styles.string);
+                let mut j = i + 1;
+                while j < close {
+                    match chars[j] {
+                        '\\' => j += 2,
+                        '$' | '`' => j = mark_expansion(chars, j, close, styles, marks),
+                        _ => j += 1,
+                    }
+                }
+                i = close;
+            },
+            '$' | '`' => i = mark_expansion(chars, i, end, styles, marks),
+            _ => i += 1,
+        }
+    }
+}
+
+/// Marks the expansion starting at `i` and returns the index after it.
+fn mark_expansion<'s>(chars: &[char], i: usize, end: usize, styles: &'s Styles, marks: &mut [Option<&'s str>]) -> usize {
+    let close = if chars[i] == '`' {
+        scan_backtick(chars, i + 1).map_or(end, |close| close + 1)
+    } else {
+        match scan_dollar(chars, i) {
+            Ok(close) if close == i + 1 => {
+                // `$name` or a special parameter.
+                match chars.get(i + 1) {
+                    Some(c) if c.is_ascii_alphabetic() || *c == '_' => {
+                        let mut j = i + 1;
+                        while j < end && (chars[j].is_ascii_alphanumeric() || chars[j] == '_') {
+                            j += 1;
+                        }
+                        j
+                    },
+                    Some(c) if c.is_ascii_digit() || "?$#@*!-".contains(*c) => i + 2,
+                    _ => i + 1,
+                }
+            },
+            Ok(close) => close,
+            Err(_) => end,
+        }
+    };
+
+    let close = close.min(end);
+    if close > i + 1 {
+        fill(marks, i, close, &styles.variable);
+    }
+    close.max(i + 1)
+}
+
+/// Comments are skipped by the lexer, so they show up between tokens.
+fn mark_comments<'s>(chars: &[char], start: usize, end: usize, styles: &'s Styles, marks: &mut [Option<&'s str>]) {
+    let gap = start..end.min(chars.len());
+    if let Some(hash) = gap.clone().find(|&i| chars[i] == '#' && (i == 0 || chars[i - 1].is_whitespace())) {
+        let line_end = (hash..gap.end).find(|&i| chars[i] == '\n').unwrap_or(gap.end);
+        fill(marks, hash, line_end, &styles.comment);
+    }
+}
+
+fn fill<'s>(marks: &mut [Option<&'s str>], start: usize, end: usize, style: &'s str) {
+    let end = end.min(marks.len());
+    for mark in &mut marks[start.min(end)..end] {
+        *mark = Some(style);
+    }
+}
+
+/// The bracket or quote at the cursor (or just before it) and the one that
+/// closes or opens it.
+fn matching_pair(chars: &[char], pos: usize) -> Vec<usize> {
+    let candidates = [pos, pos.wrapping_sub(1)];
+    let Some(&at) = candidates.iter().find(|&&i| chars.get(i).is_some_and(|c| "()[]{}'\"".contains(*c))) else {
+        return vec![];
+    };
+
+    let c = chars[at];
+    let other = match c {
+        '(' | '[' | '{' => {
+            let close = match c { '(' => ')', '[' => ']', _ => '}' };
+            let mut depth = 0;
+            (at..chars.len()).find(|&i| {
+                if chars[i] == c { depth += 1 } else if chars[i] == close { depth -= 1 }
+                depth == 0
+            })
+        },
+        ')' | ']' | '}' => {
+            let open = match c { ')' => '(', ']' => '[', _ => '{' };
+            let mut depth = 0;
+            (0..=at).rev().find(|&i| {
+                if chars[i] == c { depth += 1 } else if chars[i] == open { depth -= 1 }
+                depth == 0
+            })
+        },
+        quote => {
+            // Pair quotes from the start of the line to know whether this
+            // one opens or closes.
+            let mut open: Option<usize> = None;
+            let mut pair = None;
+            for (i, &ch) in chars.iter().enumerate() {
+                if ch != quote || (i > 0 && chars[i - 1] == '\\' && quote == '"') {
+                    continue;
+                }
+                match open.take() {
+                    Some(start) if i == at || start == at => {
+                        pair = Some(if i == at { start } else { i });
+                        break;
+                    },
+                    Some(_) => {},
+                    None => open = Some(i),
+                }
+            }
+            pair
+        },
+    };
+
+    match other {
+        Some(other) if other != at => vec![at, other],
+        _ => vec![],
+    }
+}
+
+fn render(chars: &[char], marks: &[Option<&str>], styles: &Styles) -> String {
+    let mut output = String::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let style = marks[i];
+        let start = i;
+        while i < chars.len() && marks[i] == style {
+            i += 1;
+        }
+        let text: String = chars[start..i].iter().collect();
+        match style {
+            Some(style) => output.push_str(&styles.paint(style, &text)),
+            None => output.push_str(&text),
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NAMES: [&str; 10] = ["command", "unknown", "keyword", "string", "variable", "operator", "redirect", "comment", "match", "hint"];
+
+    /// Highlights `line` after the lines in `previous` with each style named
+    /// after itself, then shows the styles as `<name:text>`.
+    fn marked(previous: &str, line: &str, pos: usize) -> String {
+        let colors = NAMES.map(|name| format!("{}={}", name, name)).join(":");
+        let styles = Styles::from_env(None, Some("xterm"), Some(&colors));
+        let mut output = highlight(previous, line, pos, &styles, |word| word == "echo" || word == "ls");
+        for name in NAMES {
+            output = output.replace(&format!("\x1b[{}m", name), &format!("<{}:", name));
+        }
+        output.replace("\x1b[0m", ">")
+    }
+
+    #[test]
+    fn colour_can_be_turned_off() {
+        assert!(Styles::from_env(None, Some("xterm"), None).enabled);
+        assert!(Styles::from_env(Some(""), Some("xterm"), None).enabled);
+        assert!(!Styles::from_env(Some("1"), Some("xterm"), None).enabled);
+        assert!(!Styles::from_env(None, Some("dumb"), None).enabled);
+        assert!(!Styles::from_env(None, None, None).enabled);
+
+        let styles = Styles::from_env(Some("1"), Some("xterm"), None);
+        assert_eq!(highlight("", "echo $HOME", 0, &styles, |_| true), "echo $HOME");
+    }
+
+    #[test]
+    fn colours_can_be_changed() {
+        let styles = Styles::from_env(None, Some("xterm"), Some("command=1;32:bogus=1:unknown=4;31"));
+        assert_eq!(styles.command, "1;32");
+        assert_eq!(styles.unknown, "4;31");
+        assert_eq!(styles.keyword, "35");
+    }
+
+    #[test]
+    fn commands_and_keywords() {
+        assert_eq!(marked("", "echo hi", 0), "<command:echo> hi");
+        assert_eq!(marked("", "nope hi", 0), "<unknown:nope> hi");
+        assert_eq!(marked("", "x=1 ls | nope", 0), "x=1 <command:ls> <operator:|> <unknown:nope>");
+        assert_eq!(marked("", "if ls; then echo; fi", 0), "<keyword:if> <command:ls><operator:;> <keyword:then> <command:echo><operator:;> <keyword:fi>");
+        assert_eq!(marked("", "for ls in a", 0), "<keyword:for> ls in a");
+    }
+
+    #[test]
+    fn strings_expansions_and_redirections() {
+        assert_eq!(marked("", "echo 'a $b'", 0), "<command:echo> <string:'a $b'>");
+        assert_eq!(marked("", "echo \"a $b\"", 0), "<command:echo> <string:\"a ><variable:$b><string:\">");
+        assert_eq!(marked("", "echo ${x:-y} $(ls) $?", 0), "<command:echo> <variable:${x:-y}> <variable:$(ls)> <variable:$?>");
+        assert_eq!(marked("", "ls >out 2>&1", 0), "<command:ls> <redirect:>>out <redirect:2>&>1");
+        assert_eq!(marked("", "ls # note", 0), "<command:ls> <comment:# note>");
+        assert_eq!(marked("", "echo 'open", 0), "<command:echo> <string:'open>");
+    }
+
+    #[test]
+    fn brackets_and_quotes_are_matched_at_the_cursor() {
+        assert_eq!(marked("", "(ls)", 0), "<match:(><command:ls><match:)>");
+        assert_eq!(marked("", "(ls)", 4), "<match:(><command:ls><match:)>");
+        assert_eq!(marked("", "echo 'a' 'b'", 9), "<command:echo> <string:'a'> <match:'><string:b><match:'>");
+        assert_eq!(marked("", "(ls", 0), "<operator:(><command:ls>");
+    }
+
+    #[test]
+    fn continuation_lines_keep_their_context() {
+        assert_eq!(marked("echo 'a\n", "b' ls", 99), "<string:b'> ls");
+        assert_eq!(marked("echo a |\n", "ls", 99), "<command:ls>");
+        assert_eq!(marked("if ls\n", "then echo", 99), "<keyword:then> <command:echo>");
+    }
+}
@@ -0,0 +1,358 @@
+use std::ffi::CStr;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::history::with_lock;
+
+/// A command run in the interactive shell, as stored in the history
+/// database: one JSON object per line.
+pub struct Record {
+    pub time: i64,
+    pub duration_ms: i64,
+    pub cwd: String,
+    pub host: String,
+    pub status: i32,
+    pub command: String
+}
+
+/// Conditions of a `history` query. Unset fields match every record.
+#[derive(Default)]
+pub struct Query {
+    pub cwd: Option<String>,
+    pub host: Option<String>,
+    pub status: Option<i32>,
+    pub failed: bool,
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    pub text: Option<String>,
+    pub limit: Option<usize>
+}
+
+impl Query {
+    fn matches(&self, record: &Record) -> bool {
+        self.cwd.as_ref().map_or(true, |cwd| record.cwd == *cwd)
+            && self.host.as_ref().map_or(true, |host| record.host == *host)
+            && self.status.map_or(true, |status| record.status == status)
+            && (!self.failed || record.status != 0)
+            && self.since.map_or(true, |since| record.time >= since)
+            && self.until.map_or(true, |until| record.time < until)
+            && self.text.as_ref().map_or(true, |text| record.command.contains(text.as_str()))
+    }
+}
+
+impl Record {
+    fn to_json(&self) -> String {
+        format!(
+            "{{\"time\":{},\"duration_ms\":{},\"cwd\":{},\"host\":{},\"status\":{},\"command\":{}}}\n",
+            self.time, self.duration_ms, json_string(&self.cwd), json_string(&self.host), self.status, json_string(&self.command)
+        )
+    }
+
+    fn from_json(line: &str) -> Option<Record> {
+        let mut record = Record {
+            time: 0,
+            duration_ms: 0,
+            cwd: String::new(),
+            host: String::new(),
+            status: 0,
+            command: String::new()
+        };
+
+        for (key, value) in parse_object(line)? {
+            match (key.as_str(), value) {
+                ("time", Value::Number(n)) => record.time = n,
+                ("duration_ms", Value::Number(n)) => record.duration_ms = n,
+                ("status", Value::Number(n)) => record.status = n as i32,
+                ("cwd", Value::String(s)) => record.cwd = s,
+                ("host", Value::String(s)) => record.host = s,
+                ("command", Value::String(s)) => record.command = s,
+                _ => {}
+            }
+        }
+        Some(record)
+    }
+
+    /// `date time  [status]  duration  cwd  command`, with the home
+    /// directory shortened to `~`.
+    pub fn display(&self, home: &str) -> String {
+        let cwd = match self.cwd.strip_prefix(home) {
+            Some(rest) if !home.is_empty() && (rest.is_empty() || rest.starts_with('/')) => format!("~{}", rest),
+            _ => self.cwd.clone(),
+        };
+        format!(
+            "{}  [{}]  {:>7}  {}  {}",
+            format_time(self.time), self.status, format_duration(self.duration_ms), cwd, self.command
+        )
+    }
+}
+
+pub fn append(path: &Path, record: &Record) -> io::Result<()> {
+    with_lock(path, false, |file| io::Write::write_all(file, record.to_json().as_bytes()))
+}
+
+/// Records of `path` matching `query`, oldest first. Lines that cannot be
+/// read are skipped.
+pub fn search(path: &Path, query: &Query) -> io::Result<Vec<Record>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err),
+    };
+
+    let mut records: Vec<Record> = content
+        .lines()
+        .filter_map(Record::from_json)
+        .filter(|record| query.matches(record))
+        .collect();
+
+    if let Some(limit) = query.limit {
+        records.drain(..records.len().saturating_sub(limit));
+    }
+    Ok(records)
+}
+
+pub fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or_default()
+}
+
+pub fn hostname() -> String {
+    let mut buffer = [0 as libc::c_char; 256];
+    if unsafe { libc::gethostname(buffer.as_mut_ptr(), buffer.len()) } != 0 {
+        return String::new();
+    }
+    buffer[buffer.len() - 1] = 0;
+    unsafe { CStr::from_ptr(buffer.as_ptr()) }.to_string_lossy().to_string()
+}
+
+pub fn local_time(time: i64) -> libc::tm {
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    let time = time as libc::time_t;
+    unsafe { libc::localtime_r(&time, &mut tm) };
+    tm
+}
+
+fn format_time(time: i64) -> String {
+    let tm = local_time(time);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        tm.tm_year + 1900, tm.tm_mon + 1, tm.tm_mday, tm.tm_hour, tm.tm_min, tm.tm_sec
+    )
+}
+
+fn format_duration(ms: i64) -> String {
+    match ms {
+        ms if ms < 1000 => format!("{}ms", ms),
+        ms if ms < 60_000 => format!("{:.1}s", ms as f64 / 1000.0),
+        ms => format!("{}m{:02}s", ms / 60_000, ms / 1000 % 60),
+    }
+}
+
+/// Parses the time given to `--since` and `--until`: `today`, `yesterday`,
+/// a duration ago (`30m`, `2h`, `3d`, `1w`), a date `YYYY-MM-DD` with an
+/// optional `HH:MM[:SS]`, or seconds since the epoch.
+pub fn parse_time(text: &str) -> Option<i64> {
+    let now = now();
+    let midnight = |days_ago: i64| {
+        let mut tm = local_time(now);
+        tm.tm_hour = 0;
+        tm.tm_min = 0;
+        tm.tm_sec = 0;
+        tm.tm_mday -= days_ago as i32;
+        tm.tm_isdst = -1;
+        unsafe { libc::mktime(&mut tm) as i64 }
+    };
+
+    match text {
+        "now" => return Some(now),
+        "today" => return Some(midnight(0)),
+        "yesterday" => return Some(midnight(1)),
+        _ => {}
+    }
+
+    if let Some(unit) = text.chars().last().filter(|c| c.is_ascii_alphabetic()) {
+        let amount: i64 = text[..text.len() - 1].parse().ok()?;
+        let seconds = match unit {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            'w' => 7 * 86400,
+            _ => return None,
+        };
+        return Some(now - amount * seconds);
+    }
+
+    if !text.contains('-') {
+        return text.parse().ok();
+    }
+
+    let (date, clock) = match text.split_once([' ', 'T']) {
+        Some((date, clock)) => (date, clock),
+        None => (text, "0:0:0"),
+    };
+    let date: Vec<i32> = date.split('-').map(|part| part.parse().ok()).collect::<Option<_>>()?;
+    let mut clock: Vec<i32> = clock.split(':').map(|part| part.parse().ok()).collect::<Option<_>>()?;
+    clock.resize(3, 0);
+
+    let [year, month, day] = date[..] else { return None };
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    tm.tm_year = year - 1900;
+    tm.tm_mon = month - 1;
+    tm.tm_mday = day;
+    tm.tm_hour = clock[0];
+    tm.tm_min = clock[1];
+    tm.tm_sec = clock[2];
+    tm.tm_isdst = -1;
+    Some(unsafe { libc::mktime(&mut tm) as i64 })
+}
+
+fn json_string(text: &str) -> String {
+    let mut json = String::with_capacity(text.len() + 2);
+    json.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\t' => json.push_str("\\t"),
+            '\r' => json.push_str("\\r"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+enum Value {
+    String(String),
+    Number(i64)
+}
+
+/// Parses a flat JSON object whose values are strings or integers, which
+/// is all the database holds.
+fn parse_object(line: &str) -> Option<Vec<(String, Value)>> {
+    let mut chars = line.trim().chars().peekable();
+    let mut fields = vec![];
+
+    if chars.next()? != '{' {
+        return None;
+    }
+
+    loop {
+        while chars.next_if(|c| c.is_whitespace() || *c == ',').is_some() {}
+        match chars.next()? {
+            '}' => return Some(fields),
+            '"' => {},
+            _ => return None,
+        }
+
+        let key = parse_string(&mut chars)?;
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.next()? != ':' {
+            return None;
+        }
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+        let value = if chars.next_if_eq(&'"').is_some() {
+            Value::String(parse_string(&mut chars)?)
+        } else {
+            let mut number = String::new();
+            while let Some(c) = chars.next_if(|c| c.is_ascii_digit() || *c == '-') {
+                number.push(c);
+            }
+            Value::Number(number.parse().ok()?)
+        };
+        fields.push((key, value));
+    }
+}
+
+/// Reads a JSON string whose opening quote was consumed.
+fn parse_string<I: Iterator<Item = char>>(chars: &mut I) -> Option<String> {
+    let mut text = String::new();
+
+    loop {
+        match chars.next()? {
+            '"' => return Some(text),
+            '\\' => match chars.next()? {
+                'n' => text.push('\n'),
+                't' => text.push('\t'),
+                'r' => text.push('\r'),
+                'b' => text.push('\u{8}'),
+                'f' => text.push('\u{c}'),
+                'u' => {
+                    let code: String = chars.by_ref().take(4).collect();
+                    text.push(char::from_u32(u32::from_str_radix(&code, 16).ok()?).unwrap_or('\u{fffd}'));
+                },
+                c => text.push(c),
+            },
+            c => text.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(command: &str) -> Record {
+        Record { time: 1700000000, duration_ms: 42, cwd: "/tmp/a \"b\"".to_string(), host: "host".to_string(), status: 130, command: command.to_string() }
+    }
+
+    #[test]
+    fn json_round_trips() {
+        for command in ["echo \"hi\"", "printf '%s\\n' a\\b", "a\nb\tc\rd", "bell\u{7}\u{1}\u{1f}", "café ☕ 🦀", ""] {
+            let json = record(command).to_json();
+            assert!(json.ends_with('\n') && !json.trim_end().contains('\n'), "{:?}", json);
+            let read = Record::from_json(&json).unwrap();
+            assert_eq!(read.command, command);
+            assert_eq!(read.cwd, "/tmp/a \"b\"");
+            assert_eq!((read.time, read.duration_ms, read.status), (1700000000, 42, 130));
+        }
+    }
+
+    #[test]
+    fn json_escapes() {
+        assert_eq!(json_string("a\"b\\c\n\u{1}é"), "\"a\\\"b\\\\c\\n\\u0001é\"");
+        assert_eq!(Record::from_json(r#"{"command": "é\/x", "status": -1}"#).map(|record| (record.command, record.status)), Some(("é/x".to_string(), -1)));
+        assert!(Record::from_json("not json").is_none());
+        assert!(Record::from_json(r#"{"command": "unterminated}"#).is_none());
+    }
+
+    #[test]
+    fn parse_time_formats() {
+        let before = now();
+        let ago = parse_time("30m").unwrap();
+        let after = now();
+        assert!((before - 1800..=after - 1800).contains(&ago));
+        assert!((before - 7 * 86400..=after - 7 * 86400).contains(&parse_time("1w").unwrap()));
+        assert_eq!(parse_time("1700000000"), Some(1700000000));
+
+        let day = parse_time("2024-01-02").unwrap();
+        assert_eq!(parse_time("2024-01-02 00:00"), Some(day));
+        assert_eq!(parse_time("2024-01-02T12:30"), Some(day + 45000));
+        assert_eq!(parse_time("2024-01-02 12:30:15"), Some(day + 45015));
+
+        let today = parse_time("today").unwrap();
+        assert!(today <= now() && now() - today < 86400 + 3600);
+        assert!((82800..=90000).contains(&(today - parse_time("yesterday").unwrap())));
+    }
+
+    #[test]
+    fn parse_time_rejects_garbage() {
+        for text in ["", "abc", "3x", "m", "2024-13", "2024-01-02 ab", "1.5h"] {
+            assert_eq!(parse_time(text), None, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn queries_filter_records() {
+        let query = Query { failed: true, text: Some("echo".to_string()), ..Default::default() };
+        assert!(query.matches(&record("echo hi")));
+        assert!(!query.matches(&record("ls")));
+        let query = Query { since: Some(1700000001), ..Default::default() };
+        assert!(!query.matches(&record("echo hi")));
+    }
+}
@@ -0,0 +1,587 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::fd::AsRawFd;
+use std::path::Path;
+
+use crate::lexer::{Lexer, Token};
+
+pub const DEFAULT_HISTSIZE: usize = 500;
+
+/// Commands entered in the interactive shell. Each entry is appended to the
+/// history file as soon as it is added, so concurrent sessions interleave
+/// their commands instead of overwriting each other on exit.
+#[derive(Clone, Default)]
+pub struct History {
+    pub entries: Vec<String>,
+    /// Set when entries were removed or read back, so that the line editor
+    /// reloads them instead of only taking the last one.
+    pub changed: bool
+}
+
+impl History {
+    pub fn add(&mut self, line: &str, file: Option<&Path>, size: usize) -> io::Result<()> {
+        if line.trim().is_empty() || self.entries.last().is_some_and(|last| last == line) {
+            return Ok(());
+        }
+
+        self.entries.push(line.to_string());
+        self.truncate(size);
+
+        match file {
+            Some(path) => with_lock(path, false, |file| file.write_all(encode(line).as_bytes())),
+            None => Ok(()),
+        }
+    }
+
+    /// Reads the entries of `path` after the current ones.
+    pub fn read(&mut self, path: &Path, size: usize) -> io::Result<()> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+
+        self.entries.extend(decode(&content));
+        self.truncate(size);
+        self.changed = true;
+        Ok(())
+    }
+
+    /// Replaces the content of `path` with the current entries.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let content: String = self.entries.iter().map(|entry| encode(entry)).collect();
+        with_lock(path, true, |file| file.write_all(content.as_bytes()))
+    }
+
+    /// Keeps the history file to its last `size` entries. The file stays
+    /// locked from the read to the rewrite, so that entries appended by
+    /// other sessions meanwhile are not lost.
+    pub fn trim_file(path: &Path, size: usize) -> io::Result<()> {
+        if !path.is_file() {
+            return Ok(());
+        }
+
+        with_lock(path, false, |file| {
+            let mut content = String::new();
+            file.read_to_string(&mut content)?;
+
+            let entries = decode(&content);
+            if entries.len() <= size {
+                return Ok(());
+            }
+
+            let content: String = entries[entries.len() - size..].iter().map(|entry| encode(entry)).collect();
+            file.set_len(0)?;
+            file.write_all(content.as_bytes())
+        })
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.changed = true;
+    }
+
+    /// Removes the entry at `offset`, counted from 1, or from the end when
+    /// negative.
+    pub fn delete(&mut self, offset: i64) -> bool {
+        let len = self.entries.len() as i64;
+        let index = if offset < 0 { len + offset } else { offset - 1 };
+
+        if index < 0 || index >= len {
+            return false;
+        }
+
+        self.entries.remove(index as usize);
+        self.changed = true;
+        true
+    }
+
+    fn truncate(&mut self, size: usize) {
+        if self.entries.len() > size {
+            self.entries.drain(..self.entries.len() - size);
+        }
+    }
+}
+
+/// Opens `path` for reading and appending, or truncated when `replace` is
+/// set, and runs `write` while holding an exclusive lock on it.
+pub fn with_lock<F>(path: &Path, replace: bool, write: F) -> io::Result<()>
+where
+    F: FnOnce(&mut fs::File) -> io::Result<()>
+{
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut file = OpenOptions::new().create(true).read(true).append(true).open(path)?;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    if replace {
+        file.set_len(0)?;
+    }
+    // The lock is released when the file is closed.
+    write(&mut file)
+}
+
+/// One entry per line; the newlines of multi-line entries are escaped with
+/// a backslash, and backslashes are doubled so that an entry ending with
+/// one is not taken for a continued one.
+fn encode(entry: &str) -> String {
+    format!("{}\n", entry.replace('\\', "\\\\").replace('\n', "\\\n"))
+}
+
+fn decode(content: &str) -> Vec<String> {
+    let mut entries = vec![];
+    let mut current: Option<String> = None;
+
+    for line in content.lines() {
+        let (line, continued) = unescape_line(line);
+
+        let entry = match current.take() {
+            Some(mut entry) => {
+                entry.push('\n');
+                entry.push_str(&line);
+                entry
+            },
+            None => line,
+        };
+
+        if continued {
+            current = Some(entry);
+        } else if !entry.is_empty() {
+            entries.push(entry);
+        }
+    }
+
+    entries.extend(current);
+    entries
+}
+
+/// Undoes the doubled backslashes of a line of the history file, and tells
+/// whether it ends with a single one, continuing the entry. A backslash
+/// before anything else is kept, as in files written by other shells.
+fn unescape_line(line: &str) -> (String, bool) {
+    let mut unescaped = String::with_capacity(line.len());
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', None) => return (unescaped, true),
+            ('\\', Some('\\')) => {
+                chars.next();
+                unescaped.push('\\');
+            },
+            (c, _) => unescaped.push(c),
+        }
+    }
+    (unescaped, false)
+}
+
+/// Result of a history expansion: the line to run, unless the `:p`
+/// modifier asked to only print it.
+pub struct Expanded {
+    pub line: String,
+    pub print_only: bool
+}
+
+/// Expands the history references of `line`: events (`!!`, `!n`, `!-n`,
+/// `!prefix`, `!?text?`), word designators (`!$`, `!^`, `!*`, `!:n-m`) and
+/// modifiers (`:h`, `:t`, `:r`, `:e`, `:p`, `:s/old/new/`, `:gs/old/new/`),
+/// as well as the `^old^new^` quick substitution. Nothing is expanded
+/// inside single quotes or after a backslash. Returns `None` when the line
+/// has no reference.
+pub fn expand(line: &str, entries: &[String]) -> Result<Option<Expanded>, String> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut expanded = String::new();
+    let mut print_only = false;
+    let mut changed = false;
+    let mut i = 0;
+
+    if chars.first() == Some(&'^') {
+        let event = entries.last().ok_or_else(|| "^: event not found".to_string())?;
+        let (text, end) = quick_substitution(&chars, event)?;
+        expanded.push_str(&text);
+        changed = true;
+        i = end;
+    }
+
+    let mut single = false;
+    let mut double = false;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '\'' if !double => single = !single,
+            '"' if !single => double = !double,
+            '\\' if !single => {
+                expanded.push(c);
+                if let Some(&next) = chars.get(i + 1) {
+                    expanded.push(next);
+                }
+                i += 2;
+                continue;
+            },
+            '!' if !single && is_reference(chars.get(i + 1).copied(), double) => {
+                let (text, end, print) = expand_reference(&chars, i + 1, entries)?;
+                expanded.push_str(&text);
+                print_only |= print;
+                changed = true;
+                i = end;
+                continue;
+            },
+            _ => {}
+        }
+        expanded.push(c);
+        i += 1;
+    }
+
+    Ok(changed.then_some(Expanded { line: expanded, print_only }))
+}
+
+fn is_reference(next: Option<char>, double: bool) -> bool {
+    match next {
+        None | Some(' ' | '\t' | '\n' | '=' | '(') => false,
+        Some('"') => !double,
+        Some(_) => true,
+    }
+}
+
+/// Expands the reference starting after a `!` at `start`. Returns the
+/// text, the offset after the reference and whether `:p` was given.
+fn expand_reference(chars: &[char], start: usize, entries: &[String]) -> Result<(String, usize, bool), String> {
+    let mut i = start;
+    let not_found = |spec: &[char]| format!("!{}: event not found", spec.iter().collect::<String>());
+
+    let event = match chars[i] {
+        '!' => {
+            i += 1;
+            entries.last()
+        },
+        '^' | '$' | '*' | '%' | ':' => entries.last(),
+        '?' => {
+            let text_start = i + 1;
+            i = text_start;
+            while i < chars.len() && chars[i] != '?' && chars[i] != '\n' {
+                i += 1;
+            }
+            let text: String = chars[text_start..i].iter().collect();
+            if chars.get(i) == Some(&'?') {
+                i += 1;
+            }
+            entries.iter().rev().find(|entry| entry.contains(&text))
+        },
+        c if c.is_ascii_digit() || c == '-' => {
+            let negative = c == '-';
+            if negative {
+                i += 1;
+            }
+            let digits_start = i;
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                i += 1;
+            }
+            let number: usize = chars[digits_start..i].iter().collect::<String>().parse()
+                .map_err(|_| not_found(&chars[start..i]))?;
+            let index = if negative { entries.len().checked_sub(number) } else { number.checked_sub(1) };
+            index.and_then(|index| entries.get(index))
+        },
+        _ => {
+            while i < chars.len() && !is_word_end(chars[i]) {
+                i += 1;
+            }
+            let prefix: String = chars[start..i].iter().collect();
+            entries.iter().rev().find(|entry| entry.starts_with(&prefix))
+        },
+    };
+    let event = event.ok_or_else(|| not_found(&chars[start..i.max(start + 1)]))?;
+
+    let words = split_words(event);
+    let mut text = event.clone();
+
+    // The colon may be left out before designators starting with ^ $ * - %.
+    let designator = match chars.get(i) {
+        Some(':') if chars.get(i + 1).is_some_and(|&c| c.is_ascii_digit() || matches!(c, '^' | '$' | '*' | '-' | '%')) => {
+            i += 1;
+            true
+        },
+        Some('^' | '$' | '*' | '-' | '%') => true,
+        _ => false,
+    };
+    if designator {
+        let (selected, end) = select_words(chars, i, &words)?;
+        text = selected;
+        i = end;
+    }
+
+    let mut print_only = false;
+    while chars.get(i) == Some(&':') {
+        match chars.get(i + 1) {
+            Some('h') => {
+                if let Some(slash) = text.rfind('/') {
+                    text.truncate(slash.max(1));
+                }
+                i += 2;
+            },
+            Some('t') => {
+                if let Some(slash) = text.rfind('/') {
+                    text = text[slash + 1..].to_string();
+                }
+                i += 2;
+            },
+            Some('r') => {
+                if let Some(dot) = text.rfind('.').filter(|&dot| !text[dot..].contains('/')) {
+                    text.truncate(dot);
+                }
+                i += 2;
+            },
+            Some('e') => {
+                text = match text.rfind('.').filter(|&dot| !text[dot..].contains('/')) {
+                    Some(dot) => text[dot..].to_string(),
+                    None => String::new(),
+                };
+                i += 2;
+            },
+            Some('p') => {
+                print_only = true;
+                i += 2;
+            },
+            Some('s') => {
+                let (substituted, end) = substitute(chars, i + 2, &text, false)?;
+                text = substituted;
+                i = end;
+            },
+            Some('g') if chars.get(i + 2) == Some(&'s') => {
+                let (substituted, end) = substitute(chars, i + 3, &text, true)?;
+                text = substituted;
+                i = end;
+            },
+            Some(&c) => return Err(format!("{}: unrecognized history modifier", c)),
+            None => break,
+        }
+    }
+
+    Ok((text, i, print_only))
+}
+
+fn is_word_end(c: char) -> bool {
+    c.is_whitespace() || matches!(c, ':' | ';' | '|' | '&' | '(' | ')' | '<' | '>' | '\'' | '"' | '`')
+}
+
+/// Words of a history entry, as the lexer splits them.
+fn split_words(entry: &str) -> Vec<String> {
+    let mut lexer = Lexer::new(entry);
+    let mut words = vec![];
+
+    loop {
+        match lexer.next_token() {
+            Ok(Token::Eof) => return words,
+            Ok(Token::Newline) => {},
+            Ok(token) => words.push(token.to_string()),
+            Err(_) => return entry.split_whitespace().map(|word| word.to_string()).collect(),
+        }
+    }
+}
+
+/// Reads a word designator at `start` and returns the selected words.
+fn select_words(chars: &[char], start: usize, words: &[String]) -> Result<(String, usize), String> {
+    let last = words.len().saturating_sub(1);
+    let bad = || "bad word specifier".to_string();
+    let mut i = start;
+
+    let number = |i: &mut usize| -> Option<usize> {
+        let digits_start = *i;
+        while *i < chars.len() && chars[*i].is_ascii_digit() {
+            *i += 1;
+        }
+        chars[digits_start..*i].iter().collect::<String>().parse().ok()
+    };
+
+    let (from, to) = match chars.get(i) {
+        Some('^') => {
+            i += 1;
+            (1, Some(1))
+        },
+        Some('$') => {
+            i += 1;
+            (last, Some(last))
+        },
+        Some('*') => {
+            i += 1;
+            if words.len() < 2 {
+                return Ok((String::new(), i));
+            }
+            (1, Some(last))
+        },
+        Some('-') => (0, None),
+        _ => {
+            let from = number(&mut i).ok_or_else(bad)?;
+            match chars.get(i) {
+                Some('*') => {
+                    i += 1;
+                    (from, Some(last))
+                },
+                Some('-') => (from, None),
+                _ => (from, Some(from)),
+            }
+        },
+    };
+
+    let to = match to {
+        Some(to) => to,
+        None => {
+            // A range: `x-y`, or `x-` which leaves out the last word.
+            i += 1;
+            match chars.get(i) {
+                Some('$') => {
+                    i += 1;
+                    last
+                },
+                Some(c) if c.is_ascii_digit() => number(&mut i).ok_or_else(bad)?,
+                _ => last.checked_sub(1).ok_or_else(bad)?,
+            }
+        },
+    };
+
+    if from > to || to > last || words.is_empty() {
+        return Err(bad());
+    }
+    Ok((words[from..=to].join(" "), i))
+}
+
+/// Applies `s/old/new/` at `start` (just after the `s`) to `text`. Any
+/// character can delimit the strings, the last delimiter may be left out
+/// at the end of the line and `&` in `new` stands for `old`.
+fn substitute(chars: &[char], start: usize, text: &str, global: bool) -> Result<(String, usize), String> {
+    let delimiter = *chars.get(start).ok_or_else(|| "no previous substitution".to_string())?;
+    let mut i = start + 1;
+
+    let read = |i: &mut usize| {
+        let mut part = String::new();
+        while *i < chars.len() && chars[*i] != delimiter {
+            if chars[*i] == '\\' && chars.get(*i + 1) == Some(&delimiter) {
+                *i += 1;
+            }
+            part.push(chars[*i]);
+            *i += 1;
+        }
+        if *i < chars.len() {
+            *i += 1;
+        }
+        part
+    };
+
+    let old = read(&mut i);
+    let new = read(&mut i).replace('&', &old);
+
+    if old.is_empty() {
+        return Err("no previous substitution".to_string());
+    }
+    if !text.contains(&old) {
+        return Err(format!(":s{}{}{}{}: substitution failed", delimiter, old, delimiter, new));
+    }
+
+    let text = if global { text.replace(&old, &new) } else { text.replacen(&old, &new, 1) };
+    Ok((text, i))
+}
+
+/// `^old^new^` at the start of a line: the last command with the first
+/// `old` replaced by `new`.
+fn quick_substitution(chars: &[char], event: &str) -> Result<(String, usize), String> {
+    substitute(chars, 0, event, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_survive_encoding() {
+        let entries = [
+            "echo foo \\",
+            "echo \\\\",
+            "for i in 1 2\ndo echo $i\ndone",
+            "echo a \\\nb",
+            "printf '%s\\n' x",
+        ];
+        let content: String = entries.iter().map(|entry| encode(entry)).collect();
+        assert_eq!(decode(&content), entries);
+    }
+
+    #[test]
+    fn single_backslashes_are_kept() {
+        assert_eq!(decode("printf '%s\\n' x\nif true\\\nthen :; fi\n"), ["printf '%s\\n' x", "if true\nthen :; fi"]);
+    }
+
+    #[test]
+    fn trim_file_keeps_the_last_entries() {
+        let path = std::env::temp_dir().join(format!("rshell-history-test-{}", std::process::id()));
+        let history = History { entries: ["one", "two \\", "three\nfour"].map(String::from).to_vec(), changed: false };
+        history.write(&path).unwrap();
+
+        History::trim_file(&path, 2).unwrap();
+        let mut read = History::default();
+        read.read(&path, 10).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(read.entries, ["two \\", "three\nfour"]);
+    }
+
+    fn history() -> Vec<String> {
+        ["cat /etc/hosts.txt", "git commit -m 'a b' --amend", "ls -l src/main.rs"].map(String::from).to_vec()
+    }
+
+    fn expanded(line: &str) -> String {
+        expand(line, &history()).unwrap().unwrap().line
+    }
+
+    #[test]
+    fn events() {
+        assert_eq!(expanded("!!"), "ls -l src/main.rs");
+        assert_eq!(expanded("sudo !!"), "sudo ls -l src/main.rs");
+        assert_eq!(expanded("!-2"), "git commit -m 'a b' --amend");
+        assert_eq!(expanded("!1"), "cat /etc/hosts.txt");
+        assert_eq!(expanded("!gi"), "git commit -m 'a b' --amend");
+        assert_eq!(expanded("!?hosts?"), "cat /etc/hosts.txt");
+        assert_eq!(expand("!nope", &history()).err().as_deref(), Some("!nope: event not found"));
+        assert_eq!(expand("!9", &history()).err().as_deref(), Some("!9: event not found"));
+    }
+
+    #[test]
+    fn word_designators() {
+        assert_eq!(expanded("vi !$"), "vi src/main.rs");
+        assert_eq!(expanded("echo !^"), "echo -l");
+        assert_eq!(expanded("echo !*"), "echo -l src/main.rs");
+        assert_eq!(expanded("echo !-2:1"), "echo commit");
+        assert_eq!(expanded("echo !-2:2-3"), "echo -m 'a b'");
+        assert_eq!(expanded("echo !:0"), "echo ls");
+        assert_eq!(expand("echo !:7", &history()).err().as_deref(), Some("bad word specifier"));
+    }
+
+    #[test]
+    fn modifiers() {
+        assert_eq!(expanded("cd !$:h"), "cd src");
+        assert_eq!(expanded("echo !$:t"), "echo main.rs");
+        assert_eq!(expanded("echo !$:r"), "echo src/main");
+        assert_eq!(expanded("echo !$:e"), "echo .rs");
+        assert_eq!(expanded("!!:s/main/lib/"), "ls -l src/lib.rs");
+        assert_eq!(expanded("!1:gs/t/T/"), "caT /eTc/hosTs.TxT");
+        assert_eq!(expand("!!:s/x/y/", &history()).err().as_deref(), Some(":s/x/y: substitution failed"));
+
+        let printed = expand("!!:p", &history()).unwrap().unwrap();
+        assert!(printed.print_only);
+        assert_eq!(printed.line, "ls -l src/main.rs");
+    }
+
+    #[test]
+    fn quick_substitutions() {
+        assert_eq!(expanded("^main^lib"), "ls -l src/lib.rs");
+        assert_eq!(expanded("^-l^-la^"), "ls -la src/main.rs");
+        assert!(expand("^x^y", &history()).is_err());
+    }
+
+    #[test]
+    fn quoted_and_plain_lines_are_left_alone() {
+        assert!(expand("echo '!!'", &history()).unwrap().is_none());
+        assert!(expand("echo \\!!", &history()).unwrap().is_none());
+        assert!(expand("echo hi! a != b", &history()).unwrap().is_none());
+        assert_eq!(expanded("echo \"!!\""), "echo \"ls -l src/main.rs\"");
+    }
+}
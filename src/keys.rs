@@ -0,0 +1,607 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use rustyline::{Anchor, At, Cmd, ConditionalEventHandler, Event, EventContext, EventHandler, KeyCode, KeyEvent, Modifiers, Movement, RepeatCount, Word};
+
+use crate::search::SearchTrigger;
+
+/// Files included from one another at most, to stop include loops.
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+/// Readline functions that can be bound to keys, for `bind -l`.
+pub const FUNCTIONS: [&str; 43] = [
+    "abort", "accept-line", "backward-char", "backward-delete-char", "backward-kill-line", "backward-kill-word",
+    "backward-word", "beginning-of-history", "beginning-of-line", "capitalize-word", "clear-screen", "complete",
+    "delete-char", "downcase-word", "edit-and-execute-command", "emacs-editing-mode", "end-of-history",
+    "end-of-line", "forward-char", "forward-search-history", "forward-word", "history-search-backward",
+    "history-search-forward", "kill-line", "kill-whole-line", "kill-word", "menu-complete", "next-history",
+    "previous-history", "quoted-insert", "re-read-init-file", "redraw-current-line", "reverse-search-history",
+    "tab-insert", "transpose-chars", "transpose-words", "undo", "unix-line-discard", "unix-word-rubout",
+    "upcase-word", "vi-editing-mode", "yank", "yank-pop",
+];
+
+/// What a key sequence is bound to.
+#[derive(Clone, PartialEq)]
+pub enum Binding {
+    /// A readline function, such as `kill-line`.
+    Function(String),
+    /// Text inserted as if typed, and accepted when it ends with a newline.
+    Macro(String),
+    /// A shell command, bound with `bind -x`.
+    Command(String)
+}
+
+/// Key bindings and readline variables, set by inputrc files and `bind`.
+/// Key sequences are kept in readline's notation, e.g. `"\C-x\C-e"`.
+#[derive(Clone, PartialEq)]
+pub struct KeyConfig {
+    pub bindings: Vec<(String, Vec<KeyEvent>, Binding)>,
+    pub variables: BTreeMap<String, String>
+}
+
+/// A line of an inputrc file.
+pub enum Directive {
+    Set(String, String),
+    Bind(String, Vec<KeyEvent>, Binding)
+}
+
+/// What a key bound to the shell asked for, carried out once the editor
+/// has returned.
+#[derive(Clone)]
+pub enum KeyAction {
+    /// Opens the line in `$VISUAL` or `$EDITOR`, then runs what was saved.
+    EditAndExecute,
+    /// Switches to the `vi` or `emacs` editing mode.
+    EditingMode(&'static str),
+    ReadInitFile,
+    /// Runs a shell command bound with `bind -x`.
+    Run(String),
+    /// Inserts the text of a macro ending with a newline, and runs the line.
+    Accept(String)
+}
+
+/// A key action with the line being edited and the cursor position.
+pub struct PendingKey {
+    pub action: KeyAction,
+    pub line: String,
+    pub pos: usize
+}
+
+pub type Pending = Arc<Mutex<Option<PendingKey>>>;
+
+/// Leaves the editor, which returns as if interrupted, with `action` to
+/// carry out.
+struct ShellKey(Pending, KeyAction);
+
+impl ConditionalEventHandler for ShellKey {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, ctx: &EventContext) -> Option<Cmd> {
+        if let Ok(mut pending) = self.0.lock() {
+            *pending = Some(PendingKey { action: self.1.clone(), line: ctx.line().to_string(), pos: ctx.pos() });
+        }
+        Some(Cmd::Interrupt)
+    }
+}
+
+/// Lets keys that start a bound sequence do what they would otherwise.
+/// The editor expects one of these on every prefix of a sequence, and
+/// panics on keys that follow a prefix and do not match.
+struct Prefix;
+
+impl ConditionalEventHandler for Prefix {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, _: &EventContext) -> Option<Cmd> {
+        None
+    }
+}
+
+impl KeyConfig {
+    /// The bindings the shell starts with: its own history search on
+    /// Ctrl-R, and Ctrl-X Ctrl-E to edit the line in an editor.
+    pub fn new() -> KeyConfig {
+        let mut config = KeyConfig { bindings: vec![], variables: BTreeMap::new() };
+        for line in [r#""\C-r": reverse-search-history"#, r#""\C-x\C-e": edit-and-execute-command"#] {
+            if let Ok(Some(directive)) = parse_line(line, false) {
+                config.apply(directive);
+            }
+        }
+        config
+    }
+
+    pub fn apply(&mut self, directive: Directive) {
+        match directive {
+            Directive::Set(name, value) => {
+                self.variables.insert(name, value);
+            },
+            Directive::Bind(keyseq, keys, binding) => {
+                self.unbind(&keys);
+                self.bindings.push((keyseq, keys, binding));
+            },
+        }
+    }
+
+    /// Removes the binding of `keys`. Returns whether there was one.
+    pub fn unbind(&mut self, keys: &[KeyEvent]) -> bool {
+        let count = self.bindings.len();
+        self.bindings.retain(|(_, bound, _)| bound != keys);
+        self.bindings.len() != count
+    }
+
+    /// Every sequence the editor binds: those of `bindings`, and those
+    /// starting them.
+    pub fn sequences(&self) -> Vec<Vec<KeyEvent>> {
+        let mut sequences: Vec<Vec<KeyEvent>> = vec![];
+        for (_, keys, _) in &self.bindings {
+            for end in 1..=keys.len() {
+                if !sequences.iter().any(|sequence| *sequence == keys[..end]) {
+                    sequences.push(keys[..end].to_vec());
+                }
+            }
+        }
+        sequences
+    }
+
+    /// The handler of each sequence in the editor. Shell actions report to
+    /// `pending`, and the history search to `search`.
+    pub fn handlers(&self, pending: &Pending, search: &Arc<Mutex<Option<String>>>) -> Vec<(Vec<KeyEvent>, EventHandler)> {
+        self.sequences()
+            .into_iter()
+            .map(|sequence| {
+                let handler = self.bindings
+                    .iter()
+                    .find(|(_, keys, _)| *keys == sequence)
+                    .and_then(|(_, _, binding)| handler(binding, pending, search))
+                    .unwrap_or_else(|| EventHandler::Conditional(Box::new(Prefix)));
+                (sequence, handler)
+            })
+            .collect()
+    }
+
+    /// Bindings of the given kind, in inputrc format: `bind -p`, `-s` and
+    /// `-X`.
+    pub fn listing(&self, kind: char) -> Vec<String> {
+        self.bindings
+            .iter()
+            .filter_map(|(keyseq, _, binding)| match (kind, binding) {
+                ('p', Binding::Function(name)) => Some(format!("{}: {}", keyseq, name)),
+                ('s', Binding::Macro(text)) => Some(format!("{}: \"{}\"", keyseq, quote_keys(text))),
+                ('X', Binding::Command(command)) => Some(format!("{}: \"{}\"", keyseq, command.replace('\\', "\\\\").replace('"', "\\\""))),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+fn handler(binding: &Binding, pending: &Pending, search: &Arc<Mutex<Option<String>>>) -> Option<EventHandler> {
+    let action = match binding {
+        Binding::Function(name) => match name.as_str() {
+            "reverse-search-history" => return Some(EventHandler::Conditional(Box::new(SearchTrigger(search.clone())))),
+            "edit-and-execute-command" => KeyAction::EditAndExecute,
+            "vi-editing-mode" => KeyAction::EditingMode("vi"),
+            "emacs-editing-mode" => KeyAction::EditingMode("emacs"),
+            "re-read-init-file" => KeyAction::ReadInitFile,
+            name => return function_cmd(name).map(EventHandler::Simple),
+        },
+        Binding::Macro(text) if text.ends_with(['\n', '\r']) => KeyAction::Accept(text.trim_end_matches(['\n', '\r']).to_string()),
+        Binding::Macro(text) => return Some(EventHandler::Simple(Cmd::Insert(1, text.clone()))),
+        Binding::Command(command) => KeyAction::Run(command.clone()),
+    };
+    Some(EventHandler::Conditional(Box::new(ShellKey(pending.clone(), action))))
+}
+
+/// The editor command of a readline function.
+fn function_cmd(name: &str) -> Option<Cmd> {
+    Some(match name {
+        "abort" => Cmd::Abort,
+        "accept-line" => Cmd::AcceptLine,
+        "backward-char" => Cmd::Move(Movement::BackwardChar(1)),
+        "backward-delete-char" => Cmd::Kill(Movement::BackwardChar(1)),
+        "backward-kill-line" | "unix-line-discard" => Cmd::Kill(Movement::BeginningOfLine),
+        "backward-kill-word" => Cmd::Kill(Movement::BackwardWord(1, Word::Emacs)),
+        "backward-word" => Cmd::Move(Movement::BackwardWord(1, Word::Emacs)),
+        "beginning-of-history" => Cmd::BeginningOfHistory,
+        "beginning-of-line" => Cmd::Move(Movement::BeginningOfLine),
+        "capitalize-word" => Cmd::CapitalizeWord,
+        "clear-screen" => Cmd::ClearScreen,
+        "complete" | "menu-complete" => Cmd::Complete,
+        "delete-char" => Cmd::Kill(Movement::ForwardChar(1)),
+        "downcase-word" => Cmd::DowncaseWord,
+        "end-of-history" => Cmd::EndOfHistory,
+        "end-of-line" => Cmd::Move(Movement::EndOfLine),
+        "forward-char" => Cmd::Move(Movement::ForwardChar(1)),
+        "forward-search-history" => Cmd::ForwardSearchHistory,
+        "forward-word" => Cmd::Move(Movement::ForwardWord(1, At::AfterEnd, Word::Emacs)),
+        "history-search-backward" => Cmd::HistorySearchBackward,
+        "history-search-forward" => Cmd::HistorySearchForward,
+        "kill-line" => Cmd::Kill(Movement::EndOfLine),
+        "kill-whole-line" => Cmd::Kill(Movement::WholeLine),
+        "kill-word" => Cmd::Kill(Movement::ForwardWord(1, At::AfterEnd, Word::Emacs)),
+        "next-history" => Cmd::NextHistory,
+        "previous-history" => Cmd::PreviousHistory,
+        "quoted-insert" => Cmd::QuotedInsert,
+        "redraw-current-line" => Cmd::Repaint,
+        "tab-insert" => Cmd::Insert(1, "\t".to_string()),
+        "transpose-chars" => Cmd::TransposeChars,
+        "transpose-words" => Cmd::TransposeWords(1),
+        "undo" => Cmd::Undo(1),
+        "unix-word-rubout" => Cmd::Kill(Movement::BackwardWord(1, Word::Big)),
+        "upcase-word" => Cmd::UpcaseWord,
+        "yank" => Cmd::Yank(1, Anchor::Before),
+        "yank-pop" => Cmd::YankPop,
+        _ => return None,
+    })
+}
+
+/// Reads an inputrc file, following `$if`, `$else`, `$endif` and
+/// `$include`. `$if` tests the editing `mode`, the `term`inal, or the
+/// application name, `rshell`. Errors name the file and line.
+pub fn read_file(path: &Path, mode: &str, term: &str, directives: &mut Vec<Directive>, errors: &mut Vec<String>, depth: usize) {
+    let Ok(text) = fs::read_to_string(path) else {
+        errors.push(format!("{}: cannot read file", path.display()));
+        return;
+    };
+
+    // Whether each enclosing `$if` holds.
+    let mut conditions: Vec<bool> = vec![];
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        let active = conditions.iter().all(|condition| *condition);
+
+        if let Some(test) = line.strip_prefix("$if") {
+            let test = test.trim();
+            let holds = match test.split_once('=') {
+                Some(("mode", value)) => value.trim() == mode,
+                Some(("term", value)) => term == value.trim() || term.split('-').next() == Some(value.trim()),
+                Some(_) => false,
+                None => test.eq_ignore_ascii_case("rshell"),
+            };
+            conditions.push(holds);
+        } else if line.starts_with("$else") {
+            if let Some(last) = conditions.last_mut() {
+                *last = !*last;
+            }
+        } else if line.starts_with("$endif") {
+            conditions.pop();
+        } else if !active {
+            continue;
+        } else if let Some(file) = line.strip_prefix("$include") {
+            let file = file.trim();
+            let file = match (file.strip_prefix("~/"), std::env::var("HOME")) {
+                (Some(rest), Ok(home)) => Path::new(&home).join(rest),
+                _ => path.parent().unwrap_or(Path::new("")).join(file),
+            };
+            if depth < MAX_INCLUDE_DEPTH {
+                read_file(&file, mode, term, directives, errors, depth + 1);
+            }
+        } else {
+            match parse_line(line, false) {
+                Ok(Some(directive)) => directives.push(directive),
+                Ok(None) => {},
+                Err(err) => errors.push(format!("{}: line {}: {}", path.display(), number + 1, err)),
+            }
+        }
+    }
+}
+
+/// Reads a line of inputrc: `set variable value`, or a key sequence, a
+/// colon and what it is bound to. With `command`, as for `bind -x`, the
+/// key runs a shell command.
+pub fn parse_line(line: &str, command: bool) -> Result<Option<Directive>, String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    if let Some(setting) = line.strip_prefix("set ").filter(|_| !command) {
+        let mut words = setting.split_whitespace();
+        let name = words.next().ok_or("set: variable name expected")?;
+        return Ok(Some(Directive::Set(name.to_lowercase(), words.next().unwrap_or_default().to_string())));
+    }
+
+    let split = match line.strip_prefix('"') {
+        Some(quoted) => closing_quote(quoted, '"').ok_or("no closing `\"' in key sequence")? + 2,
+        None => line.find(':').ok_or("no `:' after the key name")?,
+    };
+    let (keyseq, keys) = key_sequence(&line[..split])?;
+    let target = line[split..].trim_start().strip_prefix(':').ok_or("no `:' after the key sequence")?.trim();
+
+    let binding = if command {
+        Binding::Command(unquote(target))
+    } else if let Some(quote) = target.chars().next().filter(|c| *c == '"' || *c == '\'') {
+        let end = closing_quote(&target[1..], quote).unwrap_or(target.len() - 1);
+        Binding::Macro(unescape(&target[1..end + 1]).into_iter().collect())
+    } else {
+        let name = target.split_whitespace().next().unwrap_or_default().to_lowercase();
+        if !FUNCTIONS.contains(&name.as_str()) {
+            return Err(format!("{}: unknown function name", name));
+        }
+        Binding::Function(name)
+    };
+
+    Ok(Some(Directive::Bind(keyseq, keys, binding)))
+}
+
+/// A key sequence on its own, quoted or as a key name, in readline's
+/// notation and as key events.
+pub fn key_sequence(text: &str) -> Result<(String, Vec<KeyEvent>), String> {
+    let text = text.trim();
+    let chars = match text.strip_prefix('"') {
+        Some(quoted) => unescape(&quoted[..closing_quote(quoted, '"').ok_or("no closing `\"' in key sequence")?]),
+        None => key_name(text)?,
+    };
+    let keys = key_events(&chars)?;
+    if keys.is_empty() {
+        return Err("empty key sequence".to_string());
+    }
+    Ok((keyseq_text(&chars), keys))
+}
+
+fn closing_quote(text: &str, quote: char) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            c if c == quote => return Some(i),
+            _ => {},
+        }
+    }
+    None
+}
+
+fn unquote(text: &str) -> String {
+    match text.chars().next().filter(|c| *c == '"' || *c == '\'') {
+        Some(quote) if text.len() > 1 && text.ends_with(quote) => text[1..text.len() - 1].replace(&format!("\\{}", quote), &quote.to_string()),
+        _ => text.to_string(),
+    }
+}
+
+/// The characters of a key sequence or macro written with readline's
+/// escapes: `\C-x`, `\M-x`, `\e`, `\\`, `\"`, `\n`, `\t`, `\nnn`, `\xHH`...
+fn unescape(text: &str) -> Vec<char> {
+    let mut chars = text.chars().peekable();
+    let mut result = vec![];
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        let Some(escape) = chars.next() else {
+            result.push('\\');
+            break;
+        };
+        match escape {
+            'C' if chars.peek() == Some(&'-') => {
+                chars.next();
+                if let Some(key) = chars.next() {
+                    result.push(control(key));
+                }
+            },
+            'M' if chars.peek() == Some(&'-') => {
+                chars.next();
+                result.push('\x1b');
+            },
+            'e' => result.push('\x1b'),
+            'a' => result.push('\x07'),
+            'b' => result.push('\x08'),
+            'd' => result.push('\x7f'),
+            'f' => result.push('\x0c'),
+            'n' => result.push('\n'),
+            'r' => result.push('\r'),
+            't' => result.push('\t'),
+            'v' => result.push('\x0b'),
+            '0'..='7' => {
+                let mut value = escape.to_digit(8).unwrap_or_default();
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(8)) {
+                        Some(digit) => {
+                            value = value * 8 + digit;
+                            chars.next();
+                        },
+                        None => break,
+                    }
+                }
+                result.extend(char::from_u32(value));
+            },
+            'x' => {
+                let mut value = 0;
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(16)) {
+                        Some(digit) => {
+                            value = value * 16 + digit;
+                            chars.next();
+                        },
+                        None => break,
+                    }
+                }
+                result.extend(char::from_u32(value));
+            },
+            other => result.push(other),
+        }
+    }
+    result
+}
+
+fn control(key: char) -> char {
+    match key {
+        '?' => '\x7f',
+        key => ((key.to_ascii_lowercase() as u8) & 0x1f) as char,
+    }
+}
+
+/// The characters of a key written by name: `Control-u`, `C-u`,
+/// `Meta-Rubout`, `M-x`, `Tab`...
+fn key_name(name: &str) -> Result<Vec<char>, String> {
+    let mut rest = name;
+    let mut ctrl = false;
+    let mut meta = false;
+    loop {
+        let lower = rest.to_lowercase();
+        if let Some(prefix) = ["control-", "c-"].iter().find(|prefix| lower.starts_with(*prefix) && rest.len() > prefix.len()) {
+            ctrl = true;
+            rest = &rest[prefix.len()..];
+        } else if let Some(prefix) = ["meta-", "m-"].iter().find(|prefix| lower.starts_with(*prefix) && rest.len() > prefix.len()) {
+            meta = true;
+            rest = &rest[prefix.len()..];
+        } else {
+            break;
+        }
+    }
+
+    let key = match rest.to_lowercase().as_str() {
+        "rubout" | "del" => '\x7f',
+        "escape" | "esc" => '\x1b',
+        "lfd" | "newline" => '\n',
+        "ret" | "return" => '\r',
+        "spc" | "space" => ' ',
+        "tab" => '\t',
+        _ if rest.chars().count() == 1 => rest.chars().next().unwrap_or_default(),
+        _ => return Err(format!("{}: unknown key name", name)),
+    };
+
+    let key = if ctrl { control(key) } else { key };
+    Ok(if meta { vec!['\x1b', key] } else { vec![key] })
+}
+
+/// The key events the terminal sends as `chars`: an escape followed by a
+/// character is that character with Alt, and the usual escape sequences
+/// are arrows, Home, End, Delete, function keys and the like.
+fn key_events(chars: &[char]) -> Result<Vec<KeyEvent>, String> {
+    let mut keys = vec![];
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c != '\x1b' {
+            keys.push(KeyEvent::new(c, Modifiers::NONE));
+            i += 1;
+            continue;
+        }
+
+        // A second escape before a sequence holds Alt down with its key.
+        let alt = chars.get(i + 1) == Some(&'\x1b') && matches!(chars.get(i + 2), Some('[' | 'O'));
+        let start = if alt { i + 1 } else { i };
+        match chars.get(start + 1) {
+            Some('[' | 'O') => {
+                let end = (start + 2..chars.len()).find(|&j| !chars[j].is_ascii_digit() && chars[j] != ';').ok_or("unfinished escape sequence")?;
+                let sequence: String = chars[start + 1..=end].iter().collect();
+                let KeyEvent(code, mut modifiers) = escape_sequence(&sequence).ok_or_else(|| format!("\\e{}: unknown escape sequence", sequence))?;
+                if alt {
+                    modifiers.insert(Modifiers::ALT);
+                }
+                keys.push(KeyEvent(code, modifiers));
+                i = end + 1;
+            },
+            Some(next) => {
+                keys.push(KeyEvent::new(*next, Modifiers::ALT));
+                i += 2;
+            },
+            None => {
+                keys.push(KeyEvent(KeyCode::Esc, Modifiers::NONE));
+                i += 1;
+            },
+        }
+    }
+    Ok(keys)
+}
+
+/// The key of a CSI or SS3 sequence such as `[A` or `[1;5C`, without its
+/// escape.
+fn escape_sequence(sequence: &str) -> Option<KeyEvent> {
+    let (end, last) = sequence.char_indices().last()?;
+    let params: Vec<&str> = sequence.get(1..end)?.split(';').collect();
+
+    // rxvt sends Shift and Ctrl arrows as lowercase letters.
+    if let Some(index) = "abcd".find(last) {
+        let modifiers = if sequence.starts_with('O') { Modifiers::CTRL } else { Modifiers::SHIFT };
+        return Some(KeyEvent([KeyCode::Up, KeyCode::Down, KeyCode::Right, KeyCode::Left][index], modifiers));
+    }
+
+    let code = match (last, params[0]) {
+        ('A', _) => KeyCode::Up,
+        ('B', _) => KeyCode::Down,
+        ('C', _) => KeyCode::Right,
+        ('D', _) => KeyCode::Left,
+        ('H', _) | ('~', "1" | "7") => KeyCode::Home,
+        ('F', _) | ('~', "4" | "8") => KeyCode::End,
+        ('P', _) => KeyCode::F(1),
+        ('Q', _) => KeyCode::F(2),
+        ('R', _) => KeyCode::F(3),
+        ('S', _) => KeyCode::F(4),
+        ('~', "2") => KeyCode::Insert,
+        ('~', "3") => KeyCode::Delete,
+        ('~', "5") => KeyCode::PageUp,
+        ('~', "6") => KeyCode::PageDown,
+        ('~', number) => KeyCode::F(match number {
+            "15" => 5,
+            "17" => 6,
+            "18" => 7,
+            "19" => 8,
+            "20" => 9,
+            "21" => 10,
+            "23" => 11,
+            "24" => 12,
+            _ => return None,
+        }),
+        _ => return None,
+    };
+
+    // The second parameter is 1 plus a mask of Shift, Alt and Ctrl.
+    let mask = params.get(1).and_then(|mask| mask.parse::<u8>().ok()).map_or(0, |mask| mask.saturating_sub(1));
+    let mut modifiers = Modifiers::NONE;
+    for (bit, modifier) in [(1, Modifiers::SHIFT), (2, Modifiers::ALT), (4, Modifiers::CTRL)] {
+        if mask & bit != 0 {
+            modifiers.insert(modifier);
+        }
+    }
+    Some(KeyEvent(code, modifiers))
+}
+
+/// A key sequence in readline's notation, quoted.
+fn keyseq_text(chars: &[char]) -> String {
+    format!("\"{}\"", quote_keys(&chars.iter().collect::<String>()))
+}
+
+fn quote_keys(text: &str) -> String {
+    let mut quoted = String::new();
+    for c in text.chars() {
+        match c {
+            '\x1b' => quoted.push_str("\\e"),
+            '\x7f' => quoted.push_str("\\C-?"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            },
+            c if c.is_ascii_control() => quoted.push_str(&format!("\\C-{}", ((c as u8) | 0x40).to_ascii_lowercase() as char)),
+            c => quoted.push(c),
+        }
+    }
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_sequence_reads_escapes_and_names() {
+        let (keyseq, keys) = key_sequence(r#""\C-x\C-e""#).unwrap();
+        assert_eq!(keyseq, r#""\C-x\C-e""#);
+        assert_eq!(keys, [KeyEvent::ctrl('X'), KeyEvent::ctrl('E')]);
+
+        let (_, keys) = key_sequence("Meta-Rubout").unwrap();
+        assert_eq!(keys, [KeyEvent(KeyCode::Backspace, Modifiers::ALT)]);
+
+        let (_, keys) = key_sequence(r#""\e[1;5C""#).unwrap();
+        assert_eq!(keys, [KeyEvent(KeyCode::Right, Modifiers::CTRL)]);
+    }
+
+    #[test]
+    fn key_sequence_rejects_unknown_escape_sequences() {
+        assert!(key_sequence(r#""\e[é""#).unwrap_err().contains("unknown escape sequence"));
+        assert!(key_sequence(r#""\e[9z""#).is_err());
+        assert!(parse_line(r#""\e[é": beginning-of-line"#, false).is_err());
+    }
+}
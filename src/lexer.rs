@@ -0,0 +1,543 @@
+use std::fmt::Display;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Word(String),
+    Operator(String),
+    Redirect(String),
+    Newline,
+    Eof
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    Incomplete(String),
+    Syntax(String)
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::Incomplete(msg) => write!(f, "syntax error: {}", msg),
+            ParseError::Syntax(msg) => write!(f, "syntax error: {}", msg),
+        }
+    }
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "{}", word),
+            Token::Operator(op) | Token::Redirect(op) => write!(f, "{}", op),
+            Token::Newline => write!(f, "newline"),
+            Token::Eof => write!(f, "end of file"),
+        }
+    }
+}
+
+const OPERATORS: [&str; 11] = [";;&", ";;", ";&", "&&", "||", "((", ";", "&", "|", "(", ")"];
+const REDIRECTIONS: [&str; 9] = ["&>>", "&>", ">>", ">&", ">|", ">", "<<-", "<<", "<"];
+
+pub struct Lexer {
+    input: Vec<char>,
+    pos: usize,
+    peeked: Option<(Token, usize)>,
+    /// End of the here-document bodies read so far, which follow the line
+    /// of their redirections and are skipped at its newline.
+    here_end: Option<usize>
+}
+
+impl Lexer {
+    pub fn new(input: &str) -> Self {
+        Lexer {
+            input: input.chars().collect(),
+            pos: 0,
+            peeked: None,
+            here_end: None
+        }
+    }
+
+    pub fn peek(&mut self) -> Result<Token, ParseError> {
+        if let Some((token, _)) = &self.peeked {
+            return Ok(token.clone());
+        }
+
+        self.skip_blanks_and_comments();
+        let start = self.pos;
+        let token = self.read_token()?;
+        self.peeked = Some((token.clone(), start));
+        Ok(token)
+    }
+
+    /// Replaces the text of the peeked token with `text`, which is then read
+    /// again as input. Returns the offset where the replacement ends.
+    pub fn replace_peeked(&mut self, text: &str) -> usize {
+        let start = self.offset();
+        let end = self.pos;
+        self.input.splice(start..end, text.chars());
+        self.peeked = None;
+        self.pos = start;
+        start + text.chars().count()
+    }
+
+    /// Returns the token following the peeked one without consuming either.
+    pub fn peek_second(&mut self) -> Result<Token, ParseError> {
+        self.peek()?;
+        let pos = self.pos;
+        let token = self.read_token();
+        self.pos = pos;
+        token
+    }
+
+    /// Offset in the input where the next token starts.
+    pub fn offset(&self) -> usize {
+        match &self.peeked {
+            Some((_, start)) => *start,
+            None => self.pos,
+        }
+    }
+
+    pub fn slice(&self, start: usize, end: usize) -> String {
+        self.input[start..end].iter().collect::<String>().trim().to_string()
+    }
+
+    pub fn next_token(&mut self) -> Result<Token, ParseError> {
+        match self.peeked.take() {
+            Some((token, _)) => Ok(token),
+            None => self.read_token(),
+        }
+    }
+
+    /// Reads the next token along with the offsets of its text.
+    pub fn next_spanned(&mut self) -> Result<(Token, usize, usize), ParseError> {
+        self.unpeek();
+        self.skip_blanks_and_comments();
+        let start = self.pos;
+        let token = self.read_token()?;
+        Ok((token, start, self.pos))
+    }
+
+    /// Reads the raw text of an arithmetic expression up to the `))` that
+    /// closes an already consumed `((`.
+    pub fn read_arithmetic(&mut self) -> Result<String, ParseError> {
+        self.unpeek();
+        let mut depth = 0;
+        let start = self.pos;
+
+        while self.pos < self.input.len() {
+            match self.input[self.pos] {
+                '(' => depth += 1,
+                ')' if depth == 0 => {
+                    if self.input.get(self.pos + 1) == Some(&')') {
+                        let expression = self.input[start..self.pos].iter().collect();
+                        self.pos += 2;
+                        return Ok(expression);
+                    }
+                    return Err(ParseError::Syntax("expected `))'".to_string()));
+                },
+                ')' => depth -= 1,
+                _ => {}
+            }
+            self.pos += 1;
+        }
+
+        Err(ParseError::Incomplete("unexpected end of file, expected `))'".to_string()))
+    }
+
+    /// Reads the right operand of `=~` in `[[ ]]`, where parentheses and
+    /// `|` are part of the regular expression rather than operators.
+    pub fn read_regex(&mut self) -> Result<String, ParseError> {
+        self.unpeek();
+        self.skip_blanks_and_comments();
+
+        let start = self.pos;
+        let mut depth = 0;
+
+        while let Some(c) = self.current() {
+            let end = match c {
+                c if is_blank(c) && depth == 0 => break,
+                ')' if depth == 0 => break,
+                '(' => {
+                    depth += 1;
+                    Some(self.pos)
+                },
+                ')' => {
+                    depth -= 1;
+                    Some(self.pos)
+                },
+                '\'' => scan_single_quote(&self.input, self.pos + 1),
+                '"' => scan_double_quote(&self.input, self.pos + 1),
+                '$' => Some(scan_dollar(&self.input, self.pos)? - 1),
+                '\\' if self.pos + 1 < self.input.len() => Some(self.pos + 1),
+                _ => Some(self.pos),
+            };
+
+            match end {
+                Some(end) => self.pos = end + 1,
+                None => return Err(ParseError::Incomplete(format!("unexpected end of file while looking for matching `{}'", c))),
+            }
+        }
+
+        if self.pos == start {
+            return Err(ParseError::Syntax("expected regular expression after `=~'".to_string()));
+        }
+        Ok(self.input[start..self.pos].iter().collect())
+    }
+
+    /// Reads the body of a here-document whose delimiter word was just
+    /// consumed. The body starts on the line after the redirection, or
+    /// after the body of a previous here-document on the same line. With
+    /// `strip_tabs` (`<<-`), leading tabs are removed from every line.
+    pub fn read_here_document(&mut self, delimiter: &str, strip_tabs: bool) -> Result<String, ParseError> {
+        self.unpeek();
+        let (delimiter, _) = unquote_delimiter(delimiter);
+
+        let start = match self.here_end.filter(|end| *end > self.pos) {
+            Some(end) => end,
+            None => match (self.pos..self.input.len()).find(|&i| self.input[i] == '\n') {
+                Some(newline) => newline + 1,
+                None => {
+                    self.here_end = Some(self.input.len());
+                    return Err(ParseError::Incomplete(format!("here-document delimited by end of file (wanted `{}')", delimiter)));
+                },
+            },
+        };
+
+        let mut body = String::new();
+        let mut i = start;
+        while i < self.input.len() {
+            let end = (i..self.input.len()).find(|&j| self.input[j] == '\n');
+            let mut line: &[char] = &self.input[i..end.unwrap_or(self.input.len())];
+            if strip_tabs {
+                while let Some(rest) = line.strip_prefix(&['\t']) {
+                    line = rest;
+                }
+            }
+
+            let line: String = line.iter().collect();
+            if line == delimiter {
+                self.here_end = Some(end.map_or(self.input.len(), |end| end + 1));
+                return Ok(body);
+            }
+            if end.is_none() {
+                break;
+            }
+
+            body.push_str(&line);
+            body.push('\n');
+            i = end.unwrap_or(self.input.len()) + 1;
+        }
+
+        self.here_end = Some(self.input.len());
+        Err(ParseError::Incomplete(format!("here-document delimited by end of file (wanted `{}')", delimiter)))
+    }
+
+    fn unpeek(&mut self) {
+        if let Some((_, start)) = self.peeked.take() {
+            self.pos = start;
+        }
+    }
+
+    fn current(&self) -> Option<char> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn starts_with(&self, text: &str) -> bool {
+        text.chars().enumerate().all(|(i, c)| self.input.get(self.pos + i) == Some(&c))
+    }
+
+    fn skip_blanks_and_comments(&mut self) {
+        while let Some(c) = self.current() {
+            match c {
+                ' ' | '\t' | '\r' => self.pos += 1,
+                '\\' if self.input.get(self.pos + 1) == Some(&'\n') => self.pos += 2,
+                '#' => {
+                    while self.current().is_some_and(|c| c != '\n') {
+                        self.pos += 1;
+                    }
+                },
+                _ => break,
+            }
+        }
+    }
+
+    fn read_token(&mut self) -> Result<Token, ParseError> {
+        self.skip_blanks_and_comments();
+
+        let c = match self.current() {
+            Some(c) => c,
+            None if self.pos >= 2 && self.input[self.pos - 2..self.pos] == ['\\', '\n'] => {
+                return Err(ParseError::Incomplete("unexpected end of file".to_string()));
+            },
+            None => return Ok(Token::Eof),
+        };
+
+        if c == '\n' {
+            self.pos += 1;
+            // Skip the bodies of the here-documents of this line.
+            if let Some(end) = self.here_end.filter(|end| *end > self.pos) {
+                self.pos = end;
+            }
+            return Ok(Token::Newline);
+        }
+
+        if let Some(op) = REDIRECTIONS.iter().find(|op| self.starts_with(op)) {
+            self.pos += op.len();
+            return Ok(Token::Redirect(op.to_string()));
+        }
+
+        if let Some(op) = OPERATORS.iter().find(|op| self.starts_with(op)) {
+            self.pos += op.len();
+            return Ok(Token::Operator(op.to_string()));
+        }
+
+        self.read_word()
+    }
+
+    fn read_word(&mut self) -> Result<Token, ParseError> {
+        let start = self.pos;
+
+        while let Some(c) = self.current() {
+            if c == '(' && self.pos > start && self.input[self.pos - 1] == '=' {
+                let name: String = self.input[start..self.pos - 1].iter().collect();
+                if is_name(&name) {
+                    // An array assignment, `name=(word...)`.
+                    self.pos = self.scan_array(self.pos + 1)? + 1;
+                    continue;
+                }
+            }
+            if is_metachar(c) {
+                break;
+            }
+
+            let end = match c {
+                '\'' => scan_single_quote(&self.input, self.pos + 1),
+                '"' => scan_double_quote(&self.input, self.pos + 1),
+                '`' => scan_backtick(&self.input, self.pos + 1),
+                '$' => Some(scan_dollar(&self.input, self.pos)? - 1),
+                '\\' if self.input.get(self.pos + 1) == Some(&'\n') && self.pos + 2 == self.input.len() => None,
+                '\\' if self.pos + 1 < self.input.len() => Some(self.pos + 1),
+                '\\' => None,
+                _ => Some(self.pos),
+            };
+
+            match end {
+                Some(end) => self.pos = end + 1,
+                None if c == '\\' => return Err(ParseError::Incomplete("unexpected end of file".to_string())),
+                None => return Err(ParseError::Incomplete(format!("unexpected end of file while looking for matching `{}'", c))),
+            }
+        }
+
+        let word: String = self.input[start..self.pos].iter().collect();
+
+        if !word.is_empty() && word.chars().all(|c| c.is_ascii_digit()) {
+            if let Some(op) = REDIRECTIONS.iter().find(|op| !op.starts_with('&') && self.starts_with(op)) {
+                self.pos += op.len();
+                return Ok(Token::Redirect(format!("{}{}", word, op)));
+            }
+        }
+
+        Ok(Token::Word(word))
+    }
+
+    /// Finds the parenthesis closing the elements of an array assignment
+    /// that start at `pos`.
+    fn scan_array(&self, mut pos: usize) -> Result<usize, ParseError> {
+        while let Some(&c) = self.input.get(pos) {
+            let end = match c {
+                ')' => return Ok(pos),
+                '\'' => scan_single_quote(&self.input, pos + 1),
+                '"' => scan_double_quote(&self.input, pos + 1),
+                '`' => scan_backtick(&self.input, pos + 1),
+                '$' => Some(scan_dollar(&self.input, pos)? - 1),
+                '\\' if pos + 1 < self.input.len() => Some(pos + 1),
+                '\\' => None,
+                _ => Some(pos),
+            };
+            pos = end.ok_or_else(|| ParseError::Incomplete(format!("unexpected end of file while looking for matching `{}'", c)))? + 1;
+        }
+        Err(ParseError::Incomplete("unexpected end of file while looking for matching `)'".to_string()))
+    }
+}
+
+pub fn is_blank(c: char) -> bool {
+    c == ' ' || c == '\t' || c == '\n' || c == '\r'
+}
+
+pub fn is_metachar(c: char) -> bool {
+    is_blank(c) || matches!(c, ';' | '&' | '|' | '<' | '>' | '(' | ')')
+}
+
+/// Removes the quotes of a here-document delimiter. The body is expanded
+/// only when no part of the delimiter was quoted.
+pub fn unquote_delimiter(word: &str) -> (String, bool) {
+    let mut delimiter = String::new();
+    let mut quoted = false;
+    let mut chars = word.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' | '"' => quoted = true,
+            '\\' => {
+                quoted = true;
+                delimiter.extend(chars.next());
+            },
+            c => delimiter.push(c),
+        }
+    }
+
+    (delimiter, quoted)
+}
+
+pub fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Returns the index of the quote closing a single-quoted string whose
+/// content starts at `i`.
+pub fn scan_single_quote(chars: &[char], i: usize) -> Option<usize> {
+    (i..chars.len()).find(|&j| chars[j] == '\'')
+}
+
+/// Returns the index of the quote closing a double-quoted string whose
+/// content starts at `i`.
+pub fn scan_double_quote(chars: &[char], mut i: usize) -> Option<usize> {
+    while i < chars.len() {
+        match chars[i] {
+            '"' => return Some(i),
+            '\\' => i += 2,
+            '`' => i = scan_backtick(chars, i + 1)? + 1,
+            '$' => i = scan_dollar(chars, i).ok()?,
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+/// Returns the index of the backtick closing a command substitution whose
+/// content starts at `i`.
+pub fn scan_backtick(chars: &[char], mut i: usize) -> Option<usize> {
+    while i < chars.len() {
+        match chars[i] {
+            '`' => return Some(i),
+            '\\' => i += 2,
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+/// Returns the index right after the expansion starting with the `$` at `i`.
+pub fn scan_dollar(chars: &[char], i: usize) -> Result<usize, ParseError> {
+    let unterminated = |c: char| ParseError::Incomplete(format!("unexpected end of file while looking for matching `{}'", c));
+
+    match chars.get(i + 1) {
+        Some('(') => {
+            let mut j = i + 2;
+            let mut depth = 1;
+            while j < chars.len() {
+                match chars[j] {
+                    '(' => depth += 1,
+                    ')' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Ok(j + 1);
+                        }
+                    },
+                    '\'' => j = scan_single_quote(chars, j + 1).ok_or_else(|| unterminated('\''))?,
+                    '"' => j = scan_double_quote(chars, j + 1).ok_or_else(|| unterminated('"'))?,
+                    '`' => j = scan_backtick(chars, j + 1).ok_or_else(|| unterminated('`'))?,
+                    '\\' => j += 1,
+                    _ => {}
+                }
+                j += 1;
+            }
+            Err(unterminated(')'))
+        },
+        Some('{') => {
+            let mut j = i + 2;
+            while j < chars.len() {
+                match chars[j] {
+                    '}' => return Ok(j + 1),
+                    '\'' => j = scan_single_quote(chars, j + 1).ok_or_else(|| unterminated('\''))?,
+                    '"' => j = scan_double_quote(chars, j + 1).ok_or_else(|| unterminated('"'))?,
+                    '$' => {
+                        j = scan_dollar(chars, j)?;
+                        continue;
+                    },
+                    '\\' => j += 1,
+                    _ => {}
+                }
+                j += 1;
+            }
+            Err(unterminated('}'))
+        },
+        _ => Ok(i + 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(input: &str) -> Result<Vec<Token>, ParseError> {
+        let mut lexer = Lexer::new(input);
+        let mut tokens = vec![];
+        loop {
+            match lexer.next_token()? {
+                Token::Eof => return Ok(tokens),
+                token => tokens.push(token),
+            }
+        }
+    }
+
+    fn word(text: &str) -> Token {
+        Token::Word(text.to_string())
+    }
+
+    #[test]
+    fn quotes_stay_in_their_word() {
+        assert_eq!(
+            tokens(r#"echo 'a b' "c $d" e\ f x"y z"'w'"#).unwrap(),
+            [word("echo"), word("'a b'"), word(r#""c $d""#), word(r"e\ f"), word(r#"x"y z"'w'"#)]
+        );
+        assert_eq!(tokens(r#"echo "a ; b" 'c | d'"#).unwrap(), [word("echo"), word(r#""a ; b""#), word("'c | d'")]);
+    }
+
+    #[test]
+    fn operators_and_redirections_split_words() {
+        assert_eq!(
+            tokens("a&&b||c;d|e").unwrap(),
+            [
+                word("a"), Token::Operator("&&".to_string()), word("b"), Token::Operator("||".to_string()),
+                word("c"), Token::Operator(";".to_string()), word("d"), Token::Operator("|".to_string()), word("e"),
+            ]
+        );
+        assert_eq!(
+            tokens("cat <in >>out").unwrap(),
+            [word("cat"), Token::Redirect("<".to_string()), word("in"), Token::Redirect(">>".to_string()), word("out")]
+        );
+    }
+
+    #[test]
+    fn substitutions_are_one_word() {
+        assert_eq!(tokens("echo $(a | b) `c; d` $((1 + 2))").unwrap(), [word("echo"), word("$(a | b)"), word("`c; d`"), word("$((1 + 2))")]);
+    }
+
+    #[test]
+    fn open_quotes_are_incomplete() {
+        for input in ["echo 'abc", "echo \"abc", "echo $(ls", "echo `ls"] {
+            assert!(matches!(tokens(input), Err(ParseError::Incomplete(_))), "{}", input);
+        }
+    }
+
+    #[test]
+    fn names() {
+        assert!(is_name("_var1"));
+        assert!(!is_name("1var"));
+        assert!(!is_name("a-b"));
+        assert!(!is_name(""));
+    }
+}
@@ -1,50 +1,412 @@
+mod arithmetic;
+mod completion;
+mod condition;
+mod executor;
+mod expansion;
+mod git;
+mod gitobject;
+mod histdb;
+mod highlight;
+mod help;
+mod history;
 mod instruction;
+mod keys;
+mod lexer;
+mod parser;
+mod prompt;
 mod redirection;
+mod search;
+mod script;
 mod sherror;
 mod shfile;
 mod shell;
+mod tools;
 mod completer;
 
-use std::{env::{current_dir, set_current_dir}, process::{exit, Command}};
-use completer::CommandCompleter;
-use rustyline::Editor;
+use std::{env::{self, current_dir, set_current_dir}, process::{exit, Command}};
+use std::io::{self, BufReader, IsTerminal, Read};
+use std::mem::ManuallyDrop;
+use std::os::fd::FromRawFd;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use completer::{AcceptHint, CommandCompleter};
+use rustyline::{CompletionType, Config, Editor, Event, EventHandler, KeyEvent};
+use rustyline::config::{BellStyle, Configurer, EditMode};
+use rustyline::history::{FileHistory, History as _};
 use rustyline::error::ReadlineError;
-use std::fs::{self, OpenOptions};
-use std::io::Write;
-use std::path::PathBuf;
-use std::path::Path;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
-use redirection::{find_redirection, RedirOp, RedirType, Redirection};
-use shfile::{executable_exists, is_executable};
+use executor::{call_function, execute_list, file_error, report};
+use expansion::quote_word;
+use lexer::is_name;
+use completion::{generate, CompletionSpec, Word};
+use script::{run_file, run_source, source_file};
+use shfile::{find_source_file, forget_executables, is_executable};
 use sherror::{ShellError, get_error_message};
-use instruction::Instruction;
 use instruction::Output;
-use shell::Shell;
+use keys::{Directive, KeyAction, KeyConfig, PendingKey, FUNCTIONS};
+use history::{History, DEFAULT_HISTSIZE};
+use prompt::{expand_prompt, run_prompt_command};
+use shell::{Flow, Shell, OPTIONS};
+
+/// Commands read back from the history database for suggestions.
+const HISTDB_SUGGESTIONS: usize = 10000;
 
 fn main() -> rustyline::Result<()> {
-    let shell = Shell::new();
-    let mut editor = Editor::new()?;
+    let mut shell = Shell::new();
+    let arguments: Vec<String> = env::args().collect();
+
+    let invocation = match parse_arguments(&arguments) {
+        Ok(invocation) => invocation,
+        Err(message) => {
+            eprintln!("{}", message);
+            exit(2);
+        },
+    };
+
+    let interactive = invocation.mode == Mode::Stdin && io::stdin().is_terminal();
+    if interactive {
+        shell.options.insert("emacs".to_string());
+        let file = inputrc(&shell);
+        if file.is_file() {
+            for err in read_key_file(&file, &mut shell) {
+                eprintln!("rshell: {}", err);
+            }
+        }
+    }
+    run_startup_files(&startup_files(&invocation, interactive, &shell), &mut shell);
+
+    match invocation.mode {
+        Mode::Command { source, name, positional } => {
+            if let Some(name) = name {
+                shell.name = name;
+                shell.positional = positional;
+            }
+            let status = run_source("rshell: -c", source.as_bytes(), &mut shell);
+            exit(exit_status(&shell, status));
+        },
+        Mode::Script { path, positional } => {
+            let file = match fs::File::open(&path) {
+                Ok(file) => file,
+                Err(err) => {
+                    report(&file_error(&path, err));
+                    exit(127);
+                },
+            };
+            shell.name = path.clone();
+            shell.positional = positional;
+            let status = run_source(&path, BufReader::new(file), &mut shell);
+            exit(exit_status(&shell, status));
+        },
+        Mode::Stdin if !interactive => {
+            let status = run_source("rshell", io::stdin().lock(), &mut shell);
+            exit(exit_status(&shell, status));
+        },
+        Mode::Stdin => run_interactive(shell),
+    }
+}
+
+/// How the shell was started, from its command line.
+#[derive(Debug, PartialEq)]
+struct Invocation {
+    mode: Mode,
+    login: bool,
+    rc: bool,
+    profile: bool
+}
 
-    editor.set_helper(Some(
-        CommandCompleter::new(
-            shell.builtins.clone(),
-            shell.clone().environment["path"].to_string()
-        )
-    ));
+/// What the shell runs.
+#[derive(Debug, PartialEq)]
+enum Mode {
+    /// `-c source [name [arg ...]]`.
+    Command { source: String, name: Option<String>, positional: Vec<String> },
+    /// `script [arg ...]`.
+    Script { path: String, positional: Vec<String> },
+    /// Commands read from stdin, edited on a terminal.
+    Stdin
+}
 
+/// Reads the command line, starting with the name the shell was run as: a
+/// name starting with `-` makes a login shell.
+fn parse_arguments(arguments: &[String]) -> Result<Invocation, String> {
+    let mut command = false;
+    let mut login = arguments.first().is_some_and(|argv0| argv0.starts_with('-'));
+    let mut rc = true;
+    let mut profile = true;
+    let mut operands = &arguments[1.min(arguments.len())..];
+    while let Some(option) = operands.first() {
+        match option.as_str() {
+            "-c" => command = true,
+            "-l" | "--login" => login = true,
+            "--norc" => rc = false,
+            "--noprofile" => profile = false,
+            "--" => {
+                operands = &operands[1..];
+                break;
+            },
+            option if option.starts_with('-') && option.len() > 1 => {
+                return Err(format!(
+                    "rshell: {}: invalid option\nUsage: rshell [-l] [--norc] [--noprofile] [-c command [name [arg ...]]] [script [arg ...]]",
+                    option
+                ));
+            },
+            _ => break,
+        }
+        operands = &operands[1..];
+    }
+
+    let mode = if command {
+        let Some((source, rest)) = operands.split_first() else {
+            return Err("rshell: -c: option requires an argument".to_string());
+        };
+        let (name, positional) = match rest.split_first() {
+            Some((name, positional)) => (Some(name.clone()), positional.to_vec()),
+            None => (None, vec![]),
+        };
+        Mode::Command { source: source.clone(), name, positional }
+    } else {
+        match operands.split_first() {
+            Some((path, positional)) => Mode::Script { path: path.clone(), positional: positional.to_vec() },
+            None => Mode::Stdin,
+        }
+    };
+
+    Ok(Invocation { mode, login, rc, profile })
+}
+
+/// The startup files to source, in order: the profiles of a login shell,
+/// then the rc files of an interactive one.
+fn startup_files(invocation: &Invocation, interactive: bool, shell: &Shell) -> Vec<PathBuf> {
+    let mut files = vec![];
+    if invocation.login && invocation.profile {
+        files.extend([PathBuf::from("/etc/profile"), home_path(shell, ".rshell_profile")]);
+    }
+    if interactive && invocation.rc {
+        files.extend([PathBuf::from("/etc/rshellrc"), user_rc(shell)]);
+    }
+    files
+}
+
+/// Sources each existing startup file in order. Errors are reported with
+/// the file and line they come from and do not stop the shell, unless a
+/// file calls `exit`.
+fn run_startup_files(paths: &[PathBuf], shell: &mut Shell) {
+    for path in paths.iter().filter(|path| path.is_file()) {
+        if let Err(err) = run_file(path, shell) {
+            report(&err);
+        }
+        if let Flow::Exit(code) = shell.flow {
+            exit(code);
+        }
+        shell.flow = Flow::Normal;
+    }
+}
+
+fn home_path(shell: &Shell, name: &str) -> PathBuf {
+    PathBuf::from(shell.get_var("HOME").unwrap_or_default()).join(name)
+}
+
+/// The user's rc file: `$XDG_CONFIG_HOME/rshell/rshellrc` (defaulting to
+/// `~/.config`) when it exists, `~/.rshellrc` otherwise.
+fn user_rc(shell: &Shell) -> PathBuf {
+    let config = shell.get_var("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| home_path(shell, ".config"));
+
+    let xdg = config.join("rshell").join("rshellrc");
+    if xdg.is_file() { xdg } else { home_path(shell, ".rshellrc") }
+}
+
+/// Status a non-interactive shell exits with once it ran out of commands.
+fn exit_status(shell: &Shell, status: i32) -> i32 {
+    match shell.flow {
+        Flow::Exit(code) => code,
+        _ => status,
+    }
+}
+
+fn run_interactive(mut shell: Shell) -> rustyline::Result<()> {
+    // Each Tab asks the completer again, so that a directory just completed
+    // can be completed into.
+    let config = Config::builder().completion_type(CompletionType::List).build();
+    let mut editor = Editor::with_config(config)?;
+
+    if shell.get_var("HISTFILE").is_none() {
+        let file = home_path(&shell, ".rshell_history");
+        shell.set_var("HISTFILE", &file.to_string_lossy());
+    }
+    if shell.get_var("HISTDB").is_none() {
+        let file = home_path(&shell, ".rshell_history.jsonl");
+        shell.set_var("HISTDB", &file.to_string_lossy());
+    }
+    if shell.get_var("HISTSIZE").is_none() {
+        shell.set_var("HISTSIZE", &DEFAULT_HISTSIZE.to_string());
+    }
+    if shell.get_var("PS1").is_none() {
+        shell.set_var("PS1", "$ ");
+    }
+    if let Some(file) = shell.history_file() {
+        let size = shell.history_size();
+        let loaded = History::trim_file(&file, size).and_then(|_| shell.history.read(&file, size));
+        if let Err(err) = loaded {
+            eprintln!("rshell: {}: {}", file.display(), err);
+        }
+    }
+    sync_history(&mut editor, &mut shell, None);
+
+    let mut completer = CommandCompleter::new(
+        shell.builtins.clone(),
+        shell.get_var("PATH").unwrap_or_default()
+    );
+    if let Some(file) = shell.history_db() {
+        let query = histdb::Query { limit: Some(HISTDB_SUGGESTIONS), ..Default::default() };
+        for record in histdb::search(&file, &query).unwrap_or_default() {
+            completer.remember(&record.command, &record.cwd);
+        }
+    }
+    editor.set_helper(Some(completer));
+
+    let search = Arc::new(Mutex::new(None));
+    let pending: keys::Pending = Arc::new(Mutex::new(None));
+    let mut applied = KeyConfig::new();
+    apply_keys(&mut editor, &applied, &applied, &pending, &search);
+
+    // The line to edit again, split at the cursor, and a line to run as if
+    // typed.
+    let mut initial: Option<(String, String)> = None;
+    let mut queued: Option<String> = None;
     loop {
-        match editor.readline("$ ") {
+        if initial.is_none() && queued.is_none() {
+            run_prompt_command(&mut shell);
+            if let Flow::Exit(code) = shell.flow {
+                exit(code);
+            }
+            shell.flow = Flow::Normal;
+        }
+
+        let ps1 = shell.get_var("PS1").unwrap_or_default();
+        let prompt = expand_prompt(&ps1, &mut shell);
+        let cwd = current_dir().map(|dir| dir.to_string_lossy().to_string()).unwrap_or_default();
+        if let Some(helper) = editor.helper_mut() {
+            helper.refresh(&shell, &cwd);
+            helper.set_prompt(prompt.clone());
+        }
+
+        editor.set_edit_mode(if shell.option("vi") { EditMode::Vi } else { EditMode::Emacs });
+        if shell.keys != applied {
+            apply_keys(&mut editor, &applied, &shell.keys, &pending, &search);
+            applied = shell.keys.clone();
+        }
+
+        let input = match (queued.take(), initial.take()) {
+            (Some(line), _) => {
+                println!("{}", line);
+                Ok(line)
+            },
+            (None, Some((left, right))) => editor.readline_with_initial(&prompt.visible, (&left, &right)),
+            (None, None) => editor.readline(&prompt.visible),
+        };
+
+        match input {
             Ok(line) => {
-                let instruction = Instruction::new(line.trim());
-                execute_cmd(&instruction, &shell);
-                let _ = editor.add_history_entry(line);
+                let Some(line) = read_continuation(&mut editor, &mut shell, line) else {
+                    // A search started from a continuation line is dropped.
+                    if let Ok(mut pending) = search.lock() {
+                        pending.take();
+                    }
+                    continue;
+                };
+
+                let line = match history::expand(&line, &shell.history.entries) {
+                    Ok(None) => line,
+                    Ok(Some(expanded)) => {
+                        println!("{}", expanded.line);
+                        if expanded.print_only {
+                            let _ = shell.add_history(&expanded.line);
+                            sync_history(&mut editor, &mut shell, Some(&expanded.line));
+                            continue;
+                        }
+                        expanded.line
+                    },
+                    Err(err) => {
+                        eprintln!("rshell: {}", err);
+                        continue;
+                    },
+                };
+
+                if let Err(err) = shell.add_history(&line) {
+                    eprintln!("rshell: {}: {}", shell.get_var("HISTFILE").unwrap_or_default(), err);
+                }
+
+                let started = Instant::now();
+                let time = histdb::now();
+                if let Some(helper) = editor.helper_mut() {
+                    helper.remember(&line, &cwd);
+                }
+
+                run_line(&line, &mut shell);
+
+                if let Some(file) = shell.history_db().filter(|_| !line.trim().is_empty()) {
+                    let record = histdb::Record {
+                        time,
+                        duration_ms: started.elapsed().as_millis() as i64,
+                        cwd,
+                        host: histdb::hostname(),
+                        status: match shell.flow {
+                            Flow::Exit(code) => code,
+                            _ => shell.last_status,
+                        },
+                        command: line.clone()
+                    };
+                    if let Err(err) = histdb::append(&file, &record) {
+                        eprintln!("rshell: {}: {}", file.display(), err);
+                    }
+                }
+                sync_history(&mut editor, &mut shell, Some(&line));
+
+                if let Flow::Exit(code) = shell.flow {
+                    exit(code);
+                }
+                shell.flow = Flow::Normal;
             },
             Err(ReadlineError::Interrupted) => {
-                println!("Use 'exit 0' to quit");
+                let searched = search.lock().ok().and_then(|mut searched| searched.take());
+                let key = pending.lock().ok().and_then(|mut key| key.take());
+                match (searched, key) {
+                    (Some(line), _) => {
+                        let items = editor.helper()
+                            .map(|helper| helper.recent_commands(&shell.history.entries))
+                            .unwrap_or_default();
+                        let line = match search::search(&items, &line) {
+                            Ok(choice) => choice.unwrap_or(line),
+                            Err(err) => {
+                                eprintln!("rshell: {}", err);
+                                line
+                            },
+                        };
+                        initial = Some((line, String::new()));
+                    },
+                    (None, Some(key)) => {
+                        let (line, pos) = run_key_action(key, &mut shell, &mut queued);
+                        if queued.is_none() {
+                            let (left, right) = line.split_at(pos.min(line.len()));
+                            initial = Some((left.to_string(), right.to_string()));
+                        }
+                        if let Flow::Exit(code) = shell.flow {
+                            exit(code);
+                        }
+                        shell.flow = Flow::Normal;
+                    },
+                    (None, None) => println!("Use 'exit 0' to quit"),
+                }
             },
             Err(ReadlineError::Eof) => {
                 println!("exit");
-                break;
+                exit(shell.last_status);
             },
             Err(err) => {
                 eprintln!("Error: {:?}", err);
@@ -56,40 +418,109 @@ fn main() -> rustyline::Result<()> {
     Ok(())
 }
 
-fn execute_cmd(instruction: &Instruction, shell: &Shell) {
-    let command = instruction.command.trim();
-    let mut arguments = instruction.arguments.clone();
+/// Carries out what a key bound to the shell asked for. Sets `queued` to
+/// a command to run now; otherwise returns the line to edit again, and the
+/// cursor position in it.
+fn run_key_action(key: PendingKey, shell: &mut Shell, queued: &mut Option<String>) -> (String, usize) {
+    let PendingKey { action, line, pos } = key;
+    match action {
+        KeyAction::EditAndExecute => {
+            *queued = edit_line(&line, shell);
+            (line, pos)
+        },
+        KeyAction::EditingMode(mode) => {
+            let _ = set_option(shell, mode, true);
+            (line, pos)
+        },
+        KeyAction::ReadInitFile => {
+            for err in read_key_file(&inputrc(shell), shell) {
+                eprintln!("rshell: {}", err);
+            }
+            (line, pos)
+        },
+        KeyAction::Accept(text) => {
+            let mut line = line;
+            line.insert_str(pos, &text);
+            *queued = Some(line.clone());
+            (line, pos)
+        },
+        // The command sees the line and the cursor, in characters, as
+        // `READLINE_LINE` and `READLINE_POINT`, and can change them.
+        KeyAction::Run(command) => {
+            let point = line[..pos].chars().count();
+            shell.set_var("READLINE_LINE", &line);
+            shell.set_var("READLINE_POINT", &point.to_string());
+            run_line(&command, shell);
 
-    if instruction.redirection {
-        match find_redirection(arguments.clone()) {
-            Ok((x, y)) => {
-                arguments = x;
-                let redirection = y;
+            let line = shell.environment.remove("READLINE_LINE").unwrap_or_default();
+            let point = shell.environment.remove("READLINE_POINT").and_then(|point| point.parse().ok()).unwrap_or(point);
+            let pos = line.char_indices().nth(point).map_or(line.len(), |(pos, _)| pos);
+            (line, pos)
+        },
+    }
+}
 
-                let output = handle_input(command, arguments.clone(), shell)
-                .map_err(|err| get_error_message(&err).unwrap().to_string());
+/// Reads more lines with the `PS2` prompt while `line` is an unfinished
+/// command, joining them into one. Returns `None` when the command is
+/// abandoned with Ctrl-C or ended early with Ctrl-D.
+fn read_continuation(editor: &mut Editor<CommandCompleter, FileHistory>, shell: &mut Shell, mut line: String) -> Option<String> {
+    while parser::is_incomplete(&line, &shell.aliases) {
+        let ps2 = shell.get_var("PS2").unwrap_or_default();
+        let prompt = expand_prompt(&ps2, shell);
+        if let Some(helper) = editor.helper_mut() {
+            helper.continue_command(&line);
+            helper.set_prompt(prompt.clone());
+        }
+        let next = editor.readline(&prompt.visible);
+        if let Some(helper) = editor.helper_mut() {
+            helper.continue_command("");
+        }
 
-                redirect_output(redirection.clone(), output, command.to_string().clone()).unwrap();
+        match next {
+            Ok(next) => {
+                line.push('\n');
+                line.push_str(&next);
             },
-            Err(_) => println!("Redirection in wrong format."),
-        }
-    } else {
-        match handle_input(command, arguments.clone(), shell) {
-            Ok(output) => {
-                if output.to_string().is_empty() {
-                    print!("");
-                } else {
-                    println!("{}", output.to_string().trim());
+            Err(ReadlineError::Eof) => {
+                if let Err(err) = parser::parse(&line, &shell.aliases) {
+                    eprintln!("rshell: {}", err);
                 }
+                shell.last_status = 2;
+                return None;
             },
-            Err(err) => println!("{}", get_error_message(&err).unwrap()),
+            Err(_) => return None,
+        }
+    }
+    Some(line)
+}
+
+/// Brings the line editor's history in line with the shell's, either by
+/// adding the `line` just run or by reloading every entry when the
+/// `history` builtin changed them.
+fn sync_history(editor: &mut Editor<CommandCompleter, FileHistory>, shell: &mut Shell, line: Option<&str>) {
+    let _ = editor.history_mut().set_max_len(shell.history_size());
+
+    if shell.history.changed || line.is_none() {
+        let _ = editor.clear_history();
+        for entry in &shell.history.entries {
+            let _ = editor.add_history_entry(entry.as_str());
         }
+        shell.history.changed = false;
+    } else if let Some(line) = line {
+        let _ = editor.add_history_entry(line);
     }
 }
 
-fn handle_input(command: &str, arguments: Vec<String>, shell: &Shell) -> Result<Output, ShellError> {
-    let home = &shell.environment["home"];
-    let path = &shell.environment["path"];
+fn handle_input(command: &str, arguments: Vec<String>, shell: &mut Shell) -> Result<Output, ShellError> {
+    let home = shell.get_var("HOME").unwrap_or_default();
+    let path = shell.get_var("PATH").unwrap_or_default();
+
+    if let Some(function) = shell.functions.get(command).cloned() {
+        return match call_function(&function, arguments, shell) {
+            0 => Ok(Output::String(String::new())),
+            status => Err(ShellError::ExitStatus(status)),
+        };
+    }
 
     match command {
         "pwd" => current_dir()
@@ -101,14 +532,18 @@ fn handle_input(command: &str, arguments: Vec<String>, shell: &Shell) -> Result<
         }),
 
         "cd" => {
-            let directory = &arguments[0];
+            let directory = match arguments.first() {
+                Some(directory) => directory,
+                None if !home.is_empty() => &home,
+                None => return Err(ShellError::InvalidArgument("cd: HOME not set".to_string())),
+            };
 
             if directory.is_empty() {
                 return Err(ShellError::InvalidArgument("No directory specified".to_string()));
             }
 
             let path = match directory.as_str() {
-                "~" => PathBuf::from(home),
+                "~" => PathBuf::from(&home),
                 dir => PathBuf::from(dir)
             };
 
@@ -118,169 +553,977 @@ fn handle_input(command: &str, arguments: Vec<String>, shell: &Shell) -> Result<
             
         },
 
-        "echo" => Ok(Output::String(format!("{}\n", arguments.join(" ").trim()).to_string())),
+        "echo" => Ok(Output::String(format!("{}\n", arguments.join(" ").trim()))),
 
         "type" => {
-            let command = &&arguments.join("");
-            if shell.builtins.contains(&command) {
-                Ok(Output::String(String::from(format!("{} is a shell builtin", command))))
-            }
-            else { 
-                match executable_exists(&path, command) {
-                    Ok(x) => Ok(Output::String(x)),
-                    Err(x) => Err(x)
-                }
+            let command = &arguments.join("");
+            if let Some(value) = shell.aliases.get(command) {
+                Ok(Output::String(format!("{} is aliased to `{}'", command, value)))
+            }
+            else if let Some(function) = shell.functions.get(command) {
+                Ok(Output::String(format!("{} is a function\n{}", command, function.source)))
+            }
+            else if parser::is_reserved(command) {
+                Ok(Output::String(format!("{} is a shell keyword", command)))
+            }
+            else if shell.builtins.contains(command) {
+                Ok(Output::String(format!("{} is a shell builtin", command)))
+            }
+            else if let Some((full_path, _)) = shell.hash_table().get(command) {
+                Ok(Output::String(format!("{} is hashed ({})", command, full_path)))
+            }
+            else {
+                is_executable(&path, command)
+                    .map(|full_path| Output::String(format!("{} is {}", command, full_path)))
+                    .map_err(|_| ShellError::ExecutableNotFound(format!("{}: not found", command)))
             }
         },
 
         "cat" => {
             let mut vec_stdout: Vec<String> = vec![];
             let mut vec_stderr: Vec<String> = vec![];
+
+            if arguments.is_empty() {
+                // Read the descriptor itself: the buffer of `io::stdin` may
+                // hold input meant for the shell.
+                let mut stdin = ManuallyDrop::new(unsafe { fs::File::from_raw_fd(0) });
+                let mut input = String::new();
+                stdin.read_to_string(&mut input).map_err(|err| ShellError::InvalidArgument(format!("cat: {}", err)))?;
+                vec_stdout.push(input);
+            }
             
             for i in &arguments {
                 let c = fs::read_to_string(i).map_err(|_| ShellError::FileNotFound(format!("cat: {}: No such file or directory", i)));
                 
                 match c {
                     Ok(x) => vec_stdout.push(x),
-                    Err(e) => vec_stderr.push(format!("{}\n", get_error_message(&e).unwrap().to_string())),
+                    Err(e) => vec_stderr.push(format!("{}\n", get_error_message(&e).unwrap())),
                 }
             }
 
             Ok(Output::StdOutErr(vec_stdout.join(""), vec_stderr.join("\n")))
         },
 
-        "exit" => {
-            let argument = &arguments.join("");
-            match argument.as_str() {
-                "0" => exit(0),
-                _ => Err(ShellError::InvalidArgument(String::from(format!("exit: command not found"))))
+        "test" | "[" => {
+            let mut arguments = arguments;
+            if command == "[" && arguments.pop().as_deref() != Some("]") {
+                return Err(ShellError::Usage("[: missing `]'".to_string()));
             }
-        }
-        _ => {
-            match is_executable(&path, command) {
-                Ok(_) => {
-                    let output = Command::new(command)
-                        .args(&arguments)
-                        .output()
-                        .expect("Failed to execute process");
 
-                    if output.status.success() {
-                        Ok(Output::String(String::from_utf8(output.stdout).expect("Command executed successfully")))
-                    } else {
-                        Err(ShellError::ExecutionError(String::from_utf8(output.stderr).expect("Command failed")))
-                    }
+            match condition::test(&arguments, shell)? {
+                true => Ok(Output::String(String::new())),
+                false => Err(ShellError::ExitStatus(1)),
+            }
+        },
+
+        ":" | "true" => Ok(Output::String(String::new())),
+
+        "false" => Err(ShellError::ExitStatus(1)),
+
+        "break" | "continue" => {
+            let levels = match arguments.first() {
+                Some(n) => n.parse::<usize>().ok().filter(|n| *n > 0)
+                    .ok_or_else(|| ShellError::InvalidArgument(format!("{}: {}: loop count out of range", command, n)))?,
+                None => 1,
+            };
+
+            if shell.loop_depth == 0 {
+                return Err(ShellError::InvalidArgument(format!("{}: only meaningful in a `for', `while', or `until' loop", command)));
+            }
+
+            let levels = levels.min(shell.loop_depth);
+            shell.flow = if command == "break" { Flow::Break(levels) } else { Flow::Continue(levels) };
+            Ok(Output::String(String::new()))
+        },
+
+        "local" => {
+            if shell.locals.is_empty() {
+                return Err(ShellError::InvalidArgument("local: can only be used in a function".to_string()));
+            }
+
+            for argument in &arguments {
+                let (name, value) = match argument.split_once('=') {
+                    Some((name, value)) => (name, Some(value)),
+                    None => (argument.as_str(), None),
+                };
+
+                if !is_name(name) {
+                    return Err(ShellError::InvalidArgument(format!("local: `{}': not a valid identifier", argument)));
+                }
+
+                shell.declare_local(name);
+                match value {
+                    Some(value) => shell.set_var(name, value),
+                    None => { shell.environment.remove(name); },
                 }
-                Err(_) => Err(ShellError::FileNotFound(String::from(format!("{}: command not found", command)))),
             }
-        }
-    }
-}
 
-fn redirect_output(redirection: Redirection, content: Result<Output, String>, command: String) -> Result<(), String> {
-    let path = Path::new(&redirection.path);
+            Ok(Output::String(String::new()))
+        },
 
-    if let Some(parent) = path.parent() {
-        std::fs::create_dir_all(parent)
-            .map_err(|e| e.to_string())?;
-    }
+        "return" => {
+            if shell.locals.is_empty() && shell.source_depth == 0 {
+                return Err(ShellError::InvalidArgument("return: can only `return' from a function or sourced script".to_string()));
+            }
 
-    let operation = RedirType::get_redir_op(redirection.r_type.clone());
+            let code = match arguments.first() {
+                Some(argument) => argument.parse::<i32>()
+                    .map_err(|_| ShellError::InvalidArgument(format!("return: {}: numeric argument required", argument)))?,
+                None => shell.last_status,
+            };
 
-    let file = match redirection.r_type {
-        RedirType::None => None,
-        _ => {
-            let file = OpenOptions::new()
-                .create(true)
-                .write(true)
-                .append(operation == Some(RedirOp::Append))
-                .truncate(operation == Some(RedirOp::Write))
-                .open(path)
-                .map_err(|e| e.to_string())?;
-            Some(file)
-        }
-    };
+            shell.flow = Flow::Return(code);
+            Ok(Output::String(String::new()))
+        },
 
-    match content {
-        Ok(Output::String(x)) => {
-            match redirection.r_type {
-                RedirType::Stderr(_) => {
-                    if command != "echo" {
-                        if let Some(mut f) = file {
-                            f.write_all(x.as_bytes())
-                                .map_err(|e| e.to_string())?;
-                        }
-                    } else {
-                        println!("{}", x.trim());
-                    }
+        "shift" => {
+            let count = match arguments.first() {
+                Some(argument) => argument.parse::<usize>()
+                    .map_err(|_| ShellError::InvalidArgument(format!("shift: {}: numeric argument required", argument)))?,
+                None => 1,
+            };
+
+            if count > shell.positional.len() {
+                return Err(ShellError::InvalidArgument("shift: shift count out of range".to_string()));
+            }
+
+            shell.positional.drain(..count);
+            Ok(Output::String(String::new()))
+        },
+
+        "set" => {
+            if arguments.is_empty() {
+                let mut names: Vec<&String> = shell.environment.keys().collect();
+                names.sort();
+                let listing: Vec<String> = names
+                    .iter()
+                    .map(|name| format!("{}={}", name, quote_word(&shell.environment[*name])))
+                    .collect();
+                return Ok(Output::String(listing.join("\n")));
+            }
+
+            // Options come first, then the positional parameters.
+            let mut positional = None;
+            let mut arguments = arguments.into_iter();
+            while let Some(argument) = arguments.next() {
+                if argument == "--" {
+                    positional = Some(arguments.collect());
+                    break;
+                }
+                if argument.len() < 2 || !argument.starts_with(['-', '+']) {
+                    positional = Some(std::iter::once(argument).chain(arguments).collect());
+                    break;
+                }
+
+                let enable = argument.starts_with('-');
+                for flag in argument[1..].chars() {
+                    let name = match flag {
+                        'o' => match arguments.next() {
+                            Some(name) => name,
+                            None => return Ok(Output::String(list_options(shell, !enable))),
+                        },
+                        flag => OPTIONS
+                            .iter()
+                            .find(|(_, letter)| *letter == Some(flag))
+                            .map(|(name, _)| name.to_string())
+                            .ok_or_else(|| ShellError::Usage(format!("set: {}{}: invalid option", &argument[..1], flag)))?,
+                    };
+                    set_option(shell, &name, enable)?;
+                }
+            }
+
+            if let Some(positional) = positional {
+                shell.positional = positional;
+            }
+            Ok(Output::String(String::new()))
+        },
+
+        "source" | "." => {
+            let Some((name, arguments)) = arguments.split_first() else {
+                return Err(ShellError::Usage(format!("{}: filename argument required", command)));
+            };
+
+            let file = find_source_file(&path, name)
+                .ok_or_else(|| ShellError::FileNotFound(format!("{}: {}: file not found", command, name)))?;
+
+            match source_file(Path::new(&file), arguments.to_vec(), shell)? {
+                0 => Ok(Output::String(String::new())),
+                status => Err(ShellError::ExitStatus(status)),
+            }
+        },
+
+        "alias" => {
+            let names = match arguments.first().map(|x| x.as_str()) {
+                Some("-p") => &arguments[1..],
+                _ => &arguments[..],
+            };
+
+            if names.is_empty() {
+                let mut aliases: Vec<(&String, &String)> = shell.aliases.iter().collect();
+                aliases.sort();
+                let listing: Vec<String> = aliases
+                    .iter()
+                    .map(|(name, value)| format!("alias {}='{}'", name, value.replace('\'', "'\\''")))
+                    .collect();
+                return Ok(Output::String(listing.join("\n")));
+            }
+
+            let mut listing = vec![];
+            let mut missing = vec![];
+            for argument in names {
+                match argument.split_once('=') {
+                    Some((name, value)) if parser::is_alias_name(name) => {
+                        shell.aliases.insert(name.to_string(), value.to_string());
+                    },
+                    Some(_) => missing.push(format!("alias: `{}': invalid alias name", argument)),
+                    None => match shell.aliases.get(argument) {
+                        Some(value) => listing.push(format!("alias {}='{}'", argument, value.replace('\'', "'\\''"))),
+                        None => missing.push(format!("alias: {}: not found", argument)),
+                    },
+                }
+            }
+
+            if missing.is_empty() {
+                Ok(Output::String(listing.join("\n")))
+            } else {
+                Ok(Output::StdOutErr(listing.join("\n"), missing.join("\n")))
+            }
+        },
+
+        "unalias" => {
+            if arguments.first().is_some_and(|x| x == "-a") {
+                shell.aliases.clear();
+                return Ok(Output::String(String::new()));
+            }
+            if arguments.is_empty() {
+                return Err(ShellError::Usage("unalias: usage: unalias [-a] name [name ...]".to_string()));
+            }
+
+            let missing: Vec<String> = arguments
+                .iter()
+                .filter(|name| shell.aliases.remove(name.as_str()).is_none())
+                .map(|name| format!("unalias: {}: not found", name))
+                .collect();
+
+            if missing.is_empty() {
+                Ok(Output::String(String::new()))
+            } else {
+                Ok(Output::StdOutErr(String::new(), missing.join("\n")))
+            }
+        },
+
+        "hash" => {
+            match arguments.first().map(String::as_str) {
+                Some("-r") => {
+                    shell.hash_table().clear();
+                    forget_executables();
+                    return Ok(Output::String(String::new()));
                 },
-                RedirType::Stdout(_) => {
-                    if !x.starts_with("err") {
-                        if let Some(mut f) = file {
-                            f.write_all(x.as_bytes())
-                                .map_err(|e| e.to_string())?;
-                        }
-                    } else {
-                        println!("{}", x.trim());
-                    }
-                    
+                Some("-p") => {
+                    let (Some(full_path), Some(name)) = (arguments.get(1), arguments.get(2)) else {
+                        return Err(ShellError::Usage("hash: usage: hash [-lr] [-p pathname] [-dt] [name ...]".to_string()));
+                    };
+                    shell.hash_table().insert(name.to_string(), (full_path.to_string(), 0));
+                    return Ok(Output::String(String::new()));
                 },
-                RedirType::None => {
-                    if x.is_empty() {
-                        print!("");
-                    } else {
-                        println!("{}", x.trim());
+                Some("-l") | None => {
+                    let listed = !arguments.is_empty();
+                    let table = shell.hash_table();
+                    if table.is_empty() {
+                        return Ok(Output::String("hash: hash table empty".to_string()));
                     }
+                    let lines: Vec<String> = table
+                        .iter()
+                        .map(|(name, (full_path, hits))| match listed {
+                            true => format!("builtin hash -p {} {}", full_path, name),
+                            false => format!("{:>4}\t{}", hits, full_path),
+                        })
+                        .collect();
+                    let header = if listed { "" } else { "hits\tcommand\n" };
+                    return Ok(Output::String(format!("{}{}", header, lines.join("\n"))));
+                },
+                _ => {},
+            }
+
+            let (flag, names) = match arguments[0].as_str() {
+                "-d" | "-t" => (Some(arguments[0].as_str()), &arguments[1..]),
+                _ => (None, &arguments[..]),
+            };
+
+            let mut paths = vec![];
+            let mut missing = vec![];
+            for name in names {
+                let found = match flag {
+                    Some("-d") => shell.hash_table().remove(name).is_some(),
+                    Some(_) => match shell.hash_table().get(name) {
+                        Some((full_path, _)) => {
+                            paths.push(full_path.clone());
+                            true
+                        },
+                        None => false,
+                    },
+                    None => shell.builtins.contains(name) || shell.functions.contains_key(name) || shell.command_path(name).is_some(),
+                };
+                if !found {
+                    missing.push(format!("hash: {}: not found", name));
                 }
             }
+
+            if missing.is_empty() {
+                Ok(Output::String(paths.join("\n")))
+            } else {
+                Ok(Output::StdOutErr(paths.join("\n"), missing.join("\n")))
+            }
         },
-        Ok(Output::StdOutErr(stdout, stderr)) => {
-            match redirection.r_type {
-                RedirType::Stdout(_) => {
-                    if !stderr.is_empty() {
-                        println!("{}", stderr.trim());
-                    }
-                    
-                    if let Some(mut f) = file {
-                        f.write_all(stdout.as_bytes())
-                            .map_err(|e| e.to_string())?;
-                    }
-                },
-                RedirType::Stderr(_) => {
-                    if let Some(mut f) = file {
-                        f.write_all(stderr.as_bytes())
-                            .map_err(|e| e.to_string())?;
+
+        "bind" => bind(&arguments, shell),
+
+        "complete" => {
+            let (spec, flags, names) = CompletionSpec::parse(command, &arguments, "pr")?;
+
+            if flags.contains(&'r') {
+                if names.is_empty() {
+                    shell.completions.clear();
+                }
+                let missing: Vec<String> = names
+                    .iter()
+                    .filter(|name| shell.completions.remove(name.as_str()).is_none())
+                    .map(|name| format!("complete: {}: no completion specification", name))
+                    .collect();
+                return Ok(Output::StdOutErr(String::new(), missing.join("\n")));
+            }
+
+            if flags.contains(&'p') || names.is_empty() {
+                let mut listed: Vec<&String> = if names.is_empty() { shell.completions.keys().collect() } else { names.iter().collect() };
+                listed.sort();
+                let mut listing = vec![];
+                let mut missing = vec![];
+                for name in listed {
+                    match shell.completions.get(name) {
+                        Some(spec) => listing.push(spec.to_command(name)),
+                        None => missing.push(format!("complete: {}: no completion specification", name)),
                     }
+                }
+                return Ok(Output::StdOutErr(listing.join("\n"), missing.join("\n")));
+            }
+
+            for name in names {
+                shell.completions.insert(name, spec.clone());
+            }
+            Ok(Output::String(String::new()))
+        },
+
+        "compgen" => {
+            let (spec, _, words) = CompletionSpec::parse(command, &arguments, "")?;
+            let word = Word::literal(words.first().map_or("", |word| word.as_str()));
+            let cwd = current_dir().map(|dir| dir.to_string_lossy().to_string()).unwrap_or_default();
+
+            let candidates = generate(&spec, &word, "", 0, &cwd, shell);
+            if candidates.is_empty() {
+                return Err(ShellError::ExitStatus(1));
+            }
+            let listing: Vec<&str> = candidates
+                .iter()
+                .map(|candidate| if candidate.len() > 1 { candidate.trim_end_matches('/') } else { candidate })
+                .collect();
+            Ok(Output::String(listing.join("\n")))
+        },
+
+        "history" => {
+            let size = shell.history_size();
+            let file = |argument: Option<&String>| argument
+                .map(PathBuf::from)
+                .or_else(|| shell.history_file())
+                .ok_or_else(|| ShellError::InvalidArgument("history: HISTFILE is not set".to_string()));
+            let io_error = |path: &Path, err: io::Error| ShellError::ExecutionError(format!("history: {}: {}", path.display(), err));
 
-                    if !stdout.is_empty() {
-                        println!("{}", stdout.trim());
+            match arguments.first().map(|x| x.as_str()) {
+                Some(option) if option.starts_with("--") => return query_history(&arguments, shell),
+                Some("-c") => shell.history.clear(),
+                Some("-d") => {
+                    let argument = arguments.get(1)
+                        .ok_or_else(|| ShellError::Usage("history: -d: option requires an argument".to_string()))?;
+                    let deleted = argument.parse::<i64>().is_ok_and(|offset| shell.history.delete(offset));
+                    if !deleted {
+                        return Err(ShellError::InvalidArgument(format!("history: {}: history position out of range", argument)));
                     }
                 },
-                RedirType::None => {
-                    if !stdout.is_empty() {
-                        println!("{}", stdout.trim());
-                    }
-                    if !stderr.is_empty() {
-                        eprintln!("{}", stderr.trim());
-                    }
+                Some("-w") => {
+                    let path = file(arguments.get(1))?;
+                    shell.history.write(&path).map_err(|err| io_error(&path, err))?;
+                },
+                Some("-r") => {
+                    let path = file(arguments.get(1))?;
+                    shell.history.read(&path, size).map_err(|err| io_error(&path, err))?;
+                },
+                Some(option) if option.starts_with('-') => {
+                    return Err(ShellError::Usage(format!("history: {}: invalid option\nhistory: usage: history [-c] [-d offset] [n] [-w|-r [filename]]", option)));
+                },
+                count => {
+                    let entries = &shell.history.entries;
+                    let count = match count {
+                        Some(count) => count.parse::<usize>()
+                            .map_err(|_| ShellError::InvalidArgument(format!("history: {}: numeric argument required", count)))?,
+                        None => entries.len(),
+                    };
+
+                    let start = entries.len().saturating_sub(count);
+                    let listing: Vec<String> = entries[start..]
+                        .iter()
+                        .enumerate()
+                        .map(|(i, entry)| format!("{:<5} {}", start + i + 1, entry))
+                        .collect();
+                    return Ok(Output::String(listing.join("\n")));
                 },
             }
+
+            Ok(Output::String(String::new()))
+        },
+
+        "export" => {
+            let (unexport, names) = match arguments.first().map(|x| x.as_str()) {
+                Some("-n") => (true, &arguments[1..]),
+                Some("-p") => (false, &arguments[1..]),
+                _ => (false, &arguments[..]),
+            };
+
+            if names.is_empty() {
+                let mut exported = shell.exported_vars();
+                exported.sort();
+                let listing: Vec<String> = exported
+                    .iter()
+                    .map(|(name, value)| format!("export {}=\"{}\"", name, escape_double_quoted(value)))
+                    .collect();
+                return Ok(Output::String(listing.join("\n")));
+            }
+
+            for argument in names {
+                let (name, value) = match argument.split_once('=') {
+                    Some((name, value)) => (name, Some(value)),
+                    None => (argument.as_str(), None),
+                };
+
+                if !is_name(name) {
+                    return Err(ShellError::InvalidArgument(format!("export: `{}': not a valid identifier", argument)));
+                }
+
+                if let Some(value) = value {
+                    shell.set_var(name, value);
+                }
+                if unexport {
+                    shell.exported.remove(name);
+                } else {
+                    shell.exported.insert(name.to_string());
+                }
+            }
+
+            Ok(Output::String(String::new()))
+        },
+
+        "unset" => {
+            let (functions, names) = match arguments.first().map(|x| x.as_str()) {
+                Some("-f") => (true, &arguments[1..]),
+                Some("-v") => (false, &arguments[1..]),
+                _ => (false, &arguments[..]),
+            };
+
+            for name in names {
+                if functions {
+                    shell.functions.remove(name);
+                } else if is_name(name) {
+                    shell.environment.remove(name);
+                    shell.arrays.remove(name);
+                    shell.exported.remove(name);
+                } else {
+                    return Err(ShellError::InvalidArgument(format!("unset: `{}': not a valid identifier", name)));
+                }
+            }
+
+            Ok(Output::String(String::new()))
+        },
+
+        "exit" => {
+            let code = match arguments.first() {
+                Some(argument) => argument.parse::<i32>()
+                    .map_err(|_| ShellError::InvalidArgument(format!("exit: {}: numeric argument required", argument)))?,
+                None => shell.last_status,
+            };
+
+            shell.flow = Flow::Exit(code);
+            Ok(Output::String(String::new()))
         }
-        Err(e) => {
-            match redirection.r_type {
-                RedirType::Stderr(_) => {
-                    if let Some(mut f) = file {
-                        f.write_all(e.as_bytes())
-                            .map_err(|e| e.to_string())?;
+        _ => {
+            match shell.command_path(command) {
+                Some(full_path) => {
+                    if let Some((_, hits)) = shell.hashed.get_mut(command) {
+                        *hits += 1;
                     }
-                },
-                _ => {
-                    if !e.is_empty() {
-                        println!("{}", e.trim());
+                    let status = Command::new(full_path)
+                        .arg0(command)
+                        .args(&arguments)
+                        .env_clear()
+                        .envs(shell.exported_vars())
+                        .status()
+                        .map_err(|err| ShellError::ExecutionError(format!("{}: {}", command, err)))?;
+
+                    match status.code() {
+                        Some(0) => Ok(Output::String(String::new())),
+                        Some(code) => Err(ShellError::ExitStatus(code)),
+                        None => Err(ShellError::ExitStatus(128 + status.signal().unwrap_or(0))),
                     }
                 }
+                None => Err(ShellError::CommandNotFound(format!("{}: command not found", command))),
             }
-        },
+        }
     }
+}
+
+/// `history --option ... [text]`: searches the history database.
+fn query_history(arguments: &[String], shell: &Shell) -> Result<Output, ShellError> {
+    let file = shell.history_db()
+        .ok_or_else(|| ShellError::InvalidArgument("history: HISTDB is not set".to_string()))?;
+
+    let mut query = histdb::Query::default();
+    let mut text = vec![];
+    let mut arguments = arguments.iter();
+    while let Some(argument) = arguments.next() {
+        let mut value = |option: &str| arguments.next()
+            .ok_or_else(|| ShellError::Usage(format!("history: {}: option requires an argument", option)));
+        let time = |value: &String| histdb::parse_time(value)
+            .ok_or_else(|| ShellError::InvalidArgument(format!("history: {}: invalid time", value)));
+        let number = |value: &String| value.parse()
+            .map_err(|_| ShellError::InvalidArgument(format!("history: {}: numeric argument required", value)));
 
+        match argument.as_str() {
+            "--here" => query.cwd = Some(current_dir()?.to_string_lossy().to_string()),
+            "--dir" => {
+                let dir = value(argument)?;
+                let dir = fs::canonicalize(dir).unwrap_or_else(|_| PathBuf::from(dir));
+                query.cwd = Some(dir.to_string_lossy().to_string());
+            },
+            "--host" => query.host = Some(value(argument)?.clone()),
+            "--status" => query.status = Some(number(value(argument)?)?),
+            "--failed" => query.failed = true,
+            "--since" => query.since = Some(time(value(argument)?)?),
+            "--until" => query.until = Some(time(value(argument)?)?),
+            "--limit" => {
+                let limit = value(argument)?;
+                let limit = usize::from_str(limit)
+                    .map_err(|_| ShellError::InvalidArgument(format!("history: {}: invalid limit", limit)))?;
+                query.limit = Some(limit);
+            },
+            "--" => text.extend(arguments.by_ref().cloned()),
+            option if option.starts_with("--") => {
+                return Err(ShellError::Usage(format!(
+                    "history: {}: invalid option\nhistory: usage: history [--here] [--dir dir] [--host host] [--status n] [--failed] [--since time] [--until time] [--limit n] [text]",
+                    option
+                )));
+            },
+            word => text.push(word.to_string()),
+        }
+    }
+    if !text.is_empty() {
+        query.text = Some(text.join(" "));
+    }
+
+    let home = shell.get_var("HOME").unwrap_or_default();
+    let records = histdb::search(&file, &query)
+        .map_err(|err| ShellError::ExecutionError(format!("history: {}: {}", file.display(), err)))?;
+    let listing: Vec<String> = records.iter().map(|record| record.display(&home)).collect();
+    Ok(Output::String(listing.join("\n")))
+}
+
+/// `bind`: lists and changes key bindings, in the syntax of inputrc.
+fn bind(arguments: &[String], shell: &mut Shell) -> Result<Output, ShellError> {
+    let usage = || ShellError::Usage("bind: usage: bind [-lpsvX] [-m keymap] [-f filename] [-q name] [-u name] [-r keyseq] [-x keyseq:shell-command] [keyseq:readline-function or readline-command]".to_string());
+    let function = |name: &str| match FUNCTIONS.contains(&name) {
+        true => Ok(name.to_string()),
+        false => Err(ShellError::InvalidArgument(format!("bind: `{}': unknown function name", name))),
+    };
+
+    let mut listing = vec![];
+    let mut errors = vec![];
+    let mut arguments = arguments.iter();
+    while let Some(argument) = arguments.next() {
+        let mut value = || arguments.next().ok_or_else(usage);
+        match argument.as_str() {
+            "-l" => listing.extend(FUNCTIONS.iter().map(|name| name.to_string())),
+            "-p" => listing.extend(shell.keys.listing('p')),
+            "-s" => listing.extend(shell.keys.listing('s')),
+            "-X" => listing.extend(shell.keys.listing('X')),
+            "-v" => {
+                let mode = if shell.option("vi") { "vi" } else { "emacs" };
+                listing.push(format!("set editing-mode {}", mode));
+                listing.extend(shell.keys.variables.iter().map(|(name, value)| format!("set {} {}", name, value)));
+            },
+            // Emacs and vi insert mode share one keymap.
+            "-m" => {
+                value()?;
+            },
+            "-f" => {
+                let file = value()?;
+                if !Path::new(file).is_file() {
+                    return Err(ShellError::FileNotFound(format!("bind: {}: cannot read: No such file or directory", file)));
+                }
+                errors.extend(read_key_file(Path::new(file), shell).into_iter().map(|err| format!("bind: {}", err)));
+            },
+            "-q" => {
+                let name = function(value()?)?;
+                let keys: Vec<&str> = shell.keys.bindings
+                    .iter()
+                    .filter(|(_, _, binding)| *binding == keys::Binding::Function(name.clone()))
+                    .map(|(keyseq, _, _)| keyseq.as_str())
+                    .collect();
+                match keys.is_empty() {
+                    true => listing.push(format!("{} is not bound to any keys.", name)),
+                    false => listing.push(format!("{} can be invoked via {}.", name, keys.join(", "))),
+                }
+            },
+            "-u" => {
+                let name = function(value()?)?;
+                shell.keys.bindings.retain(|(_, _, binding)| *binding != keys::Binding::Function(name.clone()));
+            },
+            "-r" => {
+                let keyseq = value()?;
+                let (_, sequence) = keys::key_sequence(keyseq)
+                    .map_err(|err| ShellError::InvalidArgument(format!("bind: {}", err)))?;
+                shell.keys.unbind(&sequence);
+            },
+            "-x" => match keys::parse_line(value()?, true) {
+                Ok(Some(directive)) => apply_key_directive(shell, directive),
+                Ok(None) => {},
+                Err(err) => errors.push(format!("bind: {}", err)),
+            },
+            option if option.starts_with('-') => return Err(usage()),
+            line => match keys::parse_line(line, false) {
+                Ok(Some(directive)) => apply_key_directive(shell, directive),
+                Ok(None) => {},
+                Err(err) => errors.push(format!("bind: {}", err)),
+            },
+        }
+    }
+
+    Ok(Output::StdOutErr(listing.join("\n"), errors.join("\n")))
+}
+
+fn set_option(shell: &mut Shell, name: &str, enable: bool) -> Result<(), ShellError> {
+    if !OPTIONS.iter().any(|(option, _)| *option == name) {
+        return Err(ShellError::Usage(format!("set: {}: invalid option name", name)));
+    }
+
+    if enable {
+        // The editing modes exclude one another.
+        match name {
+            "vi" => { shell.options.remove("emacs"); },
+            "emacs" => { shell.options.remove("vi"); },
+            _ => {},
+        }
+        shell.options.insert(name.to_string());
+    } else {
+        shell.options.remove(name);
+    }
     Ok(())
 }
+
+/// The readline init file: `$INPUTRC`, `~/.inputrc` when it exists,
+/// `/etc/inputrc` otherwise.
+fn inputrc(shell: &Shell) -> PathBuf {
+    if let Some(file) = shell.get_var("INPUTRC").filter(|file| !file.is_empty()) {
+        return PathBuf::from(file);
+    }
+    let file = home_path(shell, ".inputrc");
+    if file.is_file() { file } else { PathBuf::from("/etc/inputrc") }
+}
+
+/// Applies the settings and key bindings of an inputrc file. Returns the
+/// errors, with the file and line they come from.
+fn read_key_file(path: &Path, shell: &mut Shell) -> Vec<String> {
+    let mode = if shell.option("vi") { "vi" } else { "emacs" };
+    let term = shell.get_var("TERM").unwrap_or_default();
+    let mut directives = vec![];
+    let mut errors = vec![];
+    keys::read_file(path, mode, &term, &mut directives, &mut errors, 0);
+    for directive in directives {
+        apply_key_directive(shell, directive);
+    }
+    errors
+}
+
+/// `set editing-mode` switches the `vi` or `emacs` option; other settings
+/// and bindings go to the key configuration.
+fn apply_key_directive(shell: &mut Shell, directive: Directive) {
+    match directive {
+        Directive::Set(name, value) if name == "editing-mode" => {
+            if value == "vi" || value == "emacs" {
+                let _ = set_option(shell, &value, true);
+            }
+        },
+        directive => shell.keys.apply(directive),
+    }
+}
+
+/// Binds the keys of `keys` in the editor, in place of those of `previous`.
+/// Ctrl-F accepts the hint unless bound to something else.
+fn apply_keys(editor: &mut Editor<CommandCompleter, FileHistory>, previous: &KeyConfig, keys: &KeyConfig, pending: &keys::Pending, search: &Arc<Mutex<Option<String>>>) {
+    for sequence in previous.sequences() {
+        editor.unbind_sequence(Event::KeySeq(sequence));
+    }
+    editor.bind_sequence(KeyEvent::ctrl('F'), EventHandler::Conditional(Box::new(AcceptHint)));
+    for (sequence, handler) in keys.handlers(pending, search) {
+        editor.bind_sequence(Event::KeySeq(sequence), handler);
+    }
+
+    let variable = |name: &str| keys.variables.get(name).map(String::as_str);
+    editor.set_bell_style(match variable("bell-style") {
+        Some("none" | "off") => BellStyle::None,
+        Some("visible") => BellStyle::Visible,
+        _ => BellStyle::Audible,
+    });
+    if let Some(limit) = variable("completion-query-items").and_then(|items| items.parse().ok()) {
+        editor.set_completion_prompt_limit(limit);
+    }
+    if let Some(timeout) = variable("keyseq-timeout").and_then(|timeout| timeout.parse::<i32>().ok()) {
+        editor.set_keyseq_timeout(u16::try_from(timeout).ok().filter(|timeout| *timeout > 0));
+    }
+}
+
+/// Parses and runs `line`, as typed at the prompt.
+fn run_line(line: &str, shell: &mut Shell) {
+    match parser::parse(line, &shell.aliases) {
+        Ok(list) => {
+            execute_list(&list, shell);
+        },
+        Err(err) => {
+            eprintln!("rshell: {}", err);
+            shell.last_status = 2;
+        }
+    }
+}
+
+/// Opens `line` in `$VISUAL`, `$EDITOR` or `vi`, and returns the saved
+/// command, unless the editor failed or it was left empty.
+fn edit_line(line: &str, shell: &mut Shell) -> Option<String> {
+    let file = env::temp_dir().join(format!("rshell-edit-{}.sh", std::process::id()));
+    if let Err(err) = fs::write(&file, format!("{}\n", line)) {
+        eprintln!("rshell: {}: {}", file.display(), err);
+        return None;
+    }
+
+    let editor = ["VISUAL", "EDITOR"]
+        .iter()
+        .find_map(|name| shell.get_var(name).filter(|editor| !editor.is_empty()))
+        .unwrap_or_else(|| "vi".to_string());
+    run_line(&format!("{} {}", editor, quote_word(&file.to_string_lossy())), shell);
+    let edited = fs::read_to_string(&file);
+    let _ = fs::remove_file(&file);
+
+    let command = edited.ok()?.trim_end().to_string();
+    (shell.last_status == 0 && !command.trim().is_empty()).then_some(command)
+}
+
+/// The state of every option, for `set -o`, or as the commands restoring
+/// it, for `set +o`.
+fn list_options(shell: &Shell, as_commands: bool) -> String {
+    OPTIONS
+        .iter()
+        .map(|(name, _)| match (as_commands, shell.option(name)) {
+            (true, on) => format!("set {}o {}", if on { '-' } else { '+' }, name),
+            (false, on) => format!("{:<15}\t{}", name, if on { "on" } else { "off" }),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn escape_double_quoted(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | '$' | '`') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invocation(words: &[&str]) -> Result<Invocation, String> {
+        parse_arguments(&words.iter().map(|word| word.to_string()).collect::<Vec<_>>())
+    }
+
+    fn mode(words: &[&str]) -> Result<Mode, String> {
+        invocation(words).map(|invocation| invocation.mode)
+    }
+
+    fn strings(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn command_line_modes() {
+        assert_eq!(mode(&["rshell"]), Ok(Mode::Stdin));
+        assert_eq!(
+            mode(&["rshell", "-c", "echo $0", "name", "a", "b"]),
+            Ok(Mode::Command { source: "echo $0".to_string(), name: Some("name".to_string()), positional: strings(&["a", "b"]) })
+        );
+        assert_eq!(mode(&["rshell", "-c", "true"]), Ok(Mode::Command { source: "true".to_string(), name: None, positional: vec![] }));
+        assert_eq!(mode(&["rshell", "run.sh", "-c", "x"]), Ok(Mode::Script { path: "run.sh".to_string(), positional: strings(&["-c", "x"]) }));
+        assert_eq!(mode(&["rshell", "--", "-x"]), Ok(Mode::Script { path: "-x".to_string(), positional: vec![] }));
+    }
+
+    #[test]
+    fn command_line_errors() {
+        assert_eq!(mode(&["rshell", "-c"]), Err("rshell: -c: option requires an argument".to_string()));
+        assert!(mode(&["rshell", "-z", "x"]).unwrap_err().starts_with("rshell: -z: invalid option\n"));
+    }
+
+    #[test]
+    fn login_and_startup_options() {
+        let login = invocation(&["-rshell"]).unwrap();
+        assert!(login.login && login.rc && login.profile);
+        let options = invocation(&["rshell", "-l", "--norc", "--noprofile"]).unwrap();
+        assert!(options.login && !options.rc && !options.profile);
+        assert!(!invocation(&["rshell", "script.sh", "-l"]).unwrap().login);
+    }
+
+    #[test]
+    fn startup_files_order() {
+        let mut shell = Shell::new();
+        shell.set_var("HOME", "/home/user");
+        shell.set_var("XDG_CONFIG_HOME", "/nonexistent");
+        let files = |words: &[&str], interactive: bool| startup_files(&invocation(words).unwrap(), interactive, &shell);
+        let paths = |paths: &[&str]| paths.iter().map(PathBuf::from).collect::<Vec<_>>();
+
+        assert_eq!(files(&["-rshell"], true), paths(&["/etc/profile", "/home/user/.rshell_profile", "/etc/rshellrc", "/home/user/.rshellrc"]));
+        assert_eq!(files(&["rshell"], true), paths(&["/etc/rshellrc", "/home/user/.rshellrc"]));
+        assert_eq!(files(&["rshell", "-l", "-c", "true"], false), paths(&["/etc/profile", "/home/user/.rshell_profile"]));
+        assert_eq!(files(&["rshell", "--norc", "--noprofile", "-l"], true), paths(&[]));
+        assert_eq!(files(&["rshell", "script.sh"], false), paths(&[]));
+    }
+
+    #[test]
+    fn xdg_rc_file_comes_first() {
+        let config = std::env::temp_dir().join(format!("rshell-xdg-{}", std::process::id()));
+        fs::create_dir_all(config.join("rshell")).unwrap();
+        fs::write(config.join("rshell/rshellrc"), "").unwrap();
+
+        let mut shell = Shell::new();
+        shell.set_var("HOME", "/home/user");
+        shell.set_var("XDG_CONFIG_HOME", &config.display().to_string());
+        assert_eq!(user_rc(&shell), config.join("rshell/rshellrc"));
+        shell.set_var("XDG_CONFIG_HOME", "");
+        assert_eq!(user_rc(&shell), PathBuf::from("/home/user/.rshellrc"));
+        let _ = fs::remove_dir_all(&config);
+    }
+
+    fn output(result: Result<Output, ShellError>) -> (String, String) {
+        match result {
+            Ok(Output::String(stdout)) => (stdout, String::new()),
+            Ok(Output::StdOutErr(stdout, stderr)) => (stdout, stderr),
+            Err(err) => (String::new(), get_error_message(&err).unwrap_or_default().to_string()),
+        }
+    }
+
+    #[test]
+    fn alias_and_unalias() {
+        let mut shell = Shell::new();
+        let mut run = |command: &str, arguments: &[&str]| output(handle_input(command, strings(arguments), &mut shell));
+
+        run("alias", &["ll=ls -l", "q=it's"]);
+        assert_eq!(run("alias", &[]).0, "alias ll='ls -l'\nalias q='it'\\''s'");
+        assert_eq!(run("alias", &["ll", "nope"]), ("alias ll='ls -l'".to_string(), "alias: nope: not found".to_string()));
+        assert_eq!(run("alias", &["a/b=x"]).1, "alias: `a/b=x': invalid alias name");
+        assert_eq!(run("type", &["ll"]).0, "ll is aliased to `ls -l'");
+
+        assert_eq!(run("unalias", &["ll", "nope"]).1, "unalias: nope: not found");
+        assert_eq!(run("alias", &[]).0, "alias q='it'\\''s'");
+        run("unalias", &["-a"]);
+        assert_eq!(run("alias", &[]).0, "");
+        assert_eq!(run("unalias", &[]).1, "unalias: usage: unalias [-a] name [name ...]");
+    }
+
+    fn history_query(arguments: &[&str]) -> Result<Output, ShellError> {
+        let mut shell = Shell::new();
+        let file = env::temp_dir().join(format!("rshell-histdb-test-{}.jsonl", std::process::id()));
+        shell.set_var("HISTDB", &file.to_string_lossy());
+        let arguments: Vec<String> = arguments.iter().map(|argument| argument.to_string()).collect();
+        query_history(&arguments, &shell)
+    }
+
+    #[test]
+    fn history_limit_must_be_a_count() {
+        assert!(matches!(history_query(&["--limit", "-1"]), Err(ShellError::InvalidArgument(_))));
+        assert!(matches!(history_query(&["--limit", "many"]), Err(ShellError::InvalidArgument(_))));
+        assert!(history_query(&["--limit", "5"]).is_ok());
+    }
+
+    #[test]
+    fn complete_and_compgen() {
+        let mut shell = Shell::new();
+        let mut run = |command: &str, arguments: &[&str]| output(handle_input(command, strings(arguments), &mut shell));
+
+        run("complete", &["-W", "add commit", "git", "g"]);
+        run("complete", &["-o", "nospace", "-F", "_make", "make"]);
+        assert_eq!(run("complete", &[]).0, "complete -W 'add commit' g\ncomplete -W 'add commit' git\ncomplete -o nospace -F _make make");
+        assert_eq!(run("complete", &["-p", "make", "nope"]), (
+            "complete -o nospace -F _make make".to_string(),
+            "complete: nope: no completion specification".to_string()
+        ));
+        assert_eq!(run("complete", &["-r", "g", "nope"]).1, "complete: nope: no completion specification");
+        assert_eq!(run("complete", &["-p"]).0, "complete -W 'add commit' git\ncomplete -o nospace -F _make make");
+        run("complete", &["-r"]);
+        assert_eq!(run("complete", &["-p"]).0, "");
+
+        assert_eq!(run("compgen", &["-W", "alpha beta alps", "--", "al"]).0, "alpha\nalps");
+        assert_eq!(run("compgen", &["-W", "alpha beta"]).0, "alpha\nbeta");
+        assert!(matches!(handle_input("compgen", strings(&["-W", "alpha", "x"]), &mut Shell::new()), Err(ShellError::ExitStatus(1))));
+    }
+
+    #[test]
+    fn hash_remembers_command_paths() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = env::temp_dir().join(format!("rshell-hash-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let tool = dir.join("tool");
+        fs::write(&tool, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(&tool, fs::Permissions::from_mode(0o755)).unwrap();
+        let tool = tool.display().to_string();
+
+        let mut shell = Shell::new();
+        shell.set_var("PATH", &dir.display().to_string());
+        let mut run = |command: &str, arguments: &[&str]| output(handle_input(command, strings(arguments), &mut shell));
+
+        assert_eq!(run("hash", &[]).0, "hash: hash table empty");
+        assert_eq!(run("hash", &["tool", "nope", "echo"]).1, "hash: nope: not found");
+        assert_eq!(run("hash", &[]).0, format!("hits\tcommand\n   0\t{}", tool));
+        assert_eq!(run("hash", &["-l"]).0, format!("builtin hash -p {} tool", tool));
+        assert_eq!(run("hash", &["-t", "tool"]).0, tool);
+        assert_eq!(run("type", &["tool"]).0, format!("tool is hashed ({})", tool));
+
+        run("hash", &["-p", "/bin/sh", "sh2"]);
+        assert_eq!(run("hash", &["-t", "sh2"]).0, "/bin/sh");
+        run("hash", &["-d", "sh2"]);
+        assert_eq!(run("hash", &["-t", "sh2"]).1, "hash: sh2: not found");
+        run("hash", &["-r"]);
+        assert_eq!(run("hash", &[]).0, "hash: hash table empty");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn stale_hashed_paths_are_looked_up_again() {
+        let mut shell = Shell::new();
+        shell.set_var("PATH", "/usr/bin:/bin");
+        shell.hash_table().insert("sh".to_string(), ("/nonexistent/sh".to_string(), 3));
+        let found = shell.command_path("sh").unwrap();
+        assert_ne!(found, "/nonexistent/sh");
+        assert_eq!(shell.hash_table().get("sh"), Some(&(found, 0)));
+
+        // A new PATH empties the table.
+        shell.set_var("PATH", "/bin");
+        assert!(shell.hash_table().is_empty());
+    }
+}
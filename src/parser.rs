@@ -0,0 +1,783 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::condition::{BINARY_OPERATORS, UNARY_OPERATORS};
+use crate::lexer::{is_blank, is_metachar, is_name, unquote_delimiter, Lexer, ParseError, Token};
+use crate::redirection::{RedirType, Redirection};
+
+pub type List = Vec<AndOr>;
+
+#[derive(Debug, Clone)]
+pub struct AndOr {
+    pub first: Pipeline,
+    pub rest: Vec<(Connector, Pipeline)>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Connector {
+    And,
+    Or
+}
+
+#[derive(Debug, Clone)]
+pub struct Pipeline {
+    pub negated: bool,
+    pub commands: Vec<Command>
+}
+
+#[derive(Debug, Clone)]
+pub enum Command {
+    Simple(SimpleCommand),
+    Compound(Compound, Vec<Redirection>),
+    Function(String, Rc<Function>)
+}
+
+#[derive(Debug)]
+pub struct Function {
+    pub body: Command,
+    pub source: String
+}
+
+#[derive(Debug, Clone)]
+pub struct SimpleCommand {
+    pub assignments: Vec<(String, String)>,
+    pub words: Vec<String>,
+    pub redirections: Vec<Redirection>
+}
+
+#[derive(Debug, Clone)]
+pub enum Compound {
+    If { branches: Vec<(List, List)>, otherwise: Option<List> },
+    Loop { condition: List, body: List, until: bool },
+    For { variable: String, words: Option<Vec<String>>, body: List },
+    ArithFor { init: String, condition: String, step: String, body: List },
+    Case { word: String, arms: Vec<CaseArm> },
+    Arithmetic(String),
+    Group(List),
+    Subshell(List),
+    Conditional(CondExpr)
+}
+
+#[derive(Debug, Clone)]
+pub enum CondExpr {
+    And(Box<CondExpr>, Box<CondExpr>),
+    Or(Box<CondExpr>, Box<CondExpr>),
+    Not(Box<CondExpr>),
+    Unary(String, String),
+    Binary(String, String, String),
+    Word(String)
+}
+
+#[derive(Debug, Clone)]
+pub struct CaseArm {
+    pub patterns: Vec<String>,
+    pub body: List,
+    pub terminator: CaseTerminator
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CaseTerminator {
+    Break,
+    FallThrough,
+    Continue
+}
+
+const RESERVED: [&str; 17] = ["if", "then", "elif", "else", "fi", "while", "until", "for", "do", "done", "case", "esac", "{", "}", "function", "[[", "]]"];
+
+pub fn is_reserved(word: &str) -> bool {
+    RESERVED.contains(&word)
+}
+
+/// Parses `input`, expanding the `aliases` found in command position.
+pub fn parse(input: &str, aliases: &HashMap<String, String>) -> Result<List, ParseError> {
+    let mut parser = Parser {
+        lexer: Lexer::new(input),
+        aliases,
+        expansions: vec![],
+        alias_next: None
+    };
+    let list = parser.parse_list(&[])?;
+
+    match parser.lexer.next_token()? {
+        Token::Eof => Ok(list),
+        token => Err(unexpected(&token)),
+    }
+}
+
+/// Whether `input` is an unfinished command that more lines could complete:
+/// an open quote, a trailing operator or backslash, a compound command
+/// without its closing keyword or a here-document without its delimiter.
+pub fn is_incomplete(input: &str, aliases: &HashMap<String, String>) -> bool {
+    matches!(parse(input, aliases), Err(ParseError::Incomplete(_)))
+}
+
+fn is_function_name(name: &str) -> bool {
+    !name.is_empty()
+        && !RESERVED.contains(&name)
+        && name.chars().all(|c| !is_metachar(c) && !matches!(c, '\'' | '"' | '\\' | '$' | '`' | '=' | '{' | '}'))
+}
+
+fn unexpected(token: &Token) -> ParseError {
+    match token {
+        Token::Eof => ParseError::Incomplete("unexpected end of file".to_string()),
+        token => ParseError::Syntax(format!("near unexpected token `{}'", token)),
+    }
+}
+
+/// Whether `name` can be defined as an alias.
+pub fn is_alias_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.chars().all(|c| !is_metachar(c) && !matches!(c, '\'' | '"' | '\\' | '$' | '`' | '=' | '/'))
+}
+
+struct Parser<'a> {
+    lexer: Lexer,
+    aliases: &'a HashMap<String, String>,
+    /// Aliases being expanded, with the offset where their text ends. An
+    /// alias is not expanded again within its own text.
+    expansions: Vec<(String, usize)>,
+    /// Set by an alias ending with a blank: the word starting at this
+    /// offset is checked for aliases too.
+    alias_next: Option<usize>
+}
+
+impl Parser<'_> {
+    fn peek_word(&mut self) -> Result<Option<String>, ParseError> {
+        match self.lexer.peek()? {
+            Token::Word(word) => Ok(Some(word)),
+            _ => Ok(None),
+        }
+    }
+
+    fn expect_word(&mut self, expected: &str) -> Result<(), ParseError> {
+        match self.lexer.next_token()? {
+            Token::Word(word) if word == expected => Ok(()),
+            token => Err(unexpected(&token)),
+        }
+    }
+
+    /// Expands the peeked word while it names an alias, then leaves the
+    /// first word of the replacement text peeked.
+    fn expand_aliases(&mut self) -> Result<(), ParseError> {
+        while let Some(word) = self.peek_word()? {
+            let start = self.lexer.offset();
+            let active = self.expansions.iter().any(|(name, end)| *name == word && start < *end);
+            let value = match self.aliases.get(&word) {
+                Some(value) if !active && is_alias_name(&word) => value.clone(),
+                _ => break,
+            };
+
+            let old_end = start + word.chars().count();
+            let end = self.lexer.replace_peeked(&value);
+            let shift = |offset: &mut usize| if *offset >= old_end { *offset = *offset + end - old_end };
+            self.expansions.iter_mut().for_each(|(_, outer_end)| shift(outer_end));
+            if let Some(next) = self.alias_next.as_mut() {
+                shift(next);
+            }
+            self.expansions.retain(|(_, end)| *end > start);
+            self.expansions.push((word, end));
+
+            if value.ends_with(is_blank) {
+                self.alias_next = Some(end);
+            }
+        }
+        Ok(())
+    }
+
+    fn skip_newlines(&mut self) -> Result<(), ParseError> {
+        while self.lexer.peek()? == Token::Newline {
+            self.lexer.next_token()?;
+        }
+        Ok(())
+    }
+
+    /// Parses commands separated by `;` or newlines until one of the
+    /// `terminators` keywords shows up in command position.
+    fn parse_list(&mut self, terminators: &[&str]) -> Result<List, ParseError> {
+        let mut list = vec![];
+
+        loop {
+            self.skip_newlines()?;
+
+            match self.lexer.peek()? {
+                Token::Eof => break,
+                Token::Operator(op) if op == ")" || op.starts_with(";;") || op == ";&" => break,
+                Token::Word(word) if terminators.contains(&word.as_str()) => break,
+                _ => {}
+            }
+
+            list.push(self.parse_and_or()?);
+
+            match self.lexer.peek()? {
+                Token::Newline => {},
+                Token::Operator(op) if op == ";" => {},
+                Token::Operator(op) if op == "&" => {
+                    return Err(ParseError::Syntax("background execution is not supported".to_string()));
+                },
+                _ => break,
+            }
+            self.lexer.next_token()?;
+        }
+
+        Ok(list)
+    }
+
+    fn parse_body(&mut self, terminators: &[&str]) -> Result<List, ParseError> {
+        let list = self.parse_list(terminators)?;
+
+        if list.is_empty() {
+            return Err(unexpected(&self.lexer.peek()?));
+        }
+        Ok(list)
+    }
+
+    fn parse_and_or(&mut self) -> Result<AndOr, ParseError> {
+        let first = self.parse_pipeline()?;
+        let mut rest = vec![];
+
+        loop {
+            let connector = match self.lexer.peek()? {
+                Token::Operator(op) if op == "&&" => Connector::And,
+                Token::Operator(op) if op == "||" => Connector::Or,
+                _ => break,
+            };
+            self.lexer.next_token()?;
+            self.skip_newlines()?;
+            rest.push((connector, self.parse_pipeline()?));
+        }
+
+        Ok(AndOr { first, rest })
+    }
+
+    fn parse_pipeline(&mut self) -> Result<Pipeline, ParseError> {
+        let negated = self.peek_word()?.as_deref() == Some("!");
+        if negated {
+            self.lexer.next_token()?;
+        }
+
+        let mut commands = vec![self.parse_command()?];
+        while self.lexer.peek()? == Token::Operator("|".to_string()) {
+            self.lexer.next_token()?;
+            self.skip_newlines()?;
+            commands.push(self.parse_command()?);
+        }
+
+        Ok(Pipeline { negated, commands })
+    }
+
+    fn parse_command(&mut self) -> Result<Command, ParseError> {
+        self.expand_aliases()?;
+
+        if let Some(word) = self.peek_word()? {
+            let is_definition = word == "function"
+                || (is_function_name(&word) && self.lexer.peek_second()? == Token::Operator("(".to_string()));
+            if is_definition {
+                return self.parse_function();
+            }
+        }
+
+        let compound = match self.lexer.peek()? {
+            Token::Operator(op) if op == "((" => {
+                self.lexer.next_token()?;
+                Compound::Arithmetic(self.lexer.read_arithmetic()?)
+            },
+            Token::Operator(op) if op == "(" => {
+                self.lexer.next_token()?;
+                let body = self.parse_body(&[])?;
+                match self.lexer.next_token()? {
+                    Token::Operator(op) if op == ")" => Compound::Subshell(body),
+                    token => return Err(unexpected(&token)),
+                }
+            },
+            Token::Word(word) => match word.as_str() {
+                "if" => self.parse_if()?,
+                "while" | "until" => self.parse_loop()?,
+                "for" => self.parse_for()?,
+                "case" => self.parse_case()?,
+                "{" => self.parse_group()?,
+                "[[" => self.parse_conditional()?,
+                w if RESERVED.contains(&w) => return Err(unexpected(&self.lexer.peek()?)),
+                _ => return self.parse_simple_command().map(Command::Simple),
+            },
+            _ => return self.parse_simple_command().map(Command::Simple),
+        };
+
+        let mut redirections = vec![];
+        while let Token::Redirect(op) = self.lexer.peek()? {
+            self.lexer.next_token()?;
+            redirections.push(self.parse_redirection(&op)?);
+        }
+
+        Ok(Command::Compound(compound, redirections))
+    }
+
+    fn parse_function(&mut self) -> Result<Command, ParseError> {
+        let start = self.lexer.offset();
+
+        if self.peek_word()?.as_deref() == Some("function") {
+            self.lexer.next_token()?;
+        }
+
+        let name = match self.lexer.next_token()? {
+            Token::Word(name) if is_function_name(&name) => name,
+            token => return Err(unexpected(&token)),
+        };
+
+        if self.lexer.peek()? == Token::Operator("(".to_string()) {
+            self.lexer.next_token()?;
+            match self.lexer.next_token()? {
+                Token::Operator(op) if op == ")" => {},
+                token => return Err(unexpected(&token)),
+            }
+        }
+        self.skip_newlines()?;
+
+        let body = self.parse_command()?;
+        if !matches!(body, Command::Compound(..)) {
+            return Err(ParseError::Syntax(format!("`{}': function body must be a compound command", name)));
+        }
+
+        let source = self.lexer.slice(start, self.lexer.offset());
+        Ok(Command::Function(name, Rc::new(Function { body, source })))
+    }
+
+    fn parse_redirection(&mut self, op: &str) -> Result<Redirection, ParseError> {
+        if matches!(op.trim_start_matches('0'), "<<" | "<<-") {
+            let delimiter = match self.lexer.next_token()? {
+                Token::Word(delimiter) => delimiter,
+                token => return Err(unexpected(&token)),
+            };
+            let body = self.lexer.read_here_document(&delimiter, op.ends_with('-'))?;
+            let (_, quoted) = unquote_delimiter(&delimiter);
+            return Ok(Redirection { r_type: RedirType::HereDoc { expand: !quoted }, path: body });
+        }
+
+        match self.lexer.next_token()? {
+            Token::Word(target) => Redirection::from_operator(op, target)
+                .ok_or_else(|| ParseError::Syntax(format!("unsupported redirection `{}'", op))),
+            token => Err(unexpected(&token)),
+        }
+    }
+
+    fn parse_simple_command(&mut self) -> Result<SimpleCommand, ParseError> {
+        let mut command = SimpleCommand {
+            assignments: vec![],
+            words: vec![],
+            redirections: vec![]
+        };
+
+        loop {
+            self.lexer.peek()?;
+            if self.alias_next.is_some_and(|end| self.lexer.offset() >= end) {
+                self.alias_next = None;
+                self.expand_aliases()?;
+            }
+
+            match self.lexer.peek()? {
+                Token::Word(word) => {
+                    self.lexer.next_token()?;
+                    match word.split_once('=') {
+                        Some((name, value)) if command.words.is_empty() && is_name(name) => {
+                            command.assignments.push((name.to_string(), value.to_string()));
+                        },
+                        _ => command.words.push(word),
+                    }
+                },
+                Token::Redirect(op) => {
+                    self.lexer.next_token()?;
+                    let redirection = self.parse_redirection(&op)?;
+                    command.redirections.push(redirection);
+                },
+                _ => break,
+            }
+        }
+
+        if command.assignments.is_empty() && command.words.is_empty() && command.redirections.is_empty() {
+            return Err(unexpected(&self.lexer.peek()?));
+        }
+
+        Ok(command)
+    }
+
+    fn parse_if(&mut self) -> Result<Compound, ParseError> {
+        let mut branches = vec![];
+        let mut otherwise = None;
+
+        self.expect_word("if")?;
+        loop {
+            let condition = self.parse_body(&["then"])?;
+            self.expect_word("then")?;
+            let body = self.parse_body(&["elif", "else", "fi"])?;
+            branches.push((condition, body));
+
+            match self.lexer.next_token()? {
+                Token::Word(word) if word == "elif" => continue,
+                Token::Word(word) if word == "else" => {
+                    otherwise = Some(self.parse_body(&["fi"])?);
+                    self.expect_word("fi")?;
+                    break;
+                },
+                Token::Word(word) if word == "fi" => break,
+                token => return Err(unexpected(&token)),
+            }
+        }
+
+        Ok(Compound::If { branches, otherwise })
+    }
+
+    fn parse_group(&mut self) -> Result<Compound, ParseError> {
+        self.expect_word("{")?;
+        let body = self.parse_body(&["}"])?;
+        self.expect_word("}")?;
+        Ok(Compound::Group(body))
+    }
+
+    fn parse_conditional(&mut self) -> Result<Compound, ParseError> {
+        self.expect_word("[[")?;
+        let expression = self.parse_cond_or()?;
+        self.skip_newlines()?;
+        self.expect_word("]]")?;
+        Ok(Compound::Conditional(expression))
+    }
+
+    fn parse_cond_or(&mut self) -> Result<CondExpr, ParseError> {
+        let mut expression = self.parse_cond_and()?;
+        while self.lexer.peek()? == Token::Operator("||".to_string()) {
+            self.lexer.next_token()?;
+            expression = CondExpr::Or(Box::new(expression), Box::new(self.parse_cond_and()?));
+        }
+        Ok(expression)
+    }
+
+    fn parse_cond_and(&mut self) -> Result<CondExpr, ParseError> {
+        let mut expression = self.parse_cond_not()?;
+        while self.lexer.peek()? == Token::Operator("&&".to_string()) {
+            self.lexer.next_token()?;
+            expression = CondExpr::And(Box::new(expression), Box::new(self.parse_cond_not()?));
+        }
+        Ok(expression)
+    }
+
+    fn parse_cond_not(&mut self) -> Result<CondExpr, ParseError> {
+        self.skip_newlines()?;
+        if self.peek_word()?.as_deref() == Some("!") {
+            self.lexer.next_token()?;
+            return Ok(CondExpr::Not(Box::new(self.parse_cond_not()?)));
+        }
+        self.parse_cond_primary()
+    }
+
+    fn parse_cond_primary(&mut self) -> Result<CondExpr, ParseError> {
+        let word = match self.lexer.next_token()? {
+            Token::Operator(op) if op == "(" => {
+                let expression = self.parse_cond_or()?;
+                self.skip_newlines()?;
+                return match self.lexer.next_token()? {
+                    Token::Operator(op) if op == ")" => Ok(expression),
+                    token => Err(unexpected(&token)),
+                };
+            },
+            Token::Word(word) if word != "]]" => word,
+            token => return Err(unexpected(&token)),
+        };
+
+        if UNARY_OPERATORS.contains(&word.as_str()) {
+            if let Some(operand) = self.peek_word()?.filter(|operand| operand != "]]") {
+                self.lexer.next_token()?;
+                return Ok(CondExpr::Unary(word, operand));
+            }
+        }
+
+        let operator = match self.lexer.peek()? {
+            Token::Word(op) if BINARY_OPERATORS.contains(&op.as_str()) => op,
+            Token::Redirect(op) if op == "<" || op == ">" => op,
+            _ => return Ok(CondExpr::Word(word)),
+        };
+        self.lexer.next_token()?;
+
+        let right = if operator == "=~" {
+            self.lexer.read_regex()?
+        } else {
+            match self.lexer.next_token()? {
+                Token::Word(right) => right,
+                token => return Err(unexpected(&token)),
+            }
+        };
+
+        Ok(CondExpr::Binary(operator, word, right))
+    }
+
+    fn parse_do_group(&mut self) -> Result<List, ParseError> {
+        self.expect_word("do")?;
+        let body = self.parse_body(&["done"])?;
+        self.expect_word("done")?;
+        Ok(body)
+    }
+
+    fn parse_loop(&mut self) -> Result<Compound, ParseError> {
+        let until = self.lexer.next_token()? == Token::Word("until".to_string());
+        let condition = self.parse_body(&["do"])?;
+        let body = self.parse_do_group()?;
+
+        Ok(Compound::Loop { condition, body, until })
+    }
+
+    fn parse_for(&mut self) -> Result<Compound, ParseError> {
+        self.expect_word("for")?;
+
+        if self.lexer.peek()? == Token::Operator("((".to_string()) {
+            self.lexer.next_token()?;
+            let expression = self.lexer.read_arithmetic()?;
+            let parts: Vec<&str> = expression.split(';').collect();
+            if parts.len() != 3 {
+                return Err(ParseError::Syntax(format!("invalid arithmetic for expression `(({}))'", expression)));
+            }
+
+            if self.lexer.peek()? == Token::Operator(";".to_string()) {
+                self.lexer.next_token()?;
+            }
+            self.skip_newlines()?;
+
+            return Ok(Compound::ArithFor {
+                init: parts[0].trim().to_string(),
+                condition: parts[1].trim().to_string(),
+                step: parts[2].trim().to_string(),
+                body: self.parse_do_group()?
+            });
+        }
+
+        let variable = match self.lexer.next_token()? {
+            Token::Word(word) if is_name(&word) => word,
+            token => return Err(unexpected(&token)),
+        };
+
+        // Without `in`, the loop iterates over the positional parameters.
+        let mut words = None;
+        if self.lexer.peek()? == Token::Operator(";".to_string()) {
+            self.lexer.next_token()?;
+        }
+        self.skip_newlines()?;
+
+        if self.peek_word()?.as_deref() == Some("in") {
+            self.lexer.next_token()?;
+            let mut list = vec![];
+            loop {
+                match self.lexer.next_token()? {
+                    Token::Word(word) => list.push(word),
+                    Token::Newline => break,
+                    Token::Operator(op) if op == ";" => break,
+                    token => return Err(unexpected(&token)),
+                }
+            }
+            words = Some(list);
+            self.skip_newlines()?;
+        }
+
+        Ok(Compound::For { variable, words, body: self.parse_do_group()? })
+    }
+
+    fn parse_case(&mut self) -> Result<Compound, ParseError> {
+        self.expect_word("case")?;
+
+        let word = match self.lexer.next_token()? {
+            Token::Word(word) => word,
+            token => return Err(unexpected(&token)),
+        };
+
+        self.skip_newlines()?;
+        self.expect_word("in")?;
+
+        let mut arms = vec![];
+        loop {
+            self.skip_newlines()?;
+
+            if self.peek_word()?.as_deref() == Some("esac") {
+                self.lexer.next_token()?;
+                break;
+            }
+
+            if self.lexer.peek()? == Token::Operator("(".to_string()) {
+                self.lexer.next_token()?;
+            }
+
+            let mut patterns = vec![];
+            loop {
+                match self.lexer.next_token()? {
+                    Token::Word(pattern) => patterns.push(pattern),
+                    token => return Err(unexpected(&token)),
+                }
+
+                match self.lexer.next_token()? {
+                    Token::Operator(op) if op == "|" => continue,
+                    Token::Operator(op) if op == ")" => break,
+                    token => return Err(unexpected(&token)),
+                }
+            }
+
+            let body = self.parse_list(&["esac"])?;
+
+            let terminator = match self.lexer.peek()? {
+                Token::Operator(op) if op == ";;" => CaseTerminator::Break,
+                Token::Operator(op) if op == ";&" => CaseTerminator::FallThrough,
+                Token::Operator(op) if op == ";;&" => CaseTerminator::Continue,
+                Token::Word(word) if word == "esac" => {
+                    arms.push(CaseArm { patterns, body, terminator: CaseTerminator::Break });
+                    continue;
+                },
+                token => return Err(unexpected(&token)),
+            };
+            self.lexer.next_token()?;
+
+            arms.push(CaseArm { patterns, body, terminator });
+        }
+
+        Ok(Compound::Case { word, arms })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aliases(definitions: &[(&str, &str)]) -> HashMap<String, String> {
+        definitions.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    fn words(input: &str, aliases: &HashMap<String, String>) -> Vec<String> {
+        let list = parse(input, aliases).unwrap();
+        match &list[0].first.commands[0] {
+            Command::Simple(command) => command.words.clone(),
+            command => panic!("not a simple command: {:?}", command),
+        }
+    }
+
+    #[test]
+    fn aliases_expand_in_command_position() {
+        let defined = aliases(&[("ll", "ls -l"), ("g", "git"), ("gs", "g status")]);
+        assert_eq!(words("ll /tmp", &defined), ["ls", "-l", "/tmp"]);
+        assert_eq!(words("gs -s", &defined), ["git", "status", "-s"]);
+        assert_eq!(words("echo ll", &defined), ["echo", "ll"]);
+        let list = parse("true && ll; g | ll", &defined).unwrap();
+        assert_eq!(list.len(), 2);
+    }
+
+    #[test]
+    fn aliases_ending_with_a_blank_expand_the_next_word() {
+        let defined = aliases(&[("s", "sudo "), ("ll", "ls -l"), ("t", "time")]);
+        assert_eq!(words("s ll /tmp", &defined), ["sudo", "ls", "-l", "/tmp"]);
+        assert_eq!(words("t ll", &defined), ["time", "ll"]);
+    }
+
+    #[test]
+    fn aliases_do_not_expand_recursively() {
+        let defined = aliases(&[("ls", "ls --color"), ("a", "b"), ("b", "a")]);
+        assert_eq!(words("ls x", &defined), ["ls", "--color", "x"]);
+        assert_eq!(words("a", &defined), ["a"]);
+    }
+
+    #[test]
+    fn quoted_words_are_not_aliases() {
+        let defined = aliases(&[("ll", "ls -l")]);
+        assert_eq!(words("'ll'", &defined), ["'ll'"]);
+        assert_eq!(words(r"\ll", &defined), [r"\ll"]);
+    }
+
+    #[test]
+    fn and_or_lists() {
+        let list = parse("a && b || c; d", &HashMap::new()).unwrap();
+        assert_eq!(list.len(), 2);
+        let connectors: Vec<&Connector> = list[0].rest.iter().map(|(connector, _)| connector).collect();
+        assert_eq!(connectors, [&Connector::And, &Connector::Or]);
+        assert!(parse("! a", &HashMap::new()).unwrap()[0].first.negated);
+    }
+
+    #[test]
+    fn compound_commands() {
+        let compound = |input: &str| match parse(input, &HashMap::new()).unwrap().remove(0).first.commands.remove(0) {
+            Command::Compound(compound, _) => compound,
+            command => panic!("not a compound command: {:?}", command),
+        };
+
+        assert!(matches!(compound("if a; then b; elif c; then d; else e; fi"), Compound::If { branches, otherwise: Some(_) } if branches.len() == 2));
+        assert!(matches!(compound("until a; do b; done"), Compound::Loop { until: true, .. }));
+        assert!(matches!(compound("for i in 1 2; do :; done"), Compound::For { variable, words, .. } if variable == "i" && words == Some(vec!["1".into(), "2".into()])));
+        assert!(matches!(compound("for arg; do :; done"), Compound::For { words: None, .. }));
+        assert!(matches!(compound("for ((i = 0; i < 3; i++)); do :; done"), Compound::ArithFor { condition, .. } if condition.trim() == "i < 3"));
+        assert!(matches!(compound("(( x += 1 ))"), Compound::Arithmetic(expression) if expression.trim() == "x += 1"));
+
+        let Compound::Case { word, arms } = compound("case $x in a|b) one;; *) two;& c) three;;& esac") else { panic!() };
+        assert_eq!(word, "$x");
+        assert_eq!(arms[0].patterns, ["a", "b"]);
+        let terminators: Vec<&CaseTerminator> = arms.iter().map(|arm| &arm.terminator).collect();
+        assert_eq!(terminators, [&CaseTerminator::Break, &CaseTerminator::FallThrough, &CaseTerminator::Continue]);
+    }
+
+    #[test]
+    fn function_definitions() {
+        for input in ["f() { a; }", "function f { a; }", "function f() { a; }", "f ()\n{\n a\n}"] {
+            let list = parse(input, &HashMap::new()).unwrap();
+            assert!(matches!(&list[0].first.commands[0], Command::Function(name, _) if name == "f"), "{:?}", input);
+        }
+        assert!(matches!(parse("f() a", &HashMap::new()), Err(ParseError::Syntax(_))));
+    }
+
+    #[test]
+    fn complete_and_invalid_commands() {
+        for input in ["echo hi", "if true; then :; fi", "for i in 1; do :; done", "case x in x) ;; esac", "while false\ndo :\ndone", "(a; b) >out", "{ a; } | b"] {
+            assert!(parse(input, &HashMap::new()).is_ok(), "{:?}", input);
+        }
+        for input in ["fi", ";;", "a && && b", "done", "if true; then fi", "( )", "a | | b"] {
+            assert!(matches!(parse(input, &HashMap::new()), Err(ParseError::Syntax(_))), "{:?}", input);
+        }
+        for input in ["a &&", "a |", "if true; then", "while true; do", "case x in", "( a", "{ a;"] {
+            assert!(matches!(parse(input, &HashMap::new()), Err(ParseError::Incomplete(_))), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn unfinished_commands_are_incomplete() {
+        let none = HashMap::new();
+        for input in [
+            "echo 'abc", "echo \"abc", "echo `ls", "echo $(ls", "a &&", "a ||", "a |", "echo \\", "if true; then",
+            "if true; then :; else", "while true; do", "until false; do :", "for i in 1 2; do", "case x in",
+            "case x in x) :;;", "{ echo", "( echo", "f() {", "cat <<EOF\nhi", "cat <<-EOF\n\thi\n  EOF", "[[ -n x",
+        ] {
+            assert!(is_incomplete(input, &none), "{:?}", input);
+        }
+        for input in ["echo hi", "echo 'a\nb'", "a &&\nb", "if true\nthen :\nfi", "fi", "a && && b", "( )"] {
+            assert!(!is_incomplete(input, &none), "{:?}", input);
+        }
+    }
+
+    fn here_documents(input: &str) -> Vec<Redirection> {
+        match parse(input, &HashMap::new()).unwrap().remove(0).first.commands.remove(0) {
+            Command::Simple(command) => command.redirections,
+            command => panic!("not a simple command: {:?}", command),
+        }
+    }
+
+    #[test]
+    fn here_document_bodies() {
+        let [here] = &here_documents("cat <<EOF\nhello $x\n  EOF\nEOF\n")[..] else { panic!() };
+        assert!(matches!(here.r_type, RedirType::HereDoc { expand: true }));
+        assert_eq!(here.path, "hello $x\n  EOF\n");
+
+        let [here] = &here_documents("cat <<-END\n\t\tindented\n\tEND")[..] else { panic!() };
+        assert_eq!(here.path, "indented\n");
+
+        let [first, second] = &here_documents("cat <<A <<B\none\nA\ntwo\nB")[..] else { panic!() };
+        assert_eq!((first.path.as_str(), second.path.as_str()), ("one\n", "two\n"));
+
+        let list = parse("cat <<EOF; echo after\nbody\nEOF\necho last", &HashMap::new()).unwrap();
+        assert_eq!(list.len(), 3);
+    }
+
+    #[test]
+    fn quoted_delimiters_turn_off_expansion() {
+        for input in ["cat <<'EOF'\n$x\nEOF", "cat <<\"EOF\"\n$x\nEOF", "cat <<\\EOF\n$x\nEOF", "cat <<E'O'F\n$x\nEOF"] {
+            let [here] = &here_documents(input)[..] else { panic!() };
+            assert!(matches!(here.r_type, RedirType::HereDoc { expand: false }), "{:?}", input);
+            assert_eq!(here.path, "$x\n");
+        }
+    }
+}
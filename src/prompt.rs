@@ -0,0 +1,256 @@
+use std::env::current_dir;
+use std::ffi::{CStr, CString};
+
+use crate::executor::{execute_list, report};
+use crate::expansion::expand_text;
+use crate::git;
+use crate::histdb::{hostname, local_time, now};
+use crate::parser;
+use crate::shell::Shell;
+
+/// Delimit the non-printing parts of a prompt, written `\[` and `\]`.
+const START_HIDDEN: char = '\x01';
+const END_HIDDEN: char = '\x02';
+
+/// A prompt as displayed, and the same text without its non-printing
+/// parts, from which the line editor computes its width.
+#[derive(Clone, Default)]
+pub struct Prompt {
+    pub display: String,
+    pub visible: String
+}
+
+/// Expands a prompt such as `PS1`: backslash escapes are replaced first,
+/// then parameters, command substitutions and arithmetic are expanded.
+pub fn expand_prompt(text: &str, shell: &mut Shell) -> Prompt {
+    let decoded = decode_escapes(text, shell);
+    let expanded = match expand_text(&decoded, shell) {
+        Ok(expanded) => expanded,
+        Err(err) => {
+            report(&err);
+            decoded
+        },
+    };
+
+    let mut prompt = Prompt::default();
+    let mut hidden = false;
+    for c in expanded.chars() {
+        match c {
+            START_HIDDEN => hidden = true,
+            END_HIDDEN => hidden = false,
+            c => {
+                prompt.display.push(c);
+                if !hidden {
+                    prompt.visible.push(c);
+                }
+            },
+        }
+    }
+    prompt
+}
+
+/// Runs `PROMPT_COMMAND` before the primary prompt is shown. `$?` keeps the
+/// status of the last command typed.
+pub fn run_prompt_command(shell: &mut Shell) {
+    let Some(command) = shell.get_var("PROMPT_COMMAND").filter(|command| !command.trim().is_empty()) else {
+        return;
+    };
+
+    let status = shell.last_status;
+    match parser::parse(&command, &shell.aliases) {
+        Ok(list) => {
+            execute_list(&list, shell);
+        },
+        Err(err) => eprintln!("rshell: PROMPT_COMMAND: {}", err),
+    }
+    shell.last_status = status;
+}
+
+/// Replaces the escapes of a prompt. Their values are protected from the
+/// expansion that follows.
+fn decode_escapes(text: &str, shell: &Shell) -> String {
+    let mut result = String::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+
+        let Some(escape) = chars.next() else {
+            result.push('\\');
+            break;
+        };
+
+        let value = match escape {
+            'a' => "\x07".to_string(),
+            'e' => "\x1b".to_string(),
+            'n' => "\n".to_string(),
+            'r' => "\r".to_string(),
+            '[' => START_HIDDEN.to_string(),
+            ']' => END_HIDDEN.to_string(),
+            'u' => user_name(shell),
+            'h' => hostname().split('.').next().unwrap_or_default().to_string(),
+            'H' => hostname(),
+            'w' => working_directory(&cwd(), &shell.get_var("HOME").unwrap_or_default(), false),
+            'W' => working_directory(&cwd(), &shell.get_var("HOME").unwrap_or_default(), true),
+            'g' => current_dir().map(|cwd| git::prompt_segment(&cwd)).unwrap_or_default(),
+            '$' => if unsafe { libc::geteuid() } == 0 { "#" } else { "$" }.to_string(),
+            '?' => shell.last_status.to_string(),
+            'l' => terminal_name(),
+            's' => shell.name.rsplit('/').next().unwrap_or_default().to_string(),
+            'v' => env!("CARGO_PKG_VERSION").rsplitn(2, '.').last().unwrap_or_default().to_string(),
+            'V' => env!("CARGO_PKG_VERSION").to_string(),
+            '!' => (shell.history.entries.len() + 1).to_string(),
+            'd' => format_time("%a %b %d"),
+            't' => format_time("%H:%M:%S"),
+            'T' => format_time("%I:%M:%S"),
+            '@' => format_time("%I:%M %p"),
+            'A' => format_time("%H:%M"),
+            'D' if chars.peek() == Some(&'{') => {
+                chars.next();
+                let format: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                format_time(if format.is_empty() { "%X" } else { &format })
+            },
+            '0'..='7' => {
+                let mut code = escape.to_digit(8).unwrap_or_default();
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(8)) {
+                        Some(digit) => {
+                            code = code * 8 + digit;
+                            chars.next();
+                        },
+                        None => break,
+                    }
+                }
+                char::from_u32(code).map(String::from).unwrap_or_default()
+            },
+            '\\' => "\\".to_string(),
+            other => {
+                result.push('\\');
+                result.push(other);
+                continue;
+            },
+        };
+
+        for c in value.chars() {
+            if matches!(c, '$' | '`' | '\\') {
+                result.push('\\');
+            }
+            result.push(c);
+        }
+    }
+
+    result
+}
+
+fn user_name(shell: &Shell) -> String {
+    if let Some(user) = shell.get_var("USER").filter(|user| !user.is_empty()) {
+        return user;
+    }
+
+    let passwd = unsafe { libc::getpwuid(libc::geteuid()) };
+    if passwd.is_null() {
+        return String::new();
+    }
+    unsafe { CStr::from_ptr((*passwd).pw_name) }.to_string_lossy().to_string()
+}
+
+fn cwd() -> String {
+    current_dir().map(|dir| dir.to_string_lossy().to_string()).unwrap_or_default()
+}
+
+/// `cwd` with the `home` directory shortened to `~`, or only its last
+/// component with `base`.
+fn working_directory(cwd: &str, home: &str, base: bool) -> String {
+    if !home.is_empty() && cwd == home.trim_end_matches('/') {
+        return "~".to_string();
+    }
+    if base {
+        return match cwd.rsplit('/').next() {
+            Some(name) if !name.is_empty() => name.to_string(),
+            _ => cwd.to_string(),
+        };
+    }
+
+    match cwd.strip_prefix(home.trim_end_matches('/')) {
+        Some(rest) if !home.is_empty() && rest.starts_with('/') => format!("~{}", rest),
+        _ => cwd.to_string(),
+    }
+}
+
+fn terminal_name() -> String {
+    let name = unsafe { libc::ttyname(0) };
+    if name.is_null() {
+        return "tty".to_string();
+    }
+    let name = unsafe { CStr::from_ptr(name) }.to_string_lossy().to_string();
+    name.rsplit('/').next().unwrap_or_default().to_string()
+}
+
+fn format_time(format: &str) -> String {
+    let Ok(format) = CString::new(format) else {
+        return String::new();
+    };
+
+    let tm = local_time(now());
+    let mut buffer = [0u8; 256];
+    let length = unsafe { libc::strftime(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len(), format.as_ptr(), &tm) };
+    String::from_utf8_lossy(&buffer[..length]).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes() {
+        let mut shell = Shell::new();
+        shell.set_var("USER", "alice");
+        assert_eq!(expand_prompt("\\u@\\h", &mut shell).display, format!("alice@{}", hostname().split('.').next().unwrap()));
+        let root = unsafe { libc::geteuid() } == 0;
+        assert_eq!(expand_prompt("\\$ ", &mut shell).display, if root { "# " } else { "$ " });
+        assert_eq!(expand_prompt("\\101\\061\\\\", &mut shell).display, "A1\\");
+        assert_eq!(expand_prompt("\\D{%Y}", &mut shell).display, format_time("%Y"));
+        assert_eq!(expand_prompt("a\\nb", &mut shell).display, "a\nb");
+        assert_eq!(expand_prompt("\\j\\q", &mut shell).display, "\\j\\q");
+    }
+
+    #[test]
+    fn escapes_are_not_expanded_again() {
+        let mut shell = Shell::new();
+        shell.set_var("USER", "$HOME`ls`");
+        shell.set_var("x", "1");
+        assert_eq!(expand_prompt("\\u $x $((x + 1))", &mut shell).display, "$HOME`ls` 1 2");
+    }
+
+    #[test]
+    fn non_printing_parts_are_left_out_of_the_width() {
+        let mut shell = Shell::new();
+        let prompt = expand_prompt("\\[\\e[1m\\]bold\\[\\e[0m\\] ", &mut shell);
+        assert_eq!(prompt.display, "\x1b[1mbold\x1b[0m ");
+        assert_eq!(prompt.visible, "bold ");
+    }
+
+    #[test]
+    fn home_is_shortened_in_the_working_directory() {
+        assert_eq!(working_directory("/home/alice", "/home/alice/", false), "~");
+        assert_eq!(working_directory("/home/alice/src/rshell", "/home/alice", false), "~/src/rshell");
+        assert_eq!(working_directory("/home/alice/src/rshell", "/home/alice", true), "rshell");
+        assert_eq!(working_directory("/home/alice", "/home/alice", true), "~");
+        assert_eq!(working_directory("/home/alicex", "/home/alice", false), "/home/alicex");
+        assert_eq!(working_directory("/tmp", "", false), "/tmp");
+        assert_eq!(working_directory("/", "/home/alice", true), "/");
+    }
+
+    #[test]
+    fn secondary_prompts_are_expanded_too() {
+        let mut shell = Shell::new();
+        assert_eq!(expand_prompt(&shell.get_var("PS2").unwrap(), &mut shell).display, "> ");
+        assert_eq!(expand_prompt(&shell.get_var("PS4").unwrap(), &mut shell).display, "+ ");
+        shell.set_var("PS2", "\\[\\e[2m\\]$n>\\[\\e[0m\\] ");
+        shell.set_var("n", "3");
+        assert_eq!(expand_prompt(&shell.get_var("PS2").unwrap(), &mut shell).visible, "3> ");
+    }
+}
@@ -1,8 +1,9 @@
 use std::fmt::Display;
 
-#[derive(Clone)]
+#[derive(Debug, Clone)]
 pub struct Redirection {
     pub r_type: RedirType,
+    /// The target word, or the body of a here-document.
     pub path: String
 }
 
@@ -10,7 +11,12 @@ pub struct Redirection {
 pub enum RedirType {
     Stdout(RedirOp),
     Stderr(RedirOp),
-    None
+    Both(RedirOp),
+    Stdin,
+    Duplicate(i32),
+    /// A here-document, whose body is expanded unless its delimiter was
+    /// quoted.
+    HereDoc { expand: bool }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -33,7 +39,10 @@ impl Display for RedirType {
         match self {
             RedirType::Stdout(redir_op) => write!(f, "Stdout: {}", redir_op),
             RedirType::Stderr(redir_op) => write!(f, "Stderr: {}", redir_op),
-            RedirType::None => write!(f, "none"),
+            RedirType::Both(redir_op) => write!(f, "Both: {}", redir_op),
+            RedirType::Stdin => write!(f, "Stdin"),
+            RedirType::Duplicate(fd) => write!(f, "Duplicate: {}", fd),
+            RedirType::HereDoc { expand } => write!(f, "HereDoc: {}", expand),
         }
     }
 }
@@ -41,44 +50,29 @@ impl Display for RedirType {
 impl RedirType {
     pub fn get_redir_op(self) -> Option<RedirOp> {
         match self {
-            RedirType::Stdout(op) | RedirType::Stderr(op) => Some(op),
-            RedirType::None => None,
+            RedirType::Stdout(op) | RedirType::Stderr(op) | RedirType::Both(op) => Some(op),
+            _ => None,
         }
     }
 }
 
-pub fn has_redirection(arguments: &Vec<String>) -> bool {
-    let redirections = [String::from(">"), String::from(">>"), String::from("1>"), String::from("1>>"), String::from("2>"), String::from("2>>")];
-    arguments.iter().find(|x| redirections.contains(x)).is_some() 
-}
-
-pub fn find_redirection(arguments: Vec<String>) -> Result<(Vec<String>, Redirection), bool> {
-    let args = arguments.clone();
-    let mut redirection = Redirection {
-        r_type: RedirType::None,
-        path: String::new()
-    };
-
-    let mut i = args.len();
+impl Redirection {
+    /// Builds a redirection from an operator token such as `>>`, `2>` or
+    /// `2>&`, and the word following it.
+    pub fn from_operator(operator: &str, path: String) -> Option<Redirection> {
+        let r_type = match operator {
+            ">" | "1>" | ">|" | "1>|" => RedirType::Stdout(RedirOp::Write),
+            ">>" | "1>>" => RedirType::Stdout(RedirOp::Append),
+            "2>" | "2>|" => RedirType::Stderr(RedirOp::Write),
+            "2>>" => RedirType::Stderr(RedirOp::Append),
+            "&>" => RedirType::Both(RedirOp::Write),
+            "&>>" => RedirType::Both(RedirOp::Append),
+            "<" | "0<" => RedirType::Stdin,
+            ">&" | "1>&" => RedirType::Duplicate(1),
+            "2>&" => RedirType::Duplicate(2),
+            _ => return None,
+        };
 
-    if let Some(index) = args.iter().position(|x| x == ">" || x == ">>" || x == "1>" || x == "1>>" || x == "2>" || x == "2>>" ) {
-        i = index;
-        if index + 1 < args.len() {
-            redirection.r_type = match args[index].as_str() {
-                ">>" => RedirType::Stdout(RedirOp::Append),
-                ">" => RedirType::Stdout(RedirOp::Write),
-                "1>>" => RedirType::Stdout(RedirOp::Append),
-                "1>" => RedirType::Stdout(RedirOp::Write),
-                "2>>" => RedirType::Stderr(RedirOp::Append),
-                "2>" => RedirType::Stderr(RedirOp::Write),
-                _ => RedirType::None,
-            };
-
-            redirection.path = args[index + 1].clone();
-        } else {
-            return Err(false);
-        }
+        Some(Redirection { r_type, path })
     }
-
-    Ok((args[..i].to_vec(), redirection))
 }
@@ -0,0 +1,185 @@
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::mem;
+use std::path::Path;
+
+use crate::executor::{execute_list, file_error};
+use crate::lexer::ParseError;
+use crate::parser;
+use crate::sherror::ShellError;
+use crate::shell::{Flow, Shell};
+
+/// Runs the commands read from `reader`, one complete command at a time, so
+/// that a script can define functions before using them and stop at `exit`
+/// without parsing the rest. Errors are reported as `name: line N` and a
+/// syntax error aborts the script with status 2.
+pub fn run_source<R: BufRead>(name: &str, reader: R, shell: &mut Shell) -> i32 {
+    let outer = shell.script.take();
+    let status = run_lines(name, reader, shell);
+    shell.script = outer;
+    status
+}
+
+/// Runs the file at `path` like a script, in the current shell.
+pub fn run_file(path: &Path, shell: &mut Shell) -> Result<i32, ShellError> {
+    let name = path.display().to_string();
+    let file = fs::File::open(path).map_err(|err| file_error(&name, err))?;
+    Ok(run_source(&name, BufReader::new(file), shell))
+}
+
+/// Runs a file for `source`: `arguments`, when given, replace the positional
+/// parameters while it runs, and `return` stops it with the given status.
+pub fn source_file(path: &Path, arguments: Vec<String>, shell: &mut Shell) -> Result<i32, ShellError> {
+    let positional = if arguments.is_empty() {
+        None
+    } else {
+        Some(mem::replace(&mut shell.positional, arguments))
+    };
+    shell.source_depth += 1;
+
+    let mut result = run_file(path, shell);
+    if let Flow::Return(code) = shell.flow {
+        shell.flow = Flow::Normal;
+        result = Ok(code);
+    }
+
+    shell.source_depth -= 1;
+    if let Some(positional) = positional {
+        shell.positional = positional;
+    }
+
+    result
+}
+
+fn run_lines<R: BufRead>(name: &str, reader: R, shell: &mut Shell) -> i32 {
+    let mut lines = reader.lines();
+    let mut buffer = String::new();
+    let mut line_number = 0;
+    let mut start = 1;
+
+    loop {
+        let eof = match lines.next() {
+            Some(Ok(line)) => {
+                line_number += 1;
+                if buffer.is_empty() {
+                    start = line_number;
+                }
+                buffer.push_str(&line);
+                buffer.push('\n');
+                false
+            },
+            Some(Err(err)) => {
+                eprintln!("{}: {}", name, err);
+                return 1;
+            },
+            None => true,
+        };
+
+        match parser::parse(&buffer, &shell.aliases) {
+            Ok(list) => {
+                buffer.clear();
+                shell.script = Some((name.to_string(), start));
+                execute_list(&list, shell);
+            },
+            Err(ParseError::Incomplete(_)) if !eof => continue,
+            Err(err) => {
+                eprintln!("{}: line {}: {}", name, line_number, err);
+                shell.last_status = 2;
+                return 2;
+            },
+        }
+
+        if eof || matches!(shell.flow, Flow::Exit(_) | Flow::Return(_)) {
+            break;
+        }
+    }
+
+    shell.last_status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(source: &str, shell: &mut Shell) -> i32 {
+        shell.set_var("PATH", "");
+        run_source("test.sh", source.as_bytes(), shell)
+    }
+
+    #[test]
+    fn commands_span_lines() {
+        let mut shell = Shell::new();
+        assert_eq!(run("f() {\n  x=$1\n}\nif true\nthen f one\nfi\n", &mut shell), 0);
+        assert_eq!(shell.get_var("x").as_deref(), Some("one"));
+    }
+
+    #[test]
+    fn the_last_status_is_returned() {
+        assert_eq!(run("false\ntrue\n", &mut Shell::new()), 0);
+        assert_eq!(run("true\nfalse", &mut Shell::new()), 1);
+    }
+
+    #[test]
+    fn exit_stops_the_script() {
+        let mut shell = Shell::new();
+        run("a=1\nexit 3\na=2\n", &mut shell);
+        assert!(matches!(shell.flow, Flow::Exit(3)));
+        assert_eq!(shell.get_var("a").as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn syntax_errors_abort_with_status_2() {
+        let mut shell = Shell::new();
+        assert_eq!(run("a=1\nfi\na=2\n", &mut shell), 2);
+        assert_eq!(shell.get_var("a").as_deref(), Some("1"));
+        assert_eq!(run("if true; then\n", &mut Shell::new()), 2);
+    }
+
+    #[test]
+    fn name_and_positional_parameters() {
+        let mut shell = Shell::new();
+        shell.name = "script.sh".to_string();
+        shell.positional = vec!["a".to_string(), "b c".to_string()];
+        run("x=\"$0:$1:$2:$#\"", &mut shell);
+        assert_eq!(shell.get_var("x").as_deref(), Some("script.sh:a:b c:2"));
+    }
+
+    fn sourced(name: &str, content: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("rshell-source-{}-{}", std::process::id(), name));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn source_replaces_positional_parameters_for_its_arguments() {
+        let path = sourced("args", "seen=\"$#:$*\"\n");
+        let mut shell = Shell::new();
+        shell.positional = vec!["outer".to_string()];
+
+        source_file(&path, vec!["a".to_string(), "b".to_string()], &mut shell).unwrap();
+        assert_eq!(shell.get_var("seen").as_deref(), Some("2:a b"));
+        assert_eq!(shell.positional, ["outer"]);
+
+        source_file(&path, vec![], &mut shell).unwrap();
+        assert_eq!(shell.get_var("seen").as_deref(), Some("1:outer"));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn return_stops_a_sourced_file() {
+        let path = sourced("return", "a=1\nf() { return 4; }\nf\nb=$?\nreturn 3\nc=1\n");
+        let mut shell = Shell::new();
+        assert_eq!(source_file(&path, vec![], &mut shell).unwrap(), 3);
+        assert_eq!(shell.get_var("a").as_deref(), Some("1"));
+        assert_eq!(shell.get_var("b").as_deref(), Some("4"));
+        assert_eq!(shell.get_var("c"), None);
+        assert!(matches!(shell.flow, Flow::Normal));
+        assert_eq!(shell.source_depth, 0);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn missing_files_are_errors() {
+        assert!(source_file(Path::new("/nonexistent/file"), vec![], &mut Shell::new()).is_err());
+    }
+}
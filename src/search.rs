@@ -0,0 +1,299 @@
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use rustyline::{Cmd, ConditionalEventHandler, Event, EventContext, RepeatCount};
+
+const MAX_ROWS: usize = 10;
+
+/// Scores how well `query` matches `candidate` when its characters appear
+/// in order, possibly with gaps. Consecutive characters, matches at word
+/// starts and exact substrings score higher. The match ignores case unless
+/// the query has an uppercase letter. Returns `None` when it doesn't match.
+pub fn fuzzy_score(query: &str, candidate: &str) -> Option<i64> {
+    let ignore_case = !query.chars().any(|c| c.is_uppercase());
+    let fold = |c: char| if ignore_case { c.to_ascii_lowercase() } else { c };
+    let chars: Vec<char> = candidate.chars().collect();
+
+    let mut score = 0;
+    let mut pos = 0;
+    let mut previous: Option<usize> = None;
+
+    for q in query.chars().map(fold) {
+        let found = (pos..chars.len()).find(|&i| fold(chars[i]) == q)?;
+
+        score += 16;
+        match previous {
+            Some(previous) if found == previous + 1 => score += 15,
+            Some(previous) => score -= (found - previous - 1).min(5) as i64,
+            None => score -= found.min(10) as i64,
+        }
+        if found == 0 || !chars[found - 1].is_alphanumeric() {
+            score += 10;
+        }
+
+        previous = Some(found);
+        pos = found + 1;
+    }
+
+    let contains = if ignore_case {
+        candidate.to_lowercase().contains(&query.to_lowercase())
+    } else {
+        candidate.contains(query)
+    };
+    if contains {
+        score += 50;
+    }
+
+    Some(score)
+}
+
+/// Runs the interactive Ctrl-R search over `items`, ordered from the most
+/// recent, starting with `query`. Returns the chosen entry, or `None` when
+/// the search was cancelled.
+pub fn search(items: &[String], query: &str) -> io::Result<Option<String>> {
+    let _raw = RawMode::enable()?;
+    let mut stdout = io::stdout();
+    let mut query = query.to_string();
+    let mut selected = 0;
+
+    let result = loop {
+        let matches = rank(items, &query);
+        selected = selected.min(matches.len().saturating_sub(1));
+        render(&mut stdout, &query, &matches, selected)?;
+
+        match read_key()? {
+            Key::Char(c) => {
+                query.push(c);
+                selected = 0;
+            },
+            Key::Backspace => {
+                query.pop();
+                selected = 0;
+            },
+            Key::Clear => {
+                query.clear();
+                selected = 0;
+            },
+            Key::Next => selected += 1,
+            Key::Previous => selected = selected.saturating_sub(1),
+            Key::Accept => break matches.get(selected).map(|entry| entry.to_string()),
+            Key::Cancel => break None,
+            Key::Ignored => {},
+        }
+    };
+
+    write!(stdout, "\r\x1b[J")?;
+    stdout.flush()?;
+    Ok(result)
+}
+
+/// Entries matching `query`, best first. Equal scores keep the most recent
+/// entry first.
+fn rank<'a>(items: &'a [String], query: &str) -> Vec<&'a str> {
+    let mut scored: Vec<(i64, &str)> = items
+        .iter()
+        .filter_map(|item| fuzzy_score(query, item).map(|score| (score, item.as_str())))
+        .collect();
+
+    scored.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+    scored.into_iter().map(|(_, item)| item).collect()
+}
+
+/// Draws the query line and the best matches below it, then puts the
+/// cursor back at the end of the query.
+fn render(stdout: &mut io::Stdout, query: &str, matches: &[&str], selected: usize) -> io::Result<()> {
+    let (width, height) = terminal_size();
+    let rows = MAX_ROWS.min(height.saturating_sub(2)).max(1);
+    let first = selected.saturating_sub(rows - 1);
+
+    let header = format!("(fuzzy search) {}", query);
+    write!(stdout, "\r\x1b[J{}", header)?;
+
+    let shown = matches.iter().enumerate().skip(first).take(rows);
+    let mut count = 0;
+    for (i, entry) in shown {
+        let line: String = entry.replace('\n', " ").chars().take(width.saturating_sub(3)).collect();
+        if i == selected {
+            write!(stdout, "\r\n\x1b[7m> {}\x1b[0m", line)?;
+        } else {
+            write!(stdout, "\r\n  {}", line)?;
+        }
+        count += 1;
+    }
+    if matches.is_empty() {
+        write!(stdout, "\r\n  (no match)")?;
+        count = 1;
+    }
+
+    write!(stdout, "\x1b[{}A\r", count)?;
+    let column = header.chars().count();
+    if column > 0 {
+        write!(stdout, "\x1b[{}C", column)?;
+    }
+    stdout.flush()
+}
+
+enum Key {
+    Char(char),
+    Backspace,
+    Clear,
+    Next,
+    Previous,
+    Accept,
+    Cancel,
+    Ignored
+}
+
+fn read_key() -> io::Result<Key> {
+    Ok(match read_byte()? {
+        b'\r' | b'\n' | b'\t' => Key::Accept,
+        0x03 | 0x07 | 0x04 => Key::Cancel,
+        0x7f | 0x08 => Key::Backspace,
+        0x15 => Key::Clear,
+        0x12 | 0x0e => Key::Next,
+        0x13 | 0x10 => Key::Previous,
+        0x1b => {
+            // A lone escape cancels, otherwise it starts an arrow key.
+            if !input_pending(50) {
+                return Ok(Key::Cancel);
+            }
+            match [read_byte()?, read_byte()?] {
+                [b'[', b'A'] | [b'O', b'A'] => Key::Previous,
+                [b'[', b'B'] | [b'O', b'B'] => Key::Next,
+                _ => Key::Ignored,
+            }
+        },
+        c if c < 0x20 => Key::Ignored,
+        c => {
+            // Read the rest of a UTF-8 sequence.
+            let len = match c {
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                0xf0..=0xf7 => 4,
+                _ => 1,
+            };
+            let mut bytes = vec![c];
+            for _ in 1..len {
+                bytes.push(read_byte()?);
+            }
+            match std::str::from_utf8(&bytes).ok().and_then(|s| s.chars().next()) {
+                Some(c) => Key::Char(c),
+                None => Key::Ignored,
+            }
+        },
+    })
+}
+
+/// Reads straight from the descriptor: the buffer of `io::stdin` would hide
+/// pending bytes from `input_pending`.
+fn read_byte() -> io::Result<u8> {
+    let mut byte = 0u8;
+    loop {
+        match unsafe { libc::read(0, &mut byte as *mut u8 as *mut libc::c_void, 1) } {
+            1 => return Ok(byte),
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            _ => {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            },
+        }
+    }
+}
+
+fn input_pending(timeout_ms: i32) -> bool {
+    let mut fd = libc::pollfd { fd: 0, events: libc::POLLIN, revents: 0 };
+    unsafe { libc::poll(&mut fd, 1, timeout_ms) > 0 }
+}
+
+fn terminal_size() -> (usize, usize) {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(1, libc::TIOCGWINSZ, &mut size) } < 0 || size.ws_col == 0 {
+        return (80, 24);
+    }
+    (size.ws_col as usize, size.ws_row as usize)
+}
+
+/// Puts the terminal in non-canonical mode without echo until dropped.
+struct RawMode(libc::termios);
+
+impl RawMode {
+    fn enable() -> io::Result<RawMode> {
+        let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(0, &mut termios) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let original = termios;
+        termios.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG | libc::IEXTEN);
+        termios.c_iflag &= !(libc::IXON | libc::ICRNL);
+        termios.c_cc[libc::VMIN] = 1;
+        termios.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(0, libc::TCSADRAIN, &termios) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(RawMode(original))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(0, libc::TCSADRAIN, &self.0) };
+    }
+}
+
+/// Ctrl-R binding: leaves the line editor, keeping the line typed so far,
+/// so that the shell can run the search and come back with the result.
+pub struct SearchTrigger(pub Arc<Mutex<Option<String>>>);
+
+impl ConditionalEventHandler for SearchTrigger {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, ctx: &EventContext) -> Option<Cmd> {
+        if let Ok(mut pending) = self.0.lock() {
+            *pending = Some(ctx.line().to_string());
+        }
+        Some(Cmd::Interrupt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn consecutive_and_word_start_matches_score_higher() {
+        let score = |candidate| fuzzy_score("gco", candidate).unwrap();
+        assert!(score("git checkout") > score("xgxcxox"));
+        assert!(score("gco") > score("git checkout"));
+        assert!(fuzzy_score("abc", "abcdef") > fuzzy_score("abc", "a_b_c"));
+        assert!(fuzzy_score("abc", "a_b_c") > fuzzy_score("abc", "axxxxbxxxxc"));
+        assert!(fuzzy_score("make", "make test") > fuzzy_score("make", "cmake test"));
+    }
+
+    #[test]
+    fn substrings_beat_scattered_matches() {
+        assert!(fuzzy_score("test", "cargo test") > fuzzy_score("test", "tail -f /etc/shells"));
+    }
+
+    #[test]
+    fn characters_must_come_in_order() {
+        assert_eq!(fuzzy_score("ba", "ab"), None);
+        assert_eq!(fuzzy_score("xyz", "abc"), None);
+        assert!(fuzzy_score("", "anything").is_some());
+    }
+
+    #[test]
+    fn smart_case() {
+        assert!(fuzzy_score("foo", "FOO bar").is_some());
+        assert!(fuzzy_score("Foo", "Foo bar").is_some());
+        assert_eq!(fuzzy_score("Foo", "foo bar"), None);
+        assert!(fuzzy_score("readme", "cat README.md") > fuzzy_score("readme", "read my notes"));
+    }
+
+    #[test]
+    fn rank_orders_by_score_and_keeps_recency_for_ties() {
+        let items: Vec<String> = ["git status", "echo gst", "git stash", "ls"].map(String::from).to_vec();
+        assert_eq!(rank(&items, "gst"), ["echo gst", "git status", "git stash"]);
+        assert_eq!(rank(&items, "zzz"), Vec::<&str>::new());
+    }
+}
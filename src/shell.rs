@@ -1,30 +1,195 @@
-use std::{collections::HashMap, env};
+use std::{collections::{BTreeMap, HashMap, HashSet}, env, io, path::PathBuf, rc::Rc};
+
+use crate::completion::CompletionSpec;
+use crate::history::{History, DEFAULT_HISTSIZE};
+use crate::keys::KeyConfig;
+use crate::parser::Function;
+use crate::shfile::{is_executable, is_executable_file};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Flow {
+    Normal,
+    Break(usize),
+    Continue(usize),
+    Return(i32),
+    Exit(i32)
+}
+
+/// Options of `set -o`, with their single-letter form.
+pub const OPTIONS: [(&str, Option<char>); 3] = [("emacs", None), ("vi", None), ("xtrace", Some('x'))];
+
+/// Previous state of a variable shadowed by `local`, restored when the
+/// function returns.
+pub type SavedVar = (String, Option<String>, bool);
+
+/// A command remembered by `hash`: its path and the number of times it was
+/// run from there.
+pub type Hashed = (String, usize);
 
 #[derive(Clone)]
 pub struct Shell {
+    pub name: String,
+    /// Script and line of the command being run, to locate error messages.
+    pub script: Option<(String, usize)>,
     pub environment: HashMap<String, String>,
-    pub builtins: Vec<String>
+    pub exported: HashSet<String>,
+    pub arrays: HashMap<String, Vec<String>>,
+    pub builtins: Vec<String>,
+    pub functions: HashMap<String, Rc<Function>>,
+    pub aliases: HashMap<String, String>,
+    /// Completions registered with `complete`, by command name.
+    pub completions: HashMap<String, CompletionSpec>,
+    /// Commands remembered by `hash`, forgotten when `PATH` changes.
+    pub hashed: BTreeMap<String, Hashed>,
+    /// `PATH` the commands in `hashed` were found with.
+    hashed_path: String,
+    /// Options turned on with `set -o`, by their long name.
+    pub options: HashSet<String>,
+    /// Key bindings and line editor settings from inputrc and `bind`.
+    pub keys: KeyConfig,
+    pub history: History,
+    pub positional: Vec<String>,
+    pub locals: Vec<Vec<SavedVar>>,
+    pub source_depth: usize,
+    pub last_status: i32,
+    pub substitution_status: Option<i32>,
+    pub flow: Flow,
+    pub loop_depth: usize
 }
 
 impl Shell {
     pub fn new() -> Self {
-        let path = match env::var("PATH") {
-            Ok(p) => p,
-            Err(_) => String::new(),
-        };
-    
-        let home = match env::var("HOME") {
-            Ok(p) => p,
-            Err(_) => String::new(),
-        };
-
-        let mut environment = HashMap::new();
-        environment.insert("path".to_string(), path);
-        environment.insert("home".to_string(), home);
+        let mut environment: HashMap<String, String> = env::vars().collect();
+        let exported = environment.keys().cloned().collect();
+
+        environment.entry("PATH".to_string()).or_default();
+        environment.entry("HOME".to_string()).or_default();
+        environment.entry("PS2".to_string()).or_insert_with(|| "> ".to_string());
+        environment.entry("PS4".to_string()).or_insert_with(|| "+ ".to_string());
 
         Shell {
+            name: "rshell".to_string(),
+            script: None,
             environment,
-            builtins: vec!["exit", "echo", "type", "pwd", "cd"].iter().map(|x| x.to_string()).collect(),
+            exported,
+            arrays: HashMap::new(),
+            builtins: ["exit", "echo", "type", "pwd", "cd", ":", "true", "false", "break", "continue", "local", "return", "shift", "export", "unset", "alias", "unalias", "history", "source", ".", "set", "test", "[", "complete", "compgen", "hash", "bind"].iter().map(|x| x.to_string()).collect(),
+            functions: HashMap::new(),
+            aliases: HashMap::new(),
+            completions: HashMap::new(),
+            hashed: BTreeMap::new(),
+            hashed_path: String::new(),
+            options: HashSet::new(),
+            keys: KeyConfig::new(),
+            history: History::default(),
+            positional: vec![],
+            locals: vec![],
+            source_depth: 0,
+            last_status: 0,
+            substitution_status: None,
+            flow: Flow::Normal,
+            loop_depth: 0
+        }
+    }
+
+    pub fn option(&self, name: &str) -> bool {
+        self.options.contains(name)
+    }
+
+    pub fn get_var(&self, name: &str) -> Option<String> {
+        self.environment.get(name).cloned()
+    }
+
+    pub fn set_var(&mut self, name: &str, value: &str) {
+        self.environment.insert(name.to_string(), value.to_string());
+    }
+
+    pub fn save_var(&self, name: &str) -> SavedVar {
+        (name.to_string(), self.get_var(name), self.exported.contains(name))
+    }
+
+    pub fn restore_var(&mut self, (name, value, exported): SavedVar) {
+        match value {
+            Some(value) => self.set_var(&name, &value),
+            None => { self.environment.remove(&name); },
+        }
+        if exported {
+            self.exported.insert(name);
+        } else {
+            self.exported.remove(&name);
+        }
+    }
+
+    /// Makes `name` local to the innermost function call, so that its
+    /// previous value comes back once the function returns.
+    pub fn declare_local(&mut self, name: &str) -> bool {
+        let saved = self.save_var(name);
+        match self.locals.last_mut() {
+            Some(frame) => {
+                if !frame.iter().any(|(local, _, _)| local == name) {
+                    frame.push(saved);
+                }
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// The commands remembered by `hash`, emptied first if `PATH` changed
+    /// since they were looked up.
+    pub fn hash_table(&mut self) -> &mut BTreeMap<String, Hashed> {
+        let path = self.get_var("PATH").unwrap_or_default();
+        if self.hashed_path != path {
+            self.hashed.clear();
+            self.hashed_path = path;
         }
+        &mut self.hashed
     }
-}
\ No newline at end of file
+
+    /// Path of the external `command`: the remembered one if it is still
+    /// there, otherwise the one found in `PATH`, which is then remembered.
+    pub fn command_path(&mut self, command: &str) -> Option<String> {
+        let path = self.get_var("PATH").unwrap_or_default();
+        if command.contains('/') {
+            return is_executable(&path, command).ok();
+        }
+
+        if let Some((full_path, _)) = self.hash_table().get(command).filter(|(full_path, _)| is_executable_file(full_path)) {
+            return Some(full_path.clone());
+        }
+
+        let full_path = is_executable(&path, command).ok()?;
+        self.hash_table().insert(command.to_string(), (full_path.clone(), 0));
+        Some(full_path)
+    }
+
+    /// File the history is saved to, from `HISTFILE`. An empty value keeps
+    /// the history in memory only.
+    pub fn history_file(&self) -> Option<PathBuf> {
+        self.get_var("HISTFILE").filter(|file| !file.is_empty()).map(PathBuf::from)
+    }
+
+    /// Database recording the details of each command, from `HISTDB`.
+    pub fn history_db(&self) -> Option<PathBuf> {
+        self.get_var("HISTDB").filter(|file| !file.is_empty()).map(PathBuf::from)
+    }
+
+    /// Number of entries kept, from `HISTSIZE`.
+    pub fn history_size(&self) -> usize {
+        self.get_var("HISTSIZE").and_then(|size| size.parse().ok()).unwrap_or(DEFAULT_HISTSIZE)
+    }
+
+    pub fn add_history(&mut self, line: &str) -> io::Result<()> {
+        let file = self.history_file();
+        let size = self.history_size();
+        self.history.add(line, file.as_deref(), size)
+    }
+
+    /// Variables handed down to child processes.
+    pub fn exported_vars(&self) -> Vec<(String, String)> {
+        self.exported
+            .iter()
+            .filter_map(|name| self.environment.get(name).map(|value| (name.clone(), value.clone())))
+            .collect()
+    }
+}
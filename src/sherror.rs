@@ -1,10 +1,14 @@
 #[allow(dead_code)]
+#[derive(Debug)]
 pub enum ShellError {
     IoError(std::io::Error),
     FileNotFound(String),
     InvalidArgument(String),
     ExecutionError(String),
-    ExecutableNotFound(String)
+    ExecutableNotFound(String),
+    CommandNotFound(String),
+    Usage(String),
+    ExitStatus(i32)
 }
 
 impl From<std::io::Error> for ShellError {
@@ -13,13 +17,27 @@ impl From<std::io::Error> for ShellError {
     }
 }
 
+impl ShellError {
+    /// Exit status reported by a command failing with this error.
+    pub fn status(&self) -> i32 {
+        match self {
+            ShellError::CommandNotFound(_) => 127,
+            ShellError::Usage(_) => 2,
+            ShellError::ExitStatus(status) => *status,
+            _ => 1,
+        }
+    }
+}
+
 pub fn get_error_message(err: &ShellError) -> Option<&str> {
     match err {
-        ShellError::FileNotFound(msg) 
-        | ShellError::InvalidArgument(msg) 
-        | ShellError::ExecutionError(msg) 
-        | ShellError::ExecutableNotFound(msg) => Some(msg),
-        
-        ShellError::IoError(_) => None,
+        ShellError::FileNotFound(msg)
+        | ShellError::InvalidArgument(msg)
+        | ShellError::ExecutionError(msg)
+        | ShellError::ExecutableNotFound(msg)
+        | ShellError::CommandNotFound(msg)
+        | ShellError::Usage(msg) => Some(msg),
+
+        ShellError::IoError(_) | ShellError::ExitStatus(_) => None,
     }
-}
\ No newline at end of file
+}
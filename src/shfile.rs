@@ -1,60 +1,191 @@
-use crate::sherror::ShellError;
+use std::{cell::RefCell, collections::BTreeMap, fs, os::unix::fs::PermissionsExt, time::SystemTime};
 
-use std::{fs, process::Command};
+/// Files of the directories of a `PATH` by name, with the path of the
+/// first one found. The index is rebuilt when `PATH` or the modification
+/// time of one of its directories changes; since making a file executable
+/// does not change the latter, permissions are checked on lookup.
+struct PathIndex {
+    path: String,
+    directories: Vec<(String, Option<SystemTime>)>,
+    commands: BTreeMap<String, String>
+}
+
+thread_local! {
+    static INDEX: RefCell<Option<PathIndex>> = const { RefCell::new(None) };
+}
 
-pub fn executable_exists(path: &str, command: &str) -> Result<String, ShellError> {
-    let directories = path.split(':');
+impl PathIndex {
+    fn build(path: &str) -> PathIndex {
+        let mut commands = BTreeMap::new();
+        let mut directories = vec![];
 
-    for directory in directories {
-        let full_path = format!("{}/{}", directory, command);
-        if std::fs::metadata(&full_path).is_ok() {
-            return Ok(String::from(format!("{} is {}", command, full_path)));
+        // An empty entry would mean the current directory, which changes
+        // without notice: it is left out.
+        for directory in path.split(':').filter(|directory| !directory.is_empty()) {
+            directories.push((directory.to_string(), modified(directory)));
+            let Ok(entries) = fs::read_dir(directory) else { continue };
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                let full_path = format!("{}/{}", directory, name);
+                commands.entry(name).or_insert(full_path);
+            }
         }
+
+        PathIndex { path: path.to_string(), directories, commands }
     }
 
-    Err(ShellError::ExecutableNotFound(format!("{}: not found", command)))
+    fn is_current(&self, path: &str) -> bool {
+        self.path == path && self.directories.iter().all(|(directory, time)| modified(directory) == *time)
+    }
+}
+
+fn modified(directory: &str) -> Option<SystemTime> {
+    fs::metadata(directory).and_then(|metadata| metadata.modified()).ok()
+}
+
+fn with_index<T, F: FnOnce(&PathIndex) -> T>(path: &str, f: F) -> T {
+    INDEX.with(|slot| {
+        let mut slot = slot.borrow_mut();
+        let index = match slot.take() {
+            Some(index) if index.is_current(path) => index,
+            _ => PathIndex::build(path),
+        };
+        let result = f(&index);
+        *slot = Some(index);
+        result
+    })
 }
 
+/// Drops the index of `PATH`, as `hash -r` does.
+pub fn forget_executables() {
+    INDEX.with(|slot| slot.borrow_mut().take());
+}
+
+/// Names of the executables in `PATH` starting with `partial`, sorted.
 pub fn find_executables(path: &str, partial: &str) -> Vec<String> {
-    let executables: Vec<String> = list_content(path);
-    let mut exec_completion = vec![];
+    with_index(path, |index| {
+        index.commands
+            .range(partial.to_string()..)
+            .take_while(|(name, _)| name.starts_with(partial))
+            .filter(|(_, full_path)| is_executable_file(full_path))
+            .map(|(name, _)| name.clone())
+            .collect()
+    })
+}
 
-    for executable in executables {
-        if executable.starts_with(partial) {
-            exec_completion.push(executable);
-        }
+/// The path of `command`, found in the index of `PATH`. When the indexed
+/// file is not executable, a later directory may hold one.
+pub fn is_executable(path: &str, command: &str) -> Result<String, bool> {
+    if command.contains('/') {
+        return if is_executable_file(command) { Ok(command.to_string()) } else { Err(false) };
     }
 
-    exec_completion
+    let indexed = with_index(path, |index| index.commands.get(command).cloned());
+    if let Some(full_path) = indexed.filter(|full_path| is_executable_file(full_path)) {
+        return Ok(full_path);
+    }
+
+    path.split(':')
+        .filter(|directory| !directory.is_empty())
+        .map(|directory| format!("{}/{}", directory, command))
+        .find(|full_path| is_executable_file(full_path))
+        .ok_or(false)
 }
 
-pub fn is_executable(path: &str, command: &str) -> Result<String, bool> {
-    let directories = path.split(':');
-
-    for directory in directories {
-        let full_path = format!("{}/{}", directory, command);
-        
-        if fs::metadata(&full_path).is_ok() {
-            if Command::new(&full_path).output().is_ok() {
-                return Ok(full_path)
-            }
-        }
+/// Locates the file read by `source`: names containing a slash are used as
+/// is, others are looked up in `PATH` and then in the current directory.
+pub fn find_source_file(path: &str, name: &str) -> Option<String> {
+    if name.contains('/') {
+        return Some(name.to_string());
     }
-    Err(false)
+
+    path.split(':')
+        .filter(|directory| !directory.is_empty())
+        .map(|directory| format!("{}/{}", directory, name))
+        .find(|full_path| fs::metadata(full_path).is_ok_and(|metadata| metadata.is_file()))
+        .or_else(|| fs::metadata(name).is_ok_and(|metadata| metadata.is_file()).then(|| name.to_string()))
 }
 
-pub fn list_content(path: &str) -> Vec<String> {
-    let directories = path.split(':');
+pub fn is_executable_file(path: &str) -> bool {
+    fs::metadata(path).is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_files_are_searched_in_path() {
+        let dir = std::env::temp_dir().join(format!("rshell-path-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("env.sh"), "").unwrap();
+        let path = format!("/nonexistent::{}", dir.display());
 
-    let output = Command::new("ls")
-        .args(directories)
-        .output()
-        .expect("IO Error");
-    
-    let stdout = String::from_utf8_lossy(&output.stdout);
+        assert_eq!(find_source_file(&path, "env.sh"), Some(format!("{}/env.sh", dir.display())));
+        assert_eq!(find_source_file(&path, "sub"), None);
+        assert_eq!(find_source_file(&path, "missing.sh"), None);
+        // Names with a slash are not looked up.
+        assert_eq!(find_source_file("", "./missing.sh"), Some("./missing.sh".to_string()));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    fn executable(dir: &std::path::Path, name: &str) -> String {
+        let file = dir.join(name);
+        fs::write(&file, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o755)).unwrap();
+        file.display().to_string()
+    }
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("rshell-index-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
 
-    stdout
-        .lines()
-        .map(|s| s.to_string())
-        .collect()
-}
\ No newline at end of file
+    #[test]
+    fn index_follows_path_and_its_directories() {
+        let (first, second) = (temp_dir("first"), temp_dir("second"));
+        let tool = executable(&first, "tool");
+        let other = executable(&second, "tool");
+        fs::write(second.join("tool-data"), "").unwrap();
+        let both = format!("{}:{}", first.display(), second.display());
+
+        assert_eq!(find_executables(&both, "to"), ["tool"]);
+        assert_eq!(is_executable(&both, "tool"), Ok(tool.clone()));
+        // A new PATH rebuilds the index.
+        assert_eq!(is_executable(&second.display().to_string(), "tool"), Ok(other.clone()));
+
+        // So does a file added to one of its directories.
+        executable(&second, "tooling");
+        assert_eq!(find_executables(&both, "tool"), ["tool", "tooling"]);
+
+        // A file removed from disk is not found, and a later directory
+        // still gives one.
+        fs::remove_file(&tool).unwrap();
+        assert_eq!(is_executable(&both, "tool"), Ok(other));
+        fs::set_permissions(second.join("tooling"), fs::Permissions::from_mode(0o644)).unwrap();
+        assert_eq!(find_executables(&both, "tool"), ["tool"]);
+        assert_eq!(is_executable(&both, "tooling"), Err(false));
+
+        let _ = fs::remove_dir_all(&first);
+        let _ = fs::remove_dir_all(&second);
+    }
+
+    #[test]
+    fn forgotten_index_is_rebuilt() {
+        let dir = temp_dir("forget");
+        let path = dir.display().to_string();
+        assert!(find_executables(&path, "").is_empty());
+
+        // A file added without changing the directory's time is not seen
+        // until the index is dropped.
+        let time = modified(&path).unwrap();
+        executable(&dir, "late");
+        fs::File::open(&dir).unwrap().set_modified(time).unwrap();
+        assert!(find_executables(&path, "").is_empty());
+        forget_executables();
+        assert_eq!(find_executables(&path, ""), ["late"]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,444 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::git;
+
+/// The command line a shipped completion is asked about.
+pub struct Invocation<'a> {
+    /// The command and the words after it, up to the one being completed.
+    pub words: &'a [String],
+    pub cwd: &'a Path,
+    pub home: Option<&'a Path>
+}
+
+/// What a shipped completion offers for a word.
+pub struct Candidates {
+    pub words: Vec<String>,
+    /// Whether a path fits too, tried when no word starts with the one
+    /// typed.
+    pub paths: bool
+}
+
+/// Completion shipped with the shell for a command, used when none is
+/// registered with `complete`. It returns `None` to leave the word to the
+/// default completion.
+pub struct ToolSpec {
+    pub command: &'static str,
+    pub complete: fn(&Invocation) -> Option<Candidates>
+}
+
+pub const REGISTRY: [ToolSpec; 5] = [
+    ToolSpec { command: "git", complete: git },
+    ToolSpec { command: "cargo", complete: cargo },
+    ToolSpec { command: "make", complete: make },
+    ToolSpec { command: "docker", complete: docker },
+    ToolSpec { command: "systemctl", complete: systemctl },
+];
+
+const GIT_COMMANDS: [&str; 45] = [
+    "add", "am", "apply", "archive", "bisect", "blame", "branch", "bundle", "checkout", "cherry-pick",
+    "clean", "clone", "commit", "config", "describe", "diff", "fetch", "format-patch", "gc", "grep",
+    "init", "log", "merge", "mv", "notes", "pull", "push", "range-diff", "rebase", "reflog",
+    "remote", "reset", "restore", "revert", "rm", "shortlog", "show", "sparse-checkout", "stash", "status",
+    "submodule", "switch", "tag", "worktree", "help",
+];
+
+const CARGO_COMMANDS: [&str; 33] = [
+    "add", "bench", "build", "check", "clean", "clippy", "doc", "fetch", "fix", "fmt", "generate-lockfile",
+    "help", "init", "install", "locate-project", "login", "logout", "metadata", "new", "owner", "package",
+    "pkgid", "publish", "remove", "run", "rustc", "rustdoc", "search", "test", "tree", "uninstall", "update",
+    "vendor",
+];
+
+const DOCKER_COMMANDS: [&str; 43] = [
+    "attach", "build", "builder", "buildx", "commit", "compose", "container", "context", "cp", "create",
+    "diff", "events", "exec", "export", "history", "image", "images", "import", "info", "inspect", "kill",
+    "load", "login", "logout", "logs", "network", "pause", "port", "ps", "pull", "push", "rename", "restart",
+    "rm", "rmi", "run", "save", "start", "stats", "stop", "system", "volume", "version",
+];
+
+const SYSTEMCTL_COMMANDS: [&str; 33] = [
+    "cat", "daemon-reexec", "daemon-reload", "disable", "edit", "enable", "get-default", "hibernate",
+    "is-active", "is-enabled", "is-failed", "isolate", "kill", "list-dependencies", "list-sockets",
+    "list-timers", "list-unit-files", "list-units", "mask", "poweroff", "reboot", "reenable", "reload",
+    "reload-or-restart", "reset-failed", "restart", "set-default", "show", "start", "status", "stop",
+    "try-restart", "unmask",
+];
+
+/// The shipped completion of `command`.
+pub fn find(command: &str) -> Option<&'static ToolSpec> {
+    REGISTRY.iter().find(|spec| spec.command == command)
+}
+
+/// The arguments that aren't options, skipping the value of those in
+/// `with_value`.
+fn arguments<'a>(invocation: &'a Invocation, with_value: &[&str]) -> Vec<&'a str> {
+    let mut arguments = vec![];
+    let mut words = invocation.words.iter().skip(1);
+    while let Some(word) = words.next() {
+        if with_value.contains(&word.as_str()) {
+            words.next();
+        } else if !word.starts_with('-') {
+            arguments.push(word.as_str());
+        }
+    }
+    arguments
+}
+
+fn words(names: &[&str]) -> Vec<String> {
+    names.iter().map(|name| name.to_string()).collect()
+}
+
+fn only(words: Vec<String>) -> Option<Candidates> {
+    Some(Candidates { words, paths: false })
+}
+
+/// Subcommands and aliases, then branches, tags and remotes read from the
+/// repository where they are expected.
+fn git(invocation: &Invocation) -> Option<Candidates> {
+    let arguments = arguments(invocation, &["-C", "-c", "--git-dir", "--work-tree", "--namespace"]);
+    let cwd = invocation.cwd;
+
+    let Some(subcommand) = arguments.first() else {
+        let mut names = words(&GIT_COMMANDS);
+        names.extend(git::aliases(cwd, invocation.home));
+        return only(names);
+    };
+    if invocation.words.iter().any(|word| word == "--") {
+        return None;
+    }
+
+    let refs = || {
+        let mut names = git::ref_names(cwd, "heads");
+        names.extend(git::ref_names(cwd, "tags"));
+        names.extend(git::ref_names(cwd, "remotes").into_iter().filter(|name| !name.ends_with("/HEAD")));
+        names
+    };
+    match (*subcommand, arguments.len()) {
+        ("switch" | "branch", _) => only(git::ref_names(cwd, "heads")),
+        ("merge" | "rebase" | "cherry-pick" | "revert", _) => only(refs()),
+        ("checkout" | "diff" | "log" | "show" | "reset" | "restore", _) => Some(Candidates { words: refs(), paths: true }),
+        ("push" | "pull" | "fetch", 1) => only(git::remotes(cwd)),
+        ("push" | "pull" | "fetch", _) => only(git::ref_names(cwd, "heads")),
+        ("remote", 1) => only(words(&["add", "get-url", "prune", "remove", "rename", "set-url", "show"])),
+        ("remote", 2) if arguments[1] != "add" => only(git::remotes(cwd)),
+        ("stash", 1) => only(words(&["apply", "branch", "clear", "drop", "list", "pop", "push", "show"])),
+        _ => None,
+    }
+}
+
+/// Subcommands, then the packages and targets of the manifest for
+/// `--package`, `--bin`, `--example`, `--test` and `--bench`.
+fn cargo(invocation: &Invocation) -> Option<Candidates> {
+    let arguments = arguments(invocation, &["-p", "--package", "--bin", "--example", "--test", "--bench", "--manifest-path", "-Z"]);
+    let previous = invocation.words.last().filter(|_| invocation.words.len() > 1).map(String::as_str);
+
+    let targets = |directory: &str, table: &str| {
+        let mut names = vec![];
+        for (root, manifest) in packages(invocation.cwd) {
+            names.extend(manifest_values(&manifest, table, "name"));
+            names.extend(target_files(&root.join(directory)));
+        }
+        names.sort();
+        names.dedup();
+        only(names)
+    };
+
+    match previous {
+        Some("-p" | "--package") => only(packages(invocation.cwd).iter().flat_map(|(_, manifest)| manifest_values(manifest, "package", "name")).collect()),
+        Some("--bin") => {
+            let mut bins = vec![];
+            for (root, manifest) in packages(invocation.cwd) {
+                if root.join("src/main.rs").is_file() {
+                    bins.extend(manifest_values(&manifest, "package", "name"));
+                }
+                bins.extend(manifest_values(&manifest, "[bin]", "name"));
+                bins.extend(target_files(&root.join("src/bin")));
+            }
+            bins.sort();
+            bins.dedup();
+            only(bins)
+        },
+        Some("--example") => targets("examples", "[example]"),
+        Some("--test") => targets("tests", "[test]"),
+        Some("--bench") => targets("benches", "[bench]"),
+        _ if arguments.is_empty() => only(words(&CARGO_COMMANDS)),
+        _ => None,
+    }
+}
+
+/// The packages around `cwd` with their manifest: the nearest one, and
+/// the members of the workspace it belongs to.
+fn packages(cwd: &Path) -> Vec<(PathBuf, String)> {
+    let manifests: Vec<(PathBuf, String)> = cwd
+        .ancestors()
+        .filter_map(|directory| Some((directory.to_path_buf(), fs::read_to_string(directory.join("Cargo.toml")).ok()?)))
+        .collect();
+
+    let mut packages: Vec<(PathBuf, String)> = manifests.first().cloned().into_iter().collect();
+    if let Some((root, manifest)) = manifests.iter().find(|(_, manifest)| manifest.lines().any(|line| line.trim() == "[workspace]")) {
+        packages.push((root.clone(), manifest.clone()));
+        for member in workspace_members(manifest) {
+            let directories: Vec<PathBuf> = match member.strip_suffix("/*") {
+                Some(parent) => fs::read_dir(root.join(parent))
+                    .map(|entries| entries.flatten().map(|entry| entry.path()).collect())
+                    .unwrap_or_default(),
+                None => vec![root.join(member)],
+            };
+            for directory in directories {
+                if let Ok(manifest) = fs::read_to_string(directory.join("Cargo.toml")) {
+                    packages.push((directory, manifest));
+                }
+            }
+        }
+    }
+
+    packages.dedup_by(|a, b| a.0 == b.0);
+    packages
+}
+
+/// Values of `key` in the tables named `table` of a manifest, `[bin]` for
+/// the `[[bin]]` array for instance. Read line by line, which is enough
+/// for names.
+fn manifest_values(manifest: &str, table: &str, key: &str) -> Vec<String> {
+    let mut current = "";
+    let mut values = vec![];
+
+    for line in manifest.lines().map(str::trim) {
+        if line.starts_with('[') {
+            current = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')).unwrap_or_default();
+        } else if current == table {
+            if let Some((_, value)) = line.split_once('=').filter(|(name, _)| name.trim() == key) {
+                values.push(value.trim().trim_matches('"').to_string());
+            }
+        }
+    }
+    values
+}
+
+/// The `members` of the `[workspace]` table, possibly over several lines.
+fn workspace_members(manifest: &str) -> Vec<String> {
+    let Some(start) = manifest.find("[workspace]") else {
+        return vec![];
+    };
+    let table = &manifest[start..];
+    let Some(list) = table.find("members").and_then(|members| table[members..].split_once('[')).map(|(_, rest)| rest) else {
+        return vec![];
+    };
+    let list = list.split(']').next().unwrap_or_default();
+
+    list.lines()
+        .flat_map(|line| line.split('#').next().unwrap_or_default().split(','))
+        .map(|member| member.trim().trim_matches('"').to_string())
+        .filter(|member| !member.is_empty())
+        .collect()
+}
+
+/// Targets found by convention in a directory: `name.rs` files and
+/// `name/main.rs`.
+fn target_files(directory: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(directory) else {
+        return vec![];
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().to_string();
+            match name.strip_suffix(".rs") {
+                Some(stem) if path.is_file() => Some(stem.to_string()),
+                _ if path.join("main.rs").is_file() => Some(name),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Targets of the makefile `make` would read, from the directory given
+/// with `-C`.
+fn make(invocation: &Invocation) -> Option<Candidates> {
+    let previous = invocation.words.last().filter(|_| invocation.words.len() > 1).map(String::as_str);
+    if matches!(previous, Some("-C" | "-f" | "--file" | "--directory" | "-I" | "-o" | "-W")) {
+        return None;
+    }
+
+    let mut directory = invocation.cwd.to_path_buf();
+    let mut file = None;
+    let mut words = invocation.words.iter();
+    while let Some(word) = words.next() {
+        match word.as_str() {
+            "-C" | "--directory" => directory = directory.join(words.next()?),
+            "-f" | "--file" => file = words.next().cloned(),
+            _ => {},
+        }
+    }
+
+    let text = match file {
+        Some(file) => fs::read_to_string(directory.join(file)).ok()?,
+        None => ["GNUmakefile", "makefile", "Makefile"].iter().find_map(|name| fs::read_to_string(directory.join(name)).ok())?,
+    };
+    only(make_targets(&text))
+}
+
+/// Explicit targets of a makefile: names before a `:` at the start of a
+/// line, leaving out special targets, patterns and variables.
+fn make_targets(text: &str) -> Vec<String> {
+    let mut targets = vec![];
+    for line in text.lines() {
+        if line.starts_with(char::is_whitespace) || line.starts_with('#') {
+            continue;
+        }
+        let Some((names, rest)) = line.split_once(':') else { continue };
+        if rest.starts_with('=') || names.contains('=') {
+            continue;
+        }
+        targets.extend(
+            names
+                .split_whitespace()
+                .filter(|name| !name.starts_with('.') && !name.contains(['%', '$']))
+                .map(String::from),
+        );
+    }
+    targets.sort();
+    targets.dedup();
+    targets
+}
+
+/// Commands, then the subcommands of management commands.
+fn docker(invocation: &Invocation) -> Option<Candidates> {
+    let arguments = arguments(invocation, &["-H", "--host", "-c", "--context", "--config", "-l", "--log-level"]);
+
+    let subcommands: &[&str] = match arguments.as_slice() {
+        [] => &DOCKER_COMMANDS,
+        ["container"] => &[
+            "attach", "commit", "cp", "create", "diff", "exec", "export", "inspect", "kill", "logs", "ls", "pause",
+            "port", "prune", "rename", "restart", "rm", "run", "start", "stats", "stop", "top", "unpause", "update", "wait",
+        ],
+        ["image"] => &["build", "history", "import", "inspect", "load", "ls", "prune", "pull", "push", "rm", "save", "tag"],
+        ["network"] => &["connect", "create", "disconnect", "inspect", "ls", "prune", "rm"],
+        ["volume"] => &["create", "inspect", "ls", "prune", "rm"],
+        ["system"] => &["df", "events", "info", "prune"],
+        ["context"] => &["create", "export", "import", "inspect", "ls", "rm", "update", "use"],
+        ["compose"] => &[
+            "build", "config", "create", "down", "events", "exec", "images", "kill", "logs", "ls", "pause", "port",
+            "ps", "pull", "push", "restart", "rm", "run", "start", "stop", "top", "unpause", "up", "version",
+        ],
+        _ => return None,
+    };
+    only(words(subcommands))
+}
+
+/// Commands, then units from the unit directories of the system, or of
+/// the user with `--user`.
+fn systemctl(invocation: &Invocation) -> Option<Candidates> {
+    let arguments = arguments(invocation, &["-t", "--type", "-p", "--property", "-H", "--host", "-M", "--machine"]);
+    let Some(subcommand) = arguments.first() else {
+        return only(words(&SYSTEMCTL_COMMANDS));
+    };
+    if subcommand.starts_with("list-") || matches!(*subcommand, "daemon-reload" | "daemon-reexec" | "get-default" | "poweroff" | "reboot" | "suspend" | "hibernate") {
+        return only(vec![]);
+    }
+
+    let directories: Vec<PathBuf> = if invocation.words.iter().any(|word| word == "--user") {
+        let mut directories: Vec<PathBuf> = invocation.home.map(|home| home.join(".config/systemd/user")).into_iter().collect();
+        directories.extend(["/etc/systemd/user", "/usr/lib/systemd/user"].map(PathBuf::from));
+        directories
+    } else {
+        ["/etc/systemd/system", "/run/systemd/system", "/usr/lib/systemd/system", "/lib/systemd/system"].map(PathBuf::from).to_vec()
+    };
+
+    let mut units: Vec<String> = directories
+        .iter()
+        .filter_map(|directory| fs::read_dir(directory).ok())
+        .flat_map(|entries| entries.flatten())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|name| {
+            let kind = name.rsplit('.').next().unwrap_or_default();
+            !name.contains("@.") && ["service", "socket", "timer", "target", "mount", "path", "slice", "automount", "swap"].contains(&kind)
+        })
+        .collect();
+    units.sort();
+    units.dedup();
+    only(units)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANIFEST: &str = r#"
+[workspace]
+members = [
+    "crates/core",  # the library
+    "crates/*",
+]
+
+[package]
+name = "shell"
+version = "0.1.0"
+
+[[bin]]
+name = "sh"
+path = "src/sh.rs"
+
+[[bin]]
+name = "login"
+
+[features]
+name = "not-a-package"
+default = []
+"#;
+
+    #[test]
+    fn manifest_values_are_read_from_their_table() {
+        assert_eq!(manifest_values(MANIFEST, "package", "name"), ["shell"]);
+        assert_eq!(manifest_values(MANIFEST, "[bin]", "name"), ["sh", "login"]);
+        assert_eq!(manifest_values(MANIFEST, "[bin]", "path"), ["src/sh.rs"]);
+        assert_eq!(manifest_values(MANIFEST, "features", "name"), ["not-a-package"]);
+        assert!(manifest_values(MANIFEST, "[example]", "name").is_empty());
+    }
+
+    #[test]
+    fn workspace_members_span_lines() {
+        assert_eq!(workspace_members(MANIFEST), ["crates/core", "crates/*"]);
+        assert_eq!(workspace_members("[workspace]\nmembers = [\"a\", \"b\"]\n"), ["a", "b"]);
+        assert!(workspace_members("[workspace]\nresolver = \"2\"\n").is_empty());
+        assert!(workspace_members("[package]\nname = \"a\"\n").is_empty());
+    }
+
+    #[test]
+    fn make_targets_leave_out_patterns_special_targets_and_variables() {
+        let makefile = "\
+CC := gcc
+CFLAGS = -O2
+PREFIX ?= /usr/local
+BIN = sh
+# comment: not a target
+.PHONY: all clean install
+
+all build: $(BIN)
+
+$(BIN): main.o lib.o
+\t$(CC) -o $@ $^
+
+%.o: %.c
+\t$(CC) $(CFLAGS) -c $<
+
+install: all
+\tinstall -m 755 sh $(PREFIX)/bin
+
+clean:
+\trm -f *.o sh
+all: docs
+";
+        assert_eq!(make_targets(makefile), ["all", "build", "clean", "install"]);
+    }
+
+    #[test]
+    fn arguments_skip_options_and_their_values() {
+        let words: Vec<String> = ["git", "-C", "repo", "--no-pager", "-c", "a=b", "log", "-n", "main"].iter().map(|word| word.to_string()).collect();
+        let invocation = Invocation { words: &words, cwd: Path::new("/"), home: None };
+        assert_eq!(arguments(&invocation, &["-C", "-c"]), ["log", "main"]);
+        assert_eq!(arguments(&invocation, &[]), ["repo", "a=b", "log", "main"]);
+    }
+}